@@ -0,0 +1,110 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::Value;
+
+use crate::{cmd, WorkflowResult};
+
+/// Run the Criterion benchmarks, optionally saving or comparing against a
+/// named baseline.
+///
+/// Criterion stores baselines under `{target_dir}/criterion`.
+pub fn run(
+    target_dir: &Path,
+    save_baseline: Option<&str>,
+    compare: Option<&str>,
+) -> WorkflowResult<()> {
+    // Allow for file systems that only store modification times to the second
+    let started = SystemTime::now() - Duration::from_secs(1);
+    cmd("cargo", bench_args(save_baseline, compare))?;
+
+    if let Some(baseline) = compare {
+        print_comparison(&target_dir.join("criterion"), baseline, started)?;
+    }
+
+    Ok(())
+}
+
+/// The `cargo` arguments to run the benchmarks.
+pub fn bench_args(save_baseline: Option<&str>, compare: Option<&str>) -> Vec<String> {
+    let mut args = ["bench", "--workspace", "--benches"]
+        .map(str::to_owned)
+        .to_vec();
+
+    if save_baseline.is_some() || compare.is_some() {
+        args.push("--".to_owned());
+    }
+
+    if let Some(baseline) = save_baseline {
+        args.extend(["--save-baseline".to_owned(), baseline.to_owned()]);
+    }
+
+    if let Some(baseline) = compare {
+        args.extend(["--baseline".to_owned(), baseline.to_owned()]);
+    }
+
+    args
+}
+
+/// Print the changes for the benchmarks run since `started`. Criterion leaves
+/// the estimates for benchmarks that weren't run this time in place.
+fn print_comparison(
+    criterion_dir: &Path,
+    baseline: &str,
+    started: SystemTime,
+) -> WorkflowResult<()> {
+    let mut changes = Vec::new();
+    change_estimates(criterion_dir, criterion_dir, started, &mut changes)?;
+    changes.sort_by(|(_, x), (_, y)| y.abs().total_cmp(&x.abs()));
+
+    println!("\nChange in mean time compared to baseline \"{baseline}\":\n");
+
+    for (bench, change) in changes {
+        println!("{:>+9.2}%  {}", change * 100.0, bench.display());
+    }
+
+    Ok(())
+}
+
+/// Find all `change/estimates.json` files written by Criterion since `started`,
+/// and extract the relative change in the mean.
+fn change_estimates(
+    root: &Path,
+    dir: &Path,
+    started: SystemTime,
+    changes: &mut Vec<(PathBuf, f64)>,
+) -> WorkflowResult<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let estimates_file = dir.join("change").join("estimates.json");
+
+    let is_current = |file: &Path| {
+        fs::metadata(file)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified >= started)
+    };
+
+    if estimates_file.is_file() && is_current(&estimates_file) {
+        let estimates: Value = serde_json::from_str(&fs::read_to_string(&estimates_file)?)?;
+
+        if let Some(mean) = estimates["mean"]["point_estimate"].as_f64() {
+            let bench = dir.strip_prefix(root).unwrap_or(dir).to_path_buf();
+            changes.push((bench, mean));
+        }
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() && path.file_name().is_some_and(|name| name != "change") {
+            change_estimates(root, &path, started, changes)?;
+        }
+    }
+
+    Ok(())
+}
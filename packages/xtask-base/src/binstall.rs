@@ -0,0 +1,78 @@
+use std::fs;
+
+use cargo_metadata::{MetadataCommand, Package};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+use crate::{codegen, is_publishable, update_file, WorkflowResult};
+
+/// The archive format used for release binaries
+const PKG_FMT: &str = "tgz";
+
+/// Add `[package.metadata.binstall]` to each workspace package with a binary,
+/// so it can be installed with `cargo binstall`.
+///
+/// Release archives are expected to be attached to a GitHub release tagged
+/// `v{version}`, and named `{package}-{target}.tar.gz`, with the binaries at
+/// the root. See
+/// [`Tasks::universal_macos_binary`](crate::ci::Tasks::universal_macos_binary).
+/// Other keys in the table are left alone. In check mode, it's an error if any
+/// of the generated keys are missing or different.
+pub fn generate_binstall_metadata(check: bool) -> WorkflowResult<()> {
+    if !codegen::is_selected(None) {
+        return Ok(());
+    }
+
+    let metadata = MetadataCommand::new().no_deps().exec()?;
+
+    for package in metadata.workspace_packages() {
+        if has_binary(package) && is_publishable(package) {
+            update_manifest(package, check)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The name of the release archive for `package` on `target`
+pub(crate) fn archive_name(package: &str, target: &str) -> String {
+    format!("{package}-{target}.tar.gz")
+}
+
+fn has_binary(package: &Package) -> bool {
+    package
+        .targets
+        .iter()
+        .any(|target| target.kind.iter().any(|kind| kind == "bin"))
+}
+
+fn update_manifest(package: &Package, check: bool) -> WorkflowResult<()> {
+    let path = &package.manifest_path;
+    let mut manifest: DocumentMut = fs::read_to_string(path)?.parse()?;
+    let binstall = binstall_table(&mut manifest)
+        .ok_or_else(|| format!("`package.metadata.binstall` isn't a table in \"{path}\""))?;
+    let pkg_url = format!(
+        "{{ repo }}/releases/download/v{{ version }}/{}",
+        archive_name("{ name }", "{ target }")
+    );
+
+    binstall["pkg-url"] = value(pkg_url);
+    binstall["bin-dir"] = value("{ bin }{ binary-ext }");
+    binstall["pkg-fmt"] = value(PKG_FMT);
+
+    update_file(path, &manifest.to_string(), check)
+}
+
+fn binstall_table(manifest: &mut DocumentMut) -> Option<&mut Table> {
+    let mut table = manifest.as_table_mut();
+
+    for key in ["package", "metadata", "binstall"] {
+        let item = table.entry(key).or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        });
+        table = item.as_table_mut()?;
+    }
+
+    Some(table)
+}
@@ -0,0 +1,5 @@
+use xtask_base::CommonCmds;
+
+fn main() {
+    CommonCmds::run_from_config()
+}
@@ -0,0 +1,181 @@
+use std::fs;
+
+use chrono::{Duration, Utc};
+use toml::Table;
+use toml_edit::{value, DocumentMut, Item};
+
+use crate::{ci::WorkspaceVersions, config::CONFIG_FILE, WorkflowResult, Workspace};
+
+const DIST_SERVER: &str = "https://static.rust-lang.org/dist";
+/// How many days to look back for a nightly with all the components we need.
+const MAX_NIGHTLY_AGE_DAYS: i64 = 30;
+
+/// Update the versions in the workspace config to the latest Rust stable,
+/// nightly and tool releases, then run codegen.
+///
+/// Versions are written to the `[versions]` table in `xtask.toml` if it exists,
+/// or `[workspace.metadata.xtask.versions]` in `Cargo.toml` otherwise. Rust
+/// versions that come from `rust-toolchain.toml` are left alone, as an
+/// override would stop it being used.
+pub fn bump_toolchains(workspace: &Workspace) -> WorkflowResult<()> {
+    let versions = WorkspaceVersions::load(workspace)?;
+    let current = versions.standard_versions();
+    let mut latest = Vec::new();
+
+    if versions.is_stable_from_toolchain() {
+        println!("rustc-stable is set by rust-toolchain.toml, skipping");
+    } else {
+        latest.push((
+            "rustc-stable",
+            current.rustc_stable_version,
+            latest_stable()?,
+        ));
+    }
+
+    if versions.is_nightly_from_toolchain() {
+        println!("rustc-nightly is set by rust-toolchain.toml, skipping");
+    } else {
+        latest.push((
+            "rustc-nightly",
+            current.rustc_nightly_version,
+            latest_nightly()?,
+        ));
+    }
+
+    latest.push(("udeps", current.udeps_version, latest_crate("cargo-udeps")?));
+    latest.push(("sort", current.sort_version, latest_crate("cargo-sort")?));
+
+    let latest_tools = versions
+        .tools()
+        .map(|(name, version)| Ok((name, version, latest_crate(name)?)))
+        .collect::<WorkflowResult<Vec<_>>>()?;
+
+    let root = workspace.0.workspace_root.as_std_path();
+    let config_file = root.join(CONFIG_FILE);
+    let (config_path, table_path) = if config_file.exists() {
+        (config_file, ["versions"].as_slice())
+    } else {
+        (
+            root.join("Cargo.toml"),
+            ["workspace", "metadata", "xtask", "versions"].as_slice(),
+        )
+    };
+    let mut config: DocumentMut = fs::read_to_string(&config_path)?.parse()?;
+    bump(versions_table(&mut config, table_path)?, latest);
+
+    if !latest_tools.is_empty() {
+        bump(
+            versions_table(&mut config, &[table_path, &["tools"]].concat())?,
+            latest_tools,
+        );
+    }
+
+    fs::write(&config_path, config.to_string())?;
+    duct::cmd("cargo", ["xtask", "codegen"]).run()?;
+    Ok(())
+}
+
+fn bump(table: &mut Item, latest: Vec<(&str, &str, String)>) {
+    for (key, current, latest) in latest {
+        if current == latest {
+            println!("{key} is up to date ({current})");
+        } else {
+            println!("{key}: {current} -> {latest}");
+        }
+
+        table[key] = value(latest);
+    }
+}
+
+fn versions_table<'a>(config: &'a mut DocumentMut, path: &[&str]) -> WorkflowResult<&'a mut Item> {
+    let mut item = config.as_item_mut();
+
+    for key in path {
+        let table = item
+            .as_table_like_mut()
+            .ok_or_else(|| format!("`{key}`'s parent is not a table"))?;
+        item = table.entry(key).or_insert_with(|| {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        });
+    }
+
+    Ok(item)
+}
+
+/// The latest stable Rust version, as `major.minor`.
+fn latest_stable() -> WorkflowResult<String> {
+    let manifest = fetch_channel_manifest(&format!("{DIST_SERVER}/channel-rust-stable.toml"))?
+        .ok_or("Couldn't find the stable Rust release manifest")?;
+    let version = manifest
+        .get("pkg")
+        .and_then(|pkg| pkg.get("rust"))
+        .and_then(|rust| rust.get("version"))
+        .and_then(|version| version.as_str())
+        .ok_or("No Rust version in stable release manifest")?;
+
+    // The version is of the form "1.76.0 (07dca489a 2024-02-04)"
+    let mut parts = version.split(['.', ' ']);
+
+    match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) => Ok(format!("{major}.{minor}")),
+        _ => Err(format!("Unexpected Rust version \"{version}\"").into()),
+    }
+}
+
+/// The most recent nightly that has all the components needed by the standard
+/// workflows.
+fn latest_nightly() -> WorkflowResult<String> {
+    let today = Utc::now().date_naive();
+
+    for age in 0..MAX_NIGHTLY_AGE_DAYS {
+        let date = today - Duration::days(age);
+        let url = format!("{DIST_SERVER}/{date}/channel-rust-nightly.toml");
+
+        if let Some(manifest) = fetch_channel_manifest(&url)? {
+            if ["rustfmt-preview", "clippy-preview"]
+                .into_iter()
+                .all(|component| has_component(&manifest, component))
+            {
+                return Ok(format!("nightly-{date}"));
+            }
+        }
+    }
+
+    Err(format!("No suitable nightly found in the last {MAX_NIGHTLY_AGE_DAYS} days").into())
+}
+
+fn has_component(manifest: &Table, component: &str) -> bool {
+    manifest
+        .get("pkg")
+        .and_then(|pkg| pkg.get(component))
+        .and_then(|component| component.get("target"))
+        .and_then(|target| target.get("x86_64-unknown-linux-gnu"))
+        .and_then(|target| target.get("available"))
+        .and_then(|available| available.as_bool())
+        .unwrap_or(false)
+}
+
+/// Fetch a channel manifest, returning `None` if it doesn't exist.
+fn fetch_channel_manifest(url: &str) -> WorkflowResult<Option<Table>> {
+    match ureq::get(url).call() {
+        Ok(response) => Ok(Some(response.into_string()?.parse()?)),
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The latest version of a crate on crates.io.
+fn latest_crate(name: &str) -> WorkflowResult<String> {
+    let output = duct::cmd("cargo", ["search", "--limit", "1", name]).read()?;
+
+    // Lines are of the form `name = "version"    # description`
+    output
+        .lines()
+        .filter_map(|line| line.split_once(" = \""))
+        .find(|(crate_name, _)| *crate_name == name)
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(version, _)| version.to_owned())
+        .ok_or_else(|| format!("Crate \"{name}\" not found on crates.io").into())
+}
@@ -1,15 +1,93 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs, iter, mem,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
 use crate::{
+    bench, binstall,
+    coverage::{self, CoverageTool},
     github::actions::{
-        self, cmd, install, install_rust, pull_request, push, rust_toolchain, script, Event,
-        Platform, Run, Rust, Step, Workflow,
+        self, action, checkout, cmd, install_rust, install_with, merge_group, pull_request,
+        pull_request_target, push, rust_cache, rust_toolchain, schedule, script, shell_quote,
+        shell_script, upload_artifact, Event, Installer, Job, Platform, Run, RunOptions, Rust,
+        Step, Workflow,
     },
-    WorkflowResult,
+    progress::{self, JobProgress},
+    release_notes, required_checks, required_secrets,
+    style::{self, Color},
+    Partition, WorkflowResult, Workspace,
 };
 
+mod artifacts;
+mod extra_workspace;
+mod features;
+mod matrix;
+mod profile;
+
+pub use extra_workspace::ExtraWorkspace;
+pub use features::FeaturePowerset;
+pub use matrix::EnvMatrix;
+pub use profile::Profile;
+
+/// The targets that make up a universal macOS binary.
+const MACOS_TARGETS: [&str; 2] = ["x86_64-apple-darwin", "aarch64-apple-darwin"];
+
+/// The Debian packages needed to build a Tauri 2 app.
+const TAURI_LINUX_DEPS: [&str; 7] = [
+    "libwebkit2gtk-4.1-dev",
+    "build-essential",
+    "libxdo-dev",
+    "libssl-dev",
+    "libayatana-appindicator3-dev",
+    "librsvg2-dev",
+    "patchelf",
+];
+
+/// Preinstalled tools on hosted Ubuntu runners that Rust jobs don't need
+const UNNEEDED_RUNNER_TOOLS: &[&str] = &[
+    "/usr/share/dotnet",
+    "/usr/local/lib/android",
+    "/opt/ghc",
+    "/usr/local/.ghcup",
+    "/usr/local/share/boost",
+    "/opt/hostedtoolcache/CodeQL",
+];
+
+/// The `cargo` arguments used to build the docs.
+pub(crate) const DOC_ARGS: &[&str] = &["doc"];
+
 pub struct CI {
     name: String,
     triggers: Vec<Event>,
+    permissions: Vec<(String, String)>,
     tasks: Vec<Tasks>,
+    auto_merge: Option<MergeMethod>,
+    toolchain_auto_bump: bool,
+    profiles: Vec<Profile>,
+    release: Option<Release>,
+    /// Path filters for each package with its own workflow
+    crate_filters: Vec<(String, Vec<String>)>,
+    extra_workspaces: Vec<ExtraWorkspace>,
+    /// Jobs that only run on some platforms. See [`Self::job_platforms`].
+    job_platforms: Vec<(String, Vec<Platform>)>,
+    /// Labelled steps that only run on some platforms. See
+    /// [`Self::label_platforms`].
+    label_platforms: Vec<(String, Vec<Platform>)>,
+    required_checks: bool,
+    success_job: bool,
+    /// The toolchain for the update canary workflow, if there is one
+    update_canary: Option<String>,
+    /// The MSRV for the MSRV sweep workflow, if there is one
+    msrv_sweep: Option<String>,
+    /// The toolchain for the publish check workflow, if there is one
+    publish_check: Option<String>,
 }
 
 impl CI {
@@ -19,103 +97,208 @@ impl CI {
         Self {
             name: "tests".to_owned(),
             triggers: vec![push().into(), pull_request().into()],
+            permissions: Vec::new(),
             tasks: Vec::new(),
+            auto_merge: None,
+            toolchain_auto_bump: false,
+            profiles: Vec::new(),
+            release: None,
+            crate_filters: Vec::new(),
+            extra_workspaces: Vec::new(),
+            job_platforms: Vec::new(),
+            label_platforms: Vec::new(),
+            required_checks: false,
+            success_job: false,
+            update_canary: None,
+            msrv_sweep: None,
+            publish_check: None,
         }
     }
 
+    /// A workflow called `name`, with no triggers.
     pub fn named(name: impl Into<String>) -> Self {
         Self {
             name: name.into(),
             triggers: Vec::new(),
-            tasks: Vec::new(),
+            ..Self::new()
         }
     }
 
-    /// `extra_workspaces` is a tuple of (name, dir).
+    /// A CI workflow called "link-check", that checks the links in `README.md`
+    /// and the generated docs every Monday.
+    pub fn link_check(lychee_version: &str) -> Self {
+        Self::named("link-check").on(schedule("0 0 * * 1")).job(
+            Tasks::new(
+                "link-check",
+                Platform::UbuntuLatest,
+                rust_toolchain("stable"),
+            )
+            .link_check(lychee_version),
+        )
+    }
+
+    /// The standard tests, release tests and lints jobs, for the main
+    /// workspace and each of `extra_workspaces`.
     pub fn standard_workflow(
         versions: StandardVersions,
-        extra_workspaces: &[(&str, &str)],
+        extra_workspaces: &[ExtraWorkspace],
     ) -> Self {
-        Self::new()
+        extra_workspaces
+            .iter()
+            .cloned()
+            .fold(Self::new(), Self::extra_workspace)
             .standard_tests(versions.rustc_stable_version, extra_workspaces)
             .standard_release_tests(versions.rustc_stable_version, extra_workspaces)
+            .toolchain_auto_bump_when(versions.nightly_policy == NightlyPolicy::AutoBump)
             .standard_lints(
-                versions.rustc_nightly_version,
+                versions.rustc_nightly_toolchain(),
                 versions.udeps_version,
+                versions.sort_version,
                 extra_workspaces,
             )
     }
 
-    /// `extra_workspaces` is a tuple of (name, dir).
+    /// The standard workflow, configured by the workspace config.
+    ///
+    /// Only the enabled jobs and platforms are included, steps labelled with a
+    /// task in `skip` are removed, and the `tests` job also tests with the
+    /// configured features. See [`crate::config`].
+    pub fn from_config(workspace: &Workspace) -> WorkflowResult<Self> {
+        let config = workspace.config()?;
+        let mut ci =
+            Self::standard_workflow(WorkspaceVersions::load(workspace)?.standard_versions(), &[]);
+
+        ci.tasks.retain(|task| {
+            config.is_job_enabled(&task.name) && config.is_platform_enabled(task.platform)
+        });
+
+        if !config.features.is_empty() {
+            let features = config.features.join(",");
+
+            for task in ci.tasks.iter_mut().filter(|task| task.name == "tests") {
+                task.add_run(
+                    cmd("cargo", ["test", "--workspace", "--features", &features]).label("tests"),
+                );
+            }
+        }
+
+        for task in &config.skip {
+            ci = ci.label_platforms(task, iter::empty());
+        }
+
+        Ok(ci)
+    }
+
+    /// Record an extra workspace, so commands like `cargo xtask fmt` include
+    /// it.
+    ///
+    /// [`Self::standard_workflow`] does this for its extra workspaces, but the
+    /// other `standard_*` jobs don't.
+    pub fn extra_workspace(mut self, workspace: ExtraWorkspace) -> Self {
+        self.extra_workspaces.push(workspace);
+        self
+    }
+
+    /// The extra workspaces recorded with [`Self::extra_workspace`]
+    pub fn extra_workspaces(&self) -> &[ExtraWorkspace] {
+        &self.extra_workspaces
+    }
+
+    /// The names of the jobs, without the platform
+    pub(crate) fn job_names(&self) -> BTreeSet<&str> {
+        self.tasks.iter().map(|task| task.name.as_str()).collect()
+    }
+
     pub fn standard_lints(
         self,
         rustc_version: &str,
         udeps_version: &str,
-        extra_workspaces: &[(&str, &str)],
+        sort_version: &str,
+        extra_workspaces: &[ExtraWorkspace],
     ) -> Self {
-        self.job(
-            Tasks::new(
-                "lints",
-                Platform::UbuntuLatest,
-                rust_toolchain(rustc_version).rustfmt(),
-            )
-            .lints(
-                udeps_version,
-                &extra_workspaces
+        let extra_workspace_dirs = extra_workspaces
+            .iter()
+            .map(ExtraWorkspace::dir)
+            .collect::<Vec<_>>();
+
+        let mut rust = rust_toolchain(rustc_version).rustfmt();
+
+        if !extra_workspace_dirs.is_empty() {
+            rust = rust.cache(
+                extra_workspace_dirs
                     .iter()
-                    .copied()
-                    .map(|(_name, dir)| dir)
-                    .collect::<Vec<_>>(),
-            ),
+                    .fold(rust_cache().workspace("."), |cache, dir| {
+                        cache.workspace(dir)
+                    }),
+            );
+        }
+
+        self.job(
+            Tasks::new("lints", Platform::UbuntuLatest, rust)
+                .lints(udeps_version, &extra_workspace_dirs)
+                .docs_rs()
+                .sort(sort_version, &extra_workspace_dirs),
         )
     }
 
-    /// `extra_workspaces` is a tuple of (name, dir).
     pub fn standard_tests(
         mut self,
         rustc_version: &str,
-        extra_workspaces: &[(&str, &str)],
+        extra_workspaces: &[ExtraWorkspace],
     ) -> Self {
         for platform in Platform::latest() {
-            self.tasks.push(
+            self.add_job(
                 Tasks::new("tests", platform, rust_toolchain(rustc_version).clippy())
                     .codegen()
-                    .tests(None),
+                    .tests(None)
+                    .test_readme(),
             );
 
-            for (name, workspace_dir) in extra_workspaces {
-                self.tasks.push(
-                    Tasks::new(
-                        &format!("tests-{name}"),
-                        platform,
-                        rust_toolchain(rustc_version).clippy(),
-                    )
-                    .tests(Some(workspace_dir)),
-                );
+            for workspace in extra_workspaces {
+                let dir = workspace.dir();
+                let mut tasks = Tasks::new(
+                    format!("tests-{}", workspace.name()),
+                    platform,
+                    rust_toolchain(workspace.toolchain_or(rustc_version))
+                        .clippy()
+                        .cache(rust_cache().workspace(dir)),
+                )
+                .tests(Some(dir));
+
+                if let Some(features) = workspace.feature_list() {
+                    tasks.add_run(
+                        cmd("cargo", ["test", "--features", &features])
+                            .dir(dir)
+                            .label("tests"),
+                    );
+                }
+
+                self.add_job(tasks);
             }
         }
 
         self
     }
 
-    /// `extra_workspaces` is a tuple of (name, dir).
     pub fn standard_release_tests(
         mut self,
         rustc_version: &str,
-        extra_workspaces: &[(&str, &str)],
+        extra_workspaces: &[ExtraWorkspace],
     ) -> Self {
         for platform in Platform::latest() {
-            self.tasks.push(
+            self.add_job(
                 Tasks::new("release-tests", platform, rust_toolchain(rustc_version))
                     .release_tests(None),
             );
 
-            for (name, dir) in extra_workspaces {
-                self.tasks.push(
+            for workspace in extra_workspaces {
+                let dir = workspace.dir();
+                self.add_job(
                     Tasks::new(
-                        &format!("release-tests-{name}"),
+                        format!("release-tests-{}", workspace.name()),
                         platform,
-                        rust_toolchain(rustc_version),
+                        rust_toolchain(workspace.toolchain_or(rustc_version))
+                            .cache(rust_cache().workspace(dir)),
                     )
                     .release_tests(Some(dir)),
                 );
@@ -125,97 +308,1473 @@ impl CI {
         self
     }
 
+    /// Only run the `job` jobs on `platforms`, including the jobs for extra
+    /// workspaces, like `"{job}-{workspace name}"`.
+    ///
+    /// Other jobs keep their full set of platforms. The restriction also
+    /// applies to jobs added later.
+    pub fn job_platforms(
+        mut self,
+        job: &str,
+        platforms: impl IntoIterator<Item = Platform>,
+    ) -> Self {
+        self.job_platforms
+            .push((job.to_string(), platforms.into_iter().collect()));
+        self.restrict_platforms();
+        self
+    }
+
+    /// Only run the release tests on `platforms`, while the other jobs keep
+    /// their full set of platforms.
+    ///
+    /// ```
+    /// # use xtask_base::{
+    /// #     ci::{StandardVersions, CI},
+    /// #     github::actions::Platform,
+    /// # };
+    /// let ci = CI::standard_workflow(StandardVersions::default(), &[])
+    ///     .release_tests_platforms([Platform::UbuntuLatest])
+    ///     .coverage_platforms([Platform::UbuntuLatest]);
+    /// ```
+    pub fn release_tests_platforms(self, platforms: impl IntoIterator<Item = Platform>) -> Self {
+        self.job_platforms("release-tests", platforms)
+    }
+
+    /// Only run steps labelled `label` on `platforms`, along with the tools
+    /// installed for them. See [`Run::label`].
+    ///
+    /// Jobs that have no runs left on the other platforms are removed. The
+    /// restriction also applies to jobs added later.
+    pub fn label_platforms(
+        mut self,
+        label: &str,
+        platforms: impl IntoIterator<Item = Platform>,
+    ) -> Self {
+        self.label_platforms
+            .push((label.to_string(), platforms.into_iter().collect()));
+        self.restrict_platforms();
+        self
+    }
+
+    /// Apply the platform restrictions to all the jobs so far.
+    fn restrict_platforms(&mut self) {
+        let tasks = mem::take(&mut self.tasks);
+        self.tasks = tasks
+            .into_iter()
+            .filter_map(|task| self.restrict(task))
+            .collect();
+    }
+
+    /// Apply the platform restrictions to `task`, or return `None` if it
+    /// shouldn't run at all.
+    fn restrict(&self, mut task: Tasks) -> Option<Tasks> {
+        let is_job = |job: &str| {
+            task.name == job
+                || self
+                    .extra_workspaces
+                    .iter()
+                    .any(|workspace| task.name == format!("{job}-{}", workspace.name()))
+        };
+
+        if self
+            .job_platforms
+            .iter()
+            .any(|(job, platforms)| is_job(job) && !platforms.contains(&task.platform))
+        {
+            return None;
+        }
+
+        for (label, platforms) in &self.label_platforms {
+            if !platforms.contains(&task.platform) {
+                let had_runs = task.runs().next().is_some();
+                task.tasks.retain(|task| task.label() != Some(label));
+
+                if had_runs && task.runs().next().is_none() {
+                    return None;
+                }
+            }
+        }
+
+        Some(task)
+    }
+
+    /// Only measure coverage on `platforms`. See [`Tasks::coverage`] and
+    /// [`Self::label_platforms`].
+    pub fn coverage_platforms(self, platforms: impl IntoIterator<Item = Platform>) -> Self {
+        self.label_platforms("coverage", platforms)
+    }
+
     pub fn on(mut self, event: impl Into<Event>) -> Self {
         self.triggers.push(event.into());
         self
     }
 
-    pub fn job(mut self, tasks: Tasks) -> Self {
-        self.add_job(tasks);
-        self
+    /// Add a "careful" job that runs the tests with `cargo careful`. See
+    /// [`Tasks::careful`].
+    ///
+    /// `rustc_nightly_toolchain` should be a nightly toolchain, like
+    /// [`StandardVersions::rustc_nightly_toolchain`].
+    pub fn careful(self, rustc_nightly_toolchain: &str, careful_version: &str) -> Self {
+        self.job(
+            Tasks::new(
+                "careful",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_nightly_toolchain).rust_src(),
+            )
+            .careful(careful_version),
+        )
+    }
+
+    /// Add a "geiger" job that reports on unsafe code. See [`Tasks::geiger`].
+    pub fn geiger(self, rustc_version: &str, geiger_version: &str) -> Self {
+        self.job(
+            Tasks::new(
+                "geiger",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_version),
+            )
+            .geiger(geiger_version),
+        )
+    }
+
+    /// Add a "release-macos" job that builds universal macOS binaries for
+    /// `bins`.
+    ///
+    /// See [`Tasks::universal_macos_binary`].
+    pub fn universal_macos_release(self, rustc_version: &str, bins: &[&str]) -> Self {
+        let rust = MACOS_TARGETS
+            .into_iter()
+            .fold(rust_toolchain(rustc_version), Rust::target);
+
+        self.job(
+            Tasks::new("release-macos", Platform::MacOSLatest, rust).universal_macos_binary(bins),
+        )
+    }
+
+    /// Add a "tauri" job for each platform, that builds and bundles the Tauri
+    /// app. See [`Tasks::tauri_build`].
+    pub fn tauri_release(mut self, rustc_version: &str, tauri_cli_version: &str) -> Self {
+        for platform in Platform::latest() {
+            self.add_job(
+                Tasks::new("tauri", platform, rust_toolchain(rustc_version))
+                    .tauri_build(tauri_cli_version),
+            );
+        }
+
+        self
+    }
+
+    /// Add a "trunk" job that builds the Trunk app in `app_dir` and deploys
+    /// it.
+    ///
+    /// Deploying to GitHub Pages sets the `pages` and `id-token` permissions
+    /// it needs. See [`Tasks::trunk`].
+    pub fn trunk_app(
+        self,
+        rustc_version: &str,
+        trunk_version: &str,
+        app_dir: &str,
+        deploy: TrunkDeploy,
+    ) -> Self {
+        let ci = match deploy {
+            TrunkDeploy::Artifact => self,
+            TrunkDeploy::Pages => self.pages_permissions(),
+        };
+
+        ci.job(
+            Tasks::new(
+                "trunk",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_version).wasm(),
+            )
+            .trunk(trunk_version, app_dir, deploy),
+        )
+    }
+
+    /// Add a "docs" job that builds the docs for the whole workspace, and
+    /// deploys them to GitHub Pages, with an index page linking to each crate.
+    ///
+    /// `rustc_nightly_toolchain` should be a nightly toolchain, like
+    /// [`StandardVersions::rustc_nightly_toolchain`]. See
+    /// [`Tasks::workspace_docs`].
+    pub fn workspace_docs(self, rustc_nightly_toolchain: &str) -> Self {
+        self.pages_permissions().job(
+            Tasks::new(
+                "docs",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_nightly_toolchain),
+            )
+            .workspace_docs()
+            .deploy_pages("target/doc"),
+        )
+    }
+
+    /// Set the permissions needed to deploy to GitHub Pages. See
+    /// [`Tasks::deploy_pages`].
+    pub fn pages_permissions(self) -> Self {
+        self.permission("contents", "read")
+            .permission("pages", "write")
+            .permission("id-token", "write")
+    }
+
+    /// Add a "release" job that creates a GitHub release when a tag starting
+    /// with `v` is pushed.
+    ///
+    /// The job runs once all the other jobs have passed or been skipped, and
+    /// attaches their artifacts to the release. Its first step checks the tag,
+    /// the workspace version and the latest version in `CHANGELOG.md`
+    /// agree, with `cargo xtask release --verify`. The release notes are
+    /// rendered from `template` with `cargo xtask release-notes`, so they
+    /// can use the `changelog`, `contributors` and `artifacts` helpers. The
+    /// job only runs on GitHub Actions.
+    ///
+    /// Only the release job is given `contents: write` permission, so the
+    /// other jobs keep the workflow's permissions.
+    pub fn github_release(mut self, rustc_version: &str, template: &str) -> Self {
+        self.release = Some(Release {
+            rustc_version: rustc_version.to_string(),
+            template: template.to_string(),
+        });
+        self
+    }
+
+    /// Grant the `GITHUB_TOKEN` `access` to a scope for the whole workflow.
+    ///
+    /// If the scope already has higher access, it's kept, so granting `read`
+    /// never downgrades a `write` that's needed elsewhere. For example,
+    /// `permission("pull-requests", "write")`.
+    pub fn permission(mut self, scope: &str, access: &str) -> Self {
+        actions::add_permission(&mut self.permissions, scope, access);
+        self
+    }
+
+    pub fn job(mut self, tasks: Tasks) -> Self {
+        self.add_job(tasks);
+        self
+    }
+
+    pub fn add_job(&mut self, tasks: Tasks) {
+        if let Some(tasks) = self.restrict(tasks) {
+            self.tasks.push(tasks);
+        }
+    }
+
+    /// Generate a `dependabot-auto-merge` workflow.
+    ///
+    /// This approves Dependabot pull requests, and enables auto-merge on them,
+    /// so they're merged using `method` once all required checks pass.
+    /// Auto-merge must be enabled in the repository settings.
+    pub fn dependabot_auto_merge(mut self, method: MergeMethod) -> Self {
+        self.auto_merge = Some(method);
+        self
+    }
+
+    /// Generate a workflow for each workspace package, with the jobs from
+    /// `jobs`.
+    ///
+    /// Each workflow is named after its package, and only triggers when a file
+    /// that could affect the package changes. See
+    /// [`Workspace::package_path_filters`]. This keeps CI fast for large
+    /// workspaces, as pushes only run the jobs for the packages they touch.
+    /// Locally, all the jobs are run.
+    ///
+    /// `jobs` is given the package name and its directory, relative to the
+    /// workspace root. Packages without any jobs don't get a workflow. For
+    /// example:
+    ///
+    /// ```no_run
+    /// # use xtask_base::{ci::{CI, Tasks}, github::actions::{cmd, rust_toolchain, Platform}};
+    /// # fn per_crate(workspace: &xtask_base::Workspace) -> CI {
+    /// CI::new().per_crate(workspace, |package, _dir| {
+    ///     vec![Tasks::new(
+    ///         format!("{package}-tests"),
+    ///         Platform::UbuntuLatest,
+    ///         rust_toolchain("stable"),
+    ///     )
+    ///     .run(cmd("cargo", ["test", "--package", package]))]
+    /// })
+    /// # }
+    /// ```
+    pub fn per_crate(
+        mut self,
+        workspace: &Workspace,
+        jobs: impl Fn(&str, &str) -> Vec<Tasks>,
+    ) -> Self {
+        for (package, dir, filters) in workspace.package_dirs_and_filters() {
+            let package_jobs = jobs(&package, &dir);
+
+            if package_jobs.is_empty() {
+                continue;
+            }
+
+            for mut tasks in package_jobs {
+                tasks.package = Some(package.clone());
+                self.add_job(tasks);
+            }
+
+            self.crate_filters.push((package, filters));
+        }
+
+        self
+    }
+
+    /// Define a profile, that can be run with `cargo xtask ci --profile`.
+    ///
+    /// See [`Profile`].
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profiles.push(profile);
+        self
+    }
+
+    /// Only keep the jobs and steps in the profile called `name`.
+    pub fn select_profile(mut self, name: &str) -> WorkflowResult<Self> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.name() == name)
+            .ok_or_else(|| {
+                if self.profiles.is_empty() {
+                    format!("Unknown profile \"{name}\". No profiles are defined.")
+                } else {
+                    format!(
+                        "Unknown profile \"{name}\". Profiles are: {}",
+                        self.profiles.iter().map(Profile::name).join(", ")
+                    )
+                }
+            })?;
+
+        self.tasks = self
+            .tasks
+            .iter()
+            .filter_map(|task| task.select(profile))
+            .collect();
+        Ok(self)
+    }
+
+    /// Write the names of this workflow's status checks to
+    /// `.github/required-checks.json` when generating it.
+    ///
+    /// The file is in the format the Github branch protection API uses, so it
+    /// can be applied with `cargo xtask required-checks`. That way, the
+    /// required checks for a protected branch don't drift from the generated
+    /// jobs. Jobs with a condition, and per-crate workflows, aren't included,
+    /// as they don't run for every pull request.
+    pub fn required_checks(mut self) -> Self {
+        self.required_checks = true;
+        self
+    }
+
+    /// Add a "ci-success" job that only passes if every other job passed or
+    /// was skipped, and trigger the workflow from merge queues.
+    ///
+    /// Make this the only required check, so adding or removing jobs doesn't
+    /// mean updating branch protection. With [`Self::required_checks`], it's
+    /// the only check in the manifest.
+    pub fn success_job(mut self) -> Self {
+        self.success_job = true;
+        self.on(merge_group())
+    }
+
+    /// Generate an `update-canary` workflow, as an early warning of breaking
+    /// changes in dependencies.
+    ///
+    /// Every Monday, this runs `cargo update`, then the tests from
+    /// [`Tasks::tests`] with `rustc_version`. If they pass, it opens a pull
+    /// request with the new `Cargo.lock`. If they fail, it opens an issue.
+    /// "Allow GitHub Actions to create and approve pull requests" must be
+    /// enabled in the repository settings.
+    pub fn update_canary(mut self, rustc_version: &str) -> Self {
+        self.update_canary = Some(rustc_version.to_string());
+        self
+    }
+
+    /// Generate an `msrv-sweep` workflow, to check the code still works with
+    /// each toolchain it supports.
+    ///
+    /// Every Monday, this builds and tests the whole workspace with `msrv`, and
+    /// the current stable, beta and nightly toolchains, with a job for each.
+    /// Clippy isn't run, as lints differ between toolchains. If beta or
+    /// nightly fail, it opens an issue, so breakage is found before it
+    /// reaches stable.
+    ///
+    /// ```
+    /// # use xtask_base::{ci::CI, WorkflowResult, Workspace};
+    /// # fn ci(workspace: &Workspace) -> WorkflowResult<CI> {
+    /// let msrv = workspace
+    ///     .msrv()
+    ///     .ok_or("No `rust-version` in the workspace")?;
+    /// Ok(CI::new().msrv_sweep(&msrv))
+    /// # }
+    /// ```
+    pub fn msrv_sweep(mut self, msrv: &str) -> Self {
+        self.msrv_sweep = Some(msrv.to_string());
+        self
+    }
+
+    /// Generate a `publish-check` workflow, that checks the publishable
+    /// packages with [`Tasks::publish_check`].
+    ///
+    /// This runs for pull requests that change a `Cargo.toml`, like version
+    /// bumps, so packaging problems are found before release day.
+    pub fn publish_check(mut self, rustc_version: &str) -> Self {
+        self.publish_check = Some(rustc_version.to_string());
+        self
+    }
+
+    /// Generate a `bump-toolchains` workflow.
+    ///
+    /// Every Monday, this runs `cargo xtask bump-toolchains`, and opens a pull
+    /// request with any changes. "Allow GitHub Actions to create and approve
+    /// pull requests" must be enabled in the repository settings.
+    pub fn toolchain_auto_bump(self) -> Self {
+        self.toolchain_auto_bump_when(true)
+    }
+
+    fn toolchain_auto_bump_when(mut self, condition: bool) -> Self {
+        self.toolchain_auto_bump |= condition;
+        self
+    }
+
+    /// Write the workflows, or check they're up to date if `check` is true.
+    ///
+    /// If any steps require secrets, they're listed in
+    /// `.github/required-secrets.json`. See [`Run::requires_secret`].
+    pub fn write(self, check: bool) -> WorkflowResult<()> {
+        let mut secrets = BTreeSet::new();
+        let mut write_workflow = |workflow: Workflow| {
+            secrets.extend(workflow.required_secrets());
+            workflow.write(check)
+        };
+
+        if let Some(method) = self.auto_merge {
+            write_workflow(auto_merge_workflow(method))?;
+        }
+
+        if self.toolchain_auto_bump {
+            write_workflow(toolchain_auto_bump_workflow())?;
+        }
+
+        if let Some(rustc_version) = &self.update_canary {
+            write_workflow(update_canary_workflow(rustc_version).into_workflow())?;
+        }
+
+        if let Some(msrv) = &self.msrv_sweep {
+            write_workflow(msrv_sweep_workflow(msrv).into_workflow())?;
+        }
+
+        if let Some(rustc_version) = &self.publish_check {
+            write_workflow(publish_check_workflow(rustc_version).into_workflow())?;
+        }
+
+        let (crate_tasks, tasks): (Vec<_>, Vec<_>) = self
+            .tasks
+            .into_iter()
+            .partition(|task| task.package.is_some());
+
+        for (package, filters) in self.crate_filters {
+            let package_tasks = crate_tasks
+                .iter()
+                .filter(|task| task.package.as_ref() == Some(&package))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            // The jobs might have been removed since, for example by
+            // `label_platforms`, and a workflow needs at least one job.
+            if package_tasks.is_empty() {
+                continue;
+            }
+
+            let crate_ci = CI {
+                name: package.clone(),
+                triggers: vec![
+                    push().paths(filters.clone()).into(),
+                    pull_request().paths(filters).into(),
+                ],
+                permissions: self.permissions.clone(),
+                tasks: package_tasks,
+                profiles: self.profiles.clone(),
+                ..CI::named(&package)
+            };
+            write_workflow(crate_ci.into_workflow())?;
+        }
+
+        let required_checks = self.required_checks;
+        let success_job = self.success_job;
+        let workflow = CI {
+            tasks,
+            crate_filters: Vec::new(),
+            ..self
+        }
+        .into_workflow();
+
+        if required_checks {
+            let checks = if success_job {
+                vec![success_job_id()]
+            } else {
+                workflow.check_names()
+            };
+            required_checks::write(checks, check)?;
+        }
+
+        // All the jobs might be in per crate workflows
+        if !workflow.job_ids().is_empty() {
+            write_workflow(workflow)?;
+        }
+
+        if !secrets.is_empty() {
+            required_secrets::write(&secrets, check)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn execute(&self) -> WorkflowResult<()> {
+        for task in &self.tasks {
+            task.execute()?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute all the jobs called `name`, for the current platform.
+    pub fn execute_job(&self, name: &str) -> WorkflowResult<()> {
+        let mut tasks = self
+            .tasks
+            .iter()
+            .filter(|task| task.name == name)
+            .peekable();
+
+        if tasks.peek().is_none() {
+            return Err(format!("Unknown job \"{name}\"").into());
+        }
+
+        for task in tasks {
+            task.execute()?;
+        }
+
+        Ok(())
+    }
+
+    /// Execute all jobs for the current platform, stopping at the first
+    /// failure, and report on each step that was run.
+    ///
+    /// Command output is written to `stderr`, so `stdout` is free for the
+    /// report.
+    pub fn execute_with_report(&self) -> Vec<JobReport> {
+        self.execute_with_logs(None)
+    }
+
+    /// Like [`Self::execute_with_report`], but also write the output of each
+    /// step to its own log file in `log_dir`, if it's provided.
+    pub fn execute_with_logs(&self, log_dir: Option<&Path>) -> Vec<JobReport> {
+        let mut reports = Vec::new();
+
+        for task in &self.tasks {
+            if task.platform.is_current() {
+                let job_reports = task.execute_with_report(log_dir, true);
+                let failed = job_reports
+                    .iter()
+                    .flat_map(|report| &report.steps)
+                    .any(|step| !step.success);
+                reports.extend(job_reports);
+
+                if failed {
+                    break;
+                }
+            }
+        }
+
+        reports
+    }
+
+    fn into_workflow(self) -> Workflow {
+        let mut workflow = actions::workflow(&self.name).on(self.triggers);
+
+        for (scope, access) in &self.permissions {
+            workflow = workflow.permission(scope, access);
+        }
+
+        let profile_tasks = self
+            .profiles
+            .iter()
+            .filter(|profile| profile.has_jobs())
+            .flat_map(|profile| {
+                self.tasks.iter().filter_map(|task| {
+                    let mut selected = task.select(profile)?;
+                    selected.name = format!("{}-{}", profile.name(), task.name);
+                    Some(selected)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for task in self.tasks.into_iter().chain(profile_tasks) {
+            let steps = task
+                .tasks
+                .into_iter()
+                .map(Step::from)
+                .chain(task.finally)
+                .chain(task.ci_finally);
+
+            workflow.push_job(
+                Job::new(&task.name, task.platform, steps)
+                    .condition(task.condition)
+                    .environment(task.environment)
+                    .env_matrix(task.env_matrix.vars().to_vec()),
+            );
+        }
+
+        let job_ids = workflow.job_ids();
+
+        if self.success_job && !job_ids.is_empty() {
+            workflow.push_job(success_job(job_ids));
+        }
+
+        if let Some(release) = self.release {
+            let needs = workflow.job_ids();
+            // Job permissions replace the workflow's, so keep those too.
+            let mut permissions = self.permissions;
+            actions::add_permission(&mut permissions, "contents", "write");
+            workflow.push_job(release.job(needs).permissions(permissions));
+        }
+
+        workflow
+    }
+}
+
+const SUCCESS_JOB: &str = "ci-success";
+
+fn success_job_id() -> String {
+    format!("{SUCCESS_JOB}-{}", Platform::UbuntuLatest.as_str())
+}
+
+/// A job that fails if any of `needs` failed or was cancelled
+///
+/// It always runs, as otherwise it would be skipped when a job it needs fails,
+/// and a skipped job counts as passing.
+fn success_job(needs: Vec<String>) -> Job {
+    Job::new(
+        SUCCESS_JOB,
+        Platform::UbuntuLatest,
+        [script([
+            ["echo", "A required job failed or was cancelled"],
+            ["exit", "1"],
+        ])
+        .condition("contains(needs.*.result, 'failure') || contains(needs.*.result, 'cancelled')")],
+    )
+    .needs(needs)
+    .condition(Some("always()".to_string()))
+}
+
+struct Release {
+    rustc_version: String,
+    template: String,
+}
+
+impl Release {
+    /// The release job, which runs after `needs` on version tags.
+    ///
+    /// Some of `needs` may be conditional, and a skipped job would skip the
+    /// release too, so the release only checks that none of them failed or
+    /// were cancelled.
+    fn job(&self, needs: Vec<String>) -> Job {
+        let notes = "target/release-notes.md";
+        let rust = rust_toolchain(&self.rustc_version).checkout(checkout().fetch_depth(0));
+
+        Job::new(
+            "release",
+            Platform::UbuntuLatest,
+            [
+                install_rust(rust),
+                cmd("cargo", ["xtask", "release", "--verify"]).into(),
+                action("actions/download-artifact@v3")
+                    .with("path", release_notes::ARTIFACTS_DIR)
+                    .into(),
+                cmd(
+                    "cargo",
+                    [
+                        "xtask",
+                        "release-notes",
+                        "--template",
+                        &self.template,
+                        "--output",
+                        notes,
+                    ],
+                )
+                .into(),
+                action("softprops/action-gh-release@v2")
+                    .with("body_path", notes)
+                    .with("files", format!("{}/**/*", release_notes::ARTIFACTS_DIR))
+                    .into(),
+            ],
+        )
+        .needs(needs)
+        .condition(Some(
+            "always() && !contains(needs.*.result, 'failure') && !contains(needs.*.result, \
+             'cancelled') && startsWith(github.ref, 'refs/tags/v')"
+                .to_string(),
+        ))
+    }
+}
+
+fn auto_merge_workflow(method: MergeMethod) -> Workflow {
+    let gh_pr = |args: &[&str]| {
+        shell_script([format!("gh pr {} \"$PR_URL\"", args.join(" "))])
+            .env("PR_URL", "${{ github.event.pull_request.html_url }}")
+            .env("GITHUB_TOKEN", "${{ secrets.GITHUB_TOKEN }}")
+    };
+
+    let mut workflow = actions::workflow("dependabot-auto-merge")
+        .on([pull_request_target()])
+        .permission("contents", "write")
+        .permission("pull-requests", "write");
+
+    workflow.add_conditional_job(
+        "auto-merge",
+        Platform::UbuntuLatest,
+        "github.actor == 'dependabot[bot]'",
+        [
+            gh_pr(&["review", "--approve"]),
+            gh_pr(&["merge", "--auto", method.as_arg()]),
+        ],
+    );
+
+    workflow
+}
+
+fn toolchain_auto_bump_workflow() -> Workflow {
+    let mut workflow = actions::workflow("bump-toolchains")
+        .on([schedule("0 0 * * 1")])
+        .permission("contents", "write")
+        .permission("pull-requests", "write");
+
+    workflow.add_job(
+        "bump-toolchains",
+        Platform::UbuntuLatest,
+        [
+            install_rust(rust_toolchain("stable")),
+            cmd("cargo", ["xtask", "bump-toolchains"]).into(),
+            action("peter-evans/create-pull-request@v6")
+                .with("branch", "xtask/bump-toolchains")
+                .with("title", "Bump Rust toolchains")
+                .with("commit-message", "Bump Rust toolchains")
+                .into(),
+        ],
+    );
+
+    workflow
+}
+
+/// A step that opens an issue titled `title`, linking to the run, if an
+/// earlier step in the job failed. If there's already an open issue with that
+/// title, the link is added as a comment instead.
+fn open_issue_on_failure(title: &str) -> Step {
+    let open_issue = shell_script([
+        format!("export TITLE={}", shell_quote(title)),
+        "issue=$(gh issue list --state open --search \"$TITLE in:title\" --json number,title \
+         --jq 'map(select(.title == env.TITLE)) | .[0].number // empty')"
+            .to_string(),
+        "if [ -n \"$issue\" ]; then".to_string(),
+        "  gh issue comment \"$issue\" --body \"See $RUN_URL\"".to_string(),
+        "else".to_string(),
+        "  gh issue create --title \"$TITLE\" --body \"See $RUN_URL\"".to_string(),
+        "fi".to_string(),
+    ])
+    .env("GH_TOKEN", "${{ secrets.GITHUB_TOKEN }}")
+    .env(
+        "RUN_URL",
+        "${{ github.server_url }}/${{ github.repository }}/actions/runs/${{ github.run_id }}",
+    );
+
+    Step::from(open_issue).if_failed()
+}
+
+fn update_canary_workflow(rustc_version: &str) -> CI {
+    CI::named("update-canary")
+        .on(schedule("0 0 * * 1"))
+        .permission("contents", "write")
+        .permission("pull-requests", "write")
+        .permission("issues", "write")
+        .job(
+            Tasks::new(
+                "update-canary",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_version).clippy(),
+            )
+            .run(cmd("cargo", ["update"]).label("update"))
+            .tests(None)
+            .step(
+                action("peter-evans/create-pull-request@v6")
+                    .with("branch", "xtask/update-dependencies")
+                    .with("title", "Update dependencies")
+                    .with("commit-message", "Update dependencies"),
+            )
+            .step(open_issue_on_failure(
+                "Tests fail with updated dependencies",
+            )),
+        )
+}
+
+fn msrv_sweep_workflow(msrv: &str) -> CI {
+    let toolchains = [
+        ("msrv", msrv, false),
+        ("stable", "stable", false),
+        ("beta", "beta", true),
+        ("nightly", "nightly", true),
+    ];
+    let ci = CI::named("msrv-sweep")
+        .on(schedule("0 0 * * 1"))
+        .permission("contents", "read")
+        .permission("issues", "write");
+
+    toolchains
+        .into_iter()
+        .fold(ci, |ci, (name, toolchain, open_issue)| {
+            let tasks = Tasks::new(name, Platform::UbuntuLatest, rust_toolchain(toolchain))
+                .run(cmd("cargo", ["build", "--workspace", "--all-targets"]).label("build"))
+                .run(cmd("cargo", ["test", "--workspace"]).label("tests"))
+                .step_when(
+                    open_issue,
+                    open_issue_on_failure(&format!("Tests fail with Rust {name}")),
+                );
+
+            ci.job(tasks)
+        })
+}
+
+fn publish_check_workflow(rustc_version: &str) -> CI {
+    CI::named("publish-check")
+        .on(pull_request().paths(["**/Cargo.toml"]))
+        .job(
+            Tasks::new(
+                "publish-check",
+                Platform::UbuntuLatest,
+                rust_toolchain(rustc_version),
+            )
+            .publish_check(),
+        )
+}
+
+/// How pull requests are merged.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+impl MergeMethod {
+    fn as_arg(self) -> &'static str {
+        match self {
+            MergeMethod::Merge => "--merge",
+            MergeMethod::Squash => "--squash",
+            MergeMethod::Rebase => "--rebase",
+        }
+    }
+}
+
+/// Where to publish a Trunk app's `dist` directory.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum TrunkDeploy {
+    /// Upload it as the `dist` artifact.
+    Artifact,
+    /// Deploy it to GitHub Pages, for pushes to the default branch.
+    ///
+    /// The job runs in the `github-pages` environment.
+    /// The repository's Pages source must be set to "GitHub Actions".
+    Pages,
+}
+
+impl Default for CI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StandardVersions<'a> {
+    pub rustc_stable_version: &'a str,
+    pub rustc_nightly_version: &'a str,
+    pub udeps_version: &'a str,
+    pub sort_version: &'a str,
+    pub nightly_policy: NightlyPolicy,
+}
+
+/// How the nightly toolchain is chosen.
+///
+/// The policy applies to both the generated workflows, and running them
+/// locally, so they always use the same toolchain.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NightlyPolicy {
+    /// Use the pinned [`StandardVersions::rustc_nightly_version`].
+    #[default]
+    Pinned,
+    /// Use the latest nightly.
+    Floating,
+    /// Use the pinned nightly, and add a scheduled workflow to bump it. See
+    /// [`CI::toolchain_auto_bump`].
+    AutoBump,
+}
+
+impl<'a> StandardVersions<'a> {
+    /// The nightly toolchain to use, according to [`Self::nightly_policy`].
+    pub fn rustc_nightly_toolchain(&self) -> &'a str {
+        match self.nightly_policy {
+            NightlyPolicy::Floating => "nightly",
+            NightlyPolicy::Pinned | NightlyPolicy::AutoBump => self.rustc_nightly_version,
+        }
+    }
+}
+
+impl Default for StandardVersions<'static> {
+    fn default() -> Self {
+        Self {
+            rustc_stable_version: "1.76",
+            rustc_nightly_version: "nightly-2024-02-24",
+            udeps_version: "0.1.46",
+            sort_version: "1.0.9",
+            nightly_policy: NightlyPolicy::default(),
+        }
+    }
+}
+
+/// The Rust and tool versions derived from a workspace.
+///
+/// The `channel` in `rust-toolchain.toml` (or `rust-toolchain`) is used for
+/// the nightly version if it's a nightly toolchain, or the stable version if
+/// it's a numbered release. Otherwise, the stable version is the highest
+/// `rust-version` of any workspace package. Versions in
+/// `[workspace.metadata.xtask.versions]` take precedence over both.
+///
+/// Other tools can be pinned in `[workspace.metadata.xtask.versions.tools]`,
+/// so `cargo xtask bump-toolchains` keeps them up to date. See
+/// [`Self::tool`].
+#[derive(Clone, Debug, Default)]
+pub struct WorkspaceVersions {
+    rustc_stable_version: Option<String>,
+    rustc_nightly_version: Option<String>,
+    udeps_version: Option<String>,
+    sort_version: Option<String>,
+    nightly_policy: Option<NightlyPolicy>,
+    tools: BTreeMap<String, String>,
+    stable_from_toolchain: bool,
+    nightly_from_toolchain: bool,
+}
+
+impl WorkspaceVersions {
+    /// Read the versions from `workspace`.
+    pub fn load(workspace: &Workspace) -> WorkflowResult<Self> {
+        let mut versions = Self::default();
+        let root = workspace.0.workspace_root.as_std_path();
+
+        if let Some(channel) = toolchain_channel(root)? {
+            if channel.starts_with("nightly") {
+                versions.rustc_nightly_version = Some(channel);
+                versions.nightly_from_toolchain = true;
+            } else if channel.starts_with(|c: char| c.is_ascii_digit()) {
+                versions.rustc_stable_version = Some(channel);
+                versions.stable_from_toolchain = true;
+            }
+        }
+
+        if !versions.stable_from_toolchain {
+            if let Some(msrv) = workspace.msrv() {
+                versions.rustc_stable_version = Some(msrv);
+            }
+        }
+
+        let overrides = workspace.config()?.versions;
+        versions.stable_from_toolchain &= overrides.rustc_stable.is_none();
+        versions.nightly_from_toolchain &= overrides.rustc_nightly.is_none();
+        let fields = [
+            (overrides.rustc_stable, &mut versions.rustc_stable_version),
+            (overrides.rustc_nightly, &mut versions.rustc_nightly_version),
+            (overrides.udeps, &mut versions.udeps_version),
+            (overrides.sort, &mut versions.sort_version),
+        ];
+
+        for (version, field) in fields {
+            if version.is_some() {
+                *field = version;
+            }
+        }
+
+        versions.nightly_policy = overrides.nightly_policy;
+        versions.tools = overrides.tools;
+        Ok(versions)
     }
 
-    pub fn add_job(&mut self, tasks: Tasks) {
-        self.tasks.push(tasks);
+    /// The version of the `crate_name` tool pinned in
+    /// `[workspace.metadata.xtask.versions.tools]`, or `default`.
+    ///
+    /// ```no_run
+    /// # use xtask_base::{ci::{Tasks, WorkspaceVersions}, github::actions::{rust_toolchain, Platform}, in_workspace};
+    /// in_workspace(|workspace| {
+    ///     let versions = WorkspaceVersions::load(workspace)?;
+    ///     let tasks = Tasks::new("coverage", Platform::UbuntuLatest, rust_toolchain("stable"))
+    ///         .coverage(versions.tool("cargo-llvm-cov", "0.6.5"));
+    ///     # Ok(())
+    /// });
+    /// ```
+    pub fn tool<'a>(&'a self, crate_name: &str, default: &'a str) -> &'a str {
+        self.tools.get(crate_name).map_or(default, String::as_str)
     }
 
-    pub fn write(self, check: bool) -> WorkflowResult<()> {
-        self.into_workflow().write(check)
+    /// The pinned tools, as (crate name, version)
+    pub(crate) fn tools(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tools
+            .iter()
+            .map(|(name, version)| (name.as_str(), version.as_str()))
     }
 
-    pub fn execute(self) -> WorkflowResult<()> {
-        for task in self.tasks {
-            task.execute()?;
-        }
+    /// Whether the stable version came from `rust-toolchain.toml`
+    pub(crate) fn is_stable_from_toolchain(&self) -> bool {
+        self.stable_from_toolchain
+    }
 
-        Ok(())
+    /// Whether the nightly version came from `rust-toolchain.toml`
+    pub(crate) fn is_nightly_from_toolchain(&self) -> bool {
+        self.nightly_from_toolchain
     }
 
-    fn into_workflow(self) -> Workflow {
-        let mut workflow = actions::workflow(&self.name).on(self.triggers);
+    /// The derived versions. Anything that couldn't be derived uses the
+    /// [`Default`] version, and any field can be overridden with struct update
+    /// syntax:
+    ///
+    /// ```no_run
+    /// # use xtask_base::{ci::{StandardVersions, WorkspaceVersions}, in_workspace};
+    /// in_workspace(|workspace| {
+    ///     let versions = WorkspaceVersions::load(workspace)?;
+    ///     let versions = StandardVersions {
+    ///         udeps_version: "0.1.47",
+    ///         ..versions.standard_versions()
+    ///     };
+    ///     # Ok(())
+    /// });
+    /// ```
+    pub fn standard_versions(&self) -> StandardVersions<'_> {
+        let default = StandardVersions::default();
 
-        for task in self.tasks {
-            workflow.add_job(
-                &task.name,
-                task.platform,
-                task.tasks.into_iter().map(Step::from),
-            );
+        StandardVersions {
+            rustc_stable_version: self
+                .rustc_stable_version
+                .as_deref()
+                .unwrap_or(default.rustc_stable_version),
+            rustc_nightly_version: self
+                .rustc_nightly_version
+                .as_deref()
+                .unwrap_or(default.rustc_nightly_version),
+            udeps_version: self
+                .udeps_version
+                .as_deref()
+                .unwrap_or(default.udeps_version),
+            sort_version: self.sort_version.as_deref().unwrap_or(default.sort_version),
+            nightly_policy: self.nightly_policy.unwrap_or(default.nightly_policy),
         }
-
-        workflow
     }
 }
 
-impl Default for CI {
-    fn default() -> Self {
-        Self::new()
+/// The toolchain channel from `rust-toolchain.toml`, or the legacy
+/// `rust-toolchain` file.
+fn toolchain_channel(root: &Path) -> WorkflowResult<Option<String>> {
+    #[derive(Deserialize)]
+    struct ToolchainFile {
+        toolchain: Toolchain,
+    }
+
+    #[derive(Deserialize)]
+    struct Toolchain {
+        channel: Option<String>,
+    }
+
+    let toml_file = root.join("rust-toolchain.toml");
+    let legacy_file = root.join("rust-toolchain");
+
+    let contents = if toml_file.exists() {
+        fs::read_to_string(toml_file)?
+    } else if legacy_file.exists() {
+        fs::read_to_string(legacy_file)?
+    } else {
+        return Ok(None);
+    };
+
+    let contents = contents.trim();
+
+    // The legacy format is just the channel name
+    if !contents.contains('[') {
+        return Ok(Some(contents.to_owned()));
     }
+
+    let file: ToolchainFile =
+        toml::from_str(contents).map_err(|e| format!("Invalid rust toolchain file: {e}"))?;
+    Ok(file.toolchain.channel)
 }
 
-pub struct StandardVersions<'a> {
-    pub rustc_stable_version: &'a str,
-    pub rustc_nightly_version: &'a str,
-    pub udeps_version: &'a str,
+/// The result of executing a job locally
+#[derive(Serialize)]
+pub struct JobReport {
+    pub name: String,
+    pub platform: &'static str,
+    pub steps: Vec<StepReport>,
 }
 
-impl Default for StandardVersions<'static> {
-    fn default() -> Self {
-        Self {
-            rustc_stable_version: "1.76",
-            rustc_nightly_version: "nightly-2024-02-24",
-            udeps_version: "0.1.46",
-        }
-    }
+/// The result of executing a step locally
+#[derive(Serialize)]
+pub struct StepReport {
+    pub command: String,
+    pub success: bool,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+    /// The log file for this step, if logging was enabled
+    pub log: Option<PathBuf>,
 }
 
+#[derive(Clone)]
 pub struct Tasks {
     name: String,
     platform: Platform,
-    is_nightly: bool,
+    /// The toolchain to run commands with locally, if it's not the default
+    toolchain: Option<String>,
+    environment: Option<String>,
+    condition: Option<String>,
     tasks: Vec<Task>,
+    finally: Vec<Step>,
+    /// Steps at the end of the job that only run in CI
+    ci_finally: Vec<Step>,
+    /// System packages that are only installed in CI, and are listed when the
+    /// job runs locally
+    system_packages: Vec<String>,
+    env_matrix: EnvMatrix,
+    installer: Installer,
+    /// The stage for runs added from now on
+    stage: Option<String>,
+    /// The package whose workflow this job is in, or `None` for the main
+    /// workflow. See [`CI::per_crate`].
+    package: Option<String>,
 }
 
 impl Tasks {
+    /// A job that checks out the code and installs `rust`.
+    ///
+    /// The build is cached according to the policy set with [`Rust::cache`].
     pub fn new(name: impl Into<String>, platform: Platform, rust: Rust) -> Self {
         Self {
             name: name.into(),
             platform,
-            is_nightly: rust.is_nightly(),
+            toolchain: rust.is_nightly().then(|| rust.toolchain().to_owned()),
+            environment: None,
+            condition: None,
             tasks: Vec::new(),
+            finally: Vec::new(),
+            ci_finally: Vec::new(),
+            system_packages: Vec::new(),
+            env_matrix: EnvMatrix::new(),
+            installer: Installer::default(),
+            stage: None,
+            package: None,
         }
         .step(install_rust(rust))
     }
 
-    pub fn execute(self) -> WorkflowResult<()> {
-        if self.platform.is_current() {
-            for task in self.tasks.into_iter() {
-                if let Task::Run(cmd) = task {
-                    cmd.rustup_run(self.is_nightly)?;
-                }
+    pub fn execute(&self) -> WorkflowResult<()> {
+        if !self.platform.is_current() {
+            return Ok(());
+        }
+
+        let reports = self.execute_with_report(None, false);
+        let failed = reports
+            .iter()
+            .flat_map(|report| &report.steps)
+            .find(|step| !step.success);
+
+        match failed {
+            Some(step) => Err(step
+                .error
+                .clone()
+                .unwrap_or_else(|| format!("\"{}\" failed", step.command))
+                .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn print_system_packages(&self) {
+        if !self.system_packages.is_empty() {
+            eprintln!(
+                "{} {}",
+                style::paint("This job needs these system packages:", Color::Yellow),
+                self.system_packages.join(" ")
+            );
+        }
+    }
+
+    fn runs(&self) -> impl Iterator<Item = &Run> {
+        self.tasks.iter().filter_map(|task| match task {
+            Task::Run(cmd) => Some(cmd),
+            Task::Install(_) | Task::LabelledInstall(..) => None,
+        })
+    }
+
+    /// The number of steps run locally, including `finally` steps.
+    fn step_count(&self) -> usize {
+        self.runs().count() + self.finally_runs().count()
+    }
+
+    fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.tasks.iter().filter_map(|task| match task {
+            Task::Install(step) | Task::LabelledInstall(_, step) => Some(step),
+            Task::Run(_) => None,
+        })
+    }
+
+    fn finally_runs(&self) -> impl Iterator<Item = &Run> {
+        self.finally
+            .iter()
+            .flat_map(Step::runs)
+            .filter(|cmd| cmd.is_enabled_on(self.platform))
+    }
+
+    /// The job name, platform, and any matrix variables, for reporting.
+    fn display_name(&self, env: &[(String, String)]) -> String {
+        let platform = self.platform.as_str();
+
+        if env.is_empty() {
+            format!("{} ({platform})", self.name)
+        } else {
+            format!("{} ({platform}, {})", self.name, env_label(env))
+        }
+    }
+
+    /// Execute the job for each combination in the matrix, stopping at the
+    /// first failure.
+    /// Run each combination of the environment matrix, stopping at the first
+    /// failure. With `stdout_to_stderr`, command output is written to
+    /// `stderr`, leaving `stdout` for the report.
+    fn execute_with_report(
+        &self,
+        log_dir: Option<&Path>,
+        stdout_to_stderr: bool,
+    ) -> Vec<JobReport> {
+        let mut reports = Vec::new();
+
+        for (index, env) in self.env_matrix.combinations().into_iter().enumerate() {
+            let report = self.execute_env_with_report(&env, index, log_dir, stdout_to_stderr);
+            let failed = report.steps.iter().any(|step| !step.success);
+            reports.push(report);
+
+            if failed {
+                break;
             }
         }
 
-        Ok(())
+        reports
+    }
+
+    fn execute_env_with_report(
+        &self,
+        env: &[(String, String)],
+        matrix_index: usize,
+        log_dir: Option<&Path>,
+        stdout_to_stderr: bool,
+    ) -> JobReport {
+        let mut steps = Vec::new();
+        let display_name = self.display_name(env);
+        style::header(format_args!("Job {display_name}"));
+        self.print_system_packages();
+        let progress = JobProgress::new(&display_name, self.step_count());
+
+        let mut run = |cmd: &Run| {
+            let log =
+                log_dir.map(|dir| dir.join(self.log_file_name(matrix_index, steps.len() + 1)));
+            let options = RunOptions {
+                toolchain: self.toolchain.as_deref(),
+                stdout_to_stderr,
+                log_file: log.as_deref(),
+                env,
+                progress: Some(&progress),
+            };
+            let start = Instant::now();
+            let result = run_step(cmd, options);
+            let success = result.is_ok();
+
+            steps.push(StepReport {
+                command: cmd.command_line(),
+                success,
+                duration_secs: start.elapsed().as_secs_f64(),
+                error: result.err().map(|e| e.to_string()),
+                log,
+            });
+
+            success
+        };
+
+        let mut stages = StageTimer::default();
+        let mut succeeded = true;
+
+        for cmd in self.runs() {
+            stages.enter(cmd.stage_name(), &progress);
+
+            if !run(cmd) {
+                succeeded = false;
+                break;
+            }
+        }
+
+        stages.finish(&progress);
+
+        if succeeded {
+            self.copy_artifacts(matrix_index, self.steps(), &progress);
+        }
+
+        for cmd in self.finally_runs() {
+            run(cmd);
+        }
+
+        self.copy_artifacts(matrix_index, &self.finally, &progress);
+
+        progress.finish(steps.iter().all(|step| step.success));
+
+        let name = if env.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, env_label(env))
+        };
+
+        JobReport {
+            name,
+            platform: self.platform.as_str(),
+            steps,
+        }
+    }
+
+    fn log_file_name(&self, matrix_index: usize, step: usize) -> String {
+        format!("{}-{step:02}.log", self.file_stem(matrix_index))
+    }
+
+    /// A name for files from this job, that's unique within the workflow.
+    fn file_stem(&self, matrix_index: usize) -> String {
+        let name = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect::<String>();
+        let matrix = if self.env_matrix.is_empty() {
+            String::new()
+        } else {
+            format!("-{:02}", matrix_index + 1)
+        };
+
+        format!("{name}-{}{matrix}", self.platform.as_str())
+    }
+
+    /// Copy the artifacts that `steps` would upload, reporting any errors
+    /// without failing the job.
+    fn copy_artifacts<'a>(
+        &self,
+        matrix_index: usize,
+        steps: impl IntoIterator<Item = &'a Step>,
+        progress: &JobProgress,
+    ) {
+        if let Err(e) = artifacts::copy(&self.file_stem(matrix_index), steps, progress) {
+            progress.println(format_args!("Couldn't copy artifacts: {e}"));
+        }
+    }
+
+    /// Run the job for each combination of environment variables in `matrix`.
+    ///
+    /// See [`EnvMatrix`].
+    pub fn env_matrix(mut self, matrix: EnvMatrix) -> Self {
+        self.env_matrix = matrix;
+        self
+    }
+
+    /// Run the job in a deployment environment. See
+    /// [`Workflow::add_deployment_job`].
+    pub fn environment(mut self, name: &str) -> Self {
+        self.environment = Some(name.to_string());
+        self
+    }
+
+    /// Only run the job when the Github Actions expression `condition` is
+    /// true. If there's already a condition, both must be true.
+    ///
+    /// Conditions only apply to the generated workflow. Conditional jobs
+    /// aren't included in the required checks, as they don't always run.
+    pub fn condition(mut self, condition: &str) -> Self {
+        self.condition = Some(match self.condition {
+            Some(existing) => format!("({existing}) && ({condition})"),
+            None => condition.to_string(),
+        });
+        self
+    }
+
+    /// Only run the job for the repository's default branch, so it won't run
+    /// for pull requests or other branches.
+    pub fn only_on_default_branch(self) -> Self {
+        self.condition(
+            "github.ref == format('refs/heads/{0}', github.event.repository.default_branch)",
+        )
+    }
+
+    /// Only run the job for tags.
+    pub fn only_on_tags(self) -> Self {
+        self.condition("startsWith(github.ref, 'refs/tags/')")
+    }
+
+    /// Don't run the job in forks of the repository, or for pull requests
+    /// from forks.
+    ///
+    /// Use this for jobs that need secrets, like deploying or publishing.
+    pub fn skip_for_forks(self) -> Self {
+        self.condition(
+            "github.event.repository.fork == false && github.event.pull_request.head.repo.fork \
+             != true",
+        )
+    }
+
+    /// Add a step that runs at the end of the job, even if an earlier step
+    /// failed.
+    ///
+    /// Use this for things like uploading logs, or tearing down services.
+    /// Local execution runs any commands in the step, after the other steps
+    /// have run or one of them has failed.
+    pub fn finally(mut self, step: impl Into<Step>) -> Self {
+        self.finally.push(step.into().always());
+        self
+    }
+
+    /// Set how cargo tools are installed by this job's steps, like
+    /// [`Self::lints`]. This should be called before adding any steps.
+    pub fn installer(mut self, installer: Installer) -> Self {
+        self.installer = installer;
+        self
+    }
+
+    /// Install a cargo tool, using [`Self::installer`].
+    pub fn tool(mut self, crate_name: &str, version: &str) -> Self {
+        self.add_tool(crate_name, version);
+        self
+    }
+
+    pub fn add_tool(&mut self, crate_name: &str, version: &str) {
+        self.add_step(install_with(self.installer, crate_name, version));
+    }
+
+    /// Install a cargo tool that's only needed by runs labelled `label`.
+    fn add_labelled_tool(&mut self, label: &str, crate_name: &str, version: &str) {
+        self.tasks.push(Task::LabelledInstall(
+            label.to_string(),
+            install_with(self.installer, crate_name, version),
+        ));
+    }
+
+    /// A copy of this job with only the steps in `profile`, or `None` if
+    /// there's nothing left to run.
+    fn select(&self, profile: &Profile) -> Option<Self> {
+        let mut selected = self.clone();
+        selected.tasks.retain(|task| task.is_in_profile(profile));
+
+        let has_runs = selected.runs().next().is_some();
+        has_runs.then_some(selected)
     }
 
     pub fn step(mut self, step: impl Into<Step>) -> Self {
@@ -236,8 +1795,29 @@ impl Tasks {
         self
     }
 
+    /// Put runs added after this in the stage `name`, unless they have their
+    /// own stage.
+    ///
+    /// Stages group related runs, to make long jobs easier to follow. Each
+    /// step in the workflow is named after its stage, and local runs print a
+    /// header and the time taken for each stage.
+    pub fn stage(mut self, name: &str) -> Self {
+        self.stage = Some(name.to_string());
+        self
+    }
+
+    /// Add a run, unless it's disabled on this platform with
+    /// [`Run::only_on`] or [`Run::except`].
     pub fn add_run(&mut self, run: impl Into<Run>) {
-        self.tasks.push(Task::Run(run.into()))
+        let mut run = run.into();
+
+        if let (None, Some(stage)) = (run.stage_name(), &self.stage) {
+            run = run.stage(stage);
+        }
+
+        if run.is_enabled_on(self.platform) {
+            self.tasks.push(Task::Run(run))
+        }
     }
 
     pub fn run_when(self, condition: bool, run: impl Into<Run>) -> Self {
@@ -247,7 +1827,7 @@ impl Tasks {
     pub fn cmd(
         self,
         program: impl Into<String>,
-        args: impl IntoIterator<Item = impl AsRef<str>>,
+        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
     ) -> Self {
         self.run(cmd(program, args))
     }
@@ -255,7 +1835,7 @@ impl Tasks {
     pub fn add_cmd(
         &mut self,
         program: impl Into<String>,
-        args: impl IntoIterator<Item = impl AsRef<str>>,
+        args: impl IntoIterator<Item = impl AsRef<OsStr>>,
     ) {
         self.add_run(cmd(program, args));
     }
@@ -283,7 +1863,13 @@ impl Tasks {
     }
 
     pub fn codegen(self) -> Self {
-        self.cmd("cargo", ["xtask", "codegen", "--check"])
+        self.run(cmd("cargo", ["xtask", "codegen", "--check"]).label("codegen"))
+    }
+
+    /// Test the Rust code blocks in the markdown docs, using `cargo xtask
+    /// test-readme`.
+    pub fn test_readme(self) -> Self {
+        self.run(cmd("cargo", ["xtask", "test-readme"]).label("test-readme"))
     }
 
     pub fn tests(mut self, workspace_dir: Option<&str>) -> Self {
@@ -300,24 +1886,28 @@ impl Tasks {
                         "-D",
                         "clippy::all",
                     ],
-                ),
-                cmd("cargo", ["test"]),
-                cmd("cargo", ["build", "--all-targets"]),
-                cmd("cargo", ["doc"]),
+                )
+                .label("clippy"),
+                cmd("cargo", ["test"]).label("tests"),
+                cmd("cargo", ["build", "--all-targets"]).label("build"),
+                cmd("cargo", DOC_ARGS).label("doc"),
             ]
         };
 
         if let Some(dir) = workspace_dir {
-            tests().map(|run| self.add_run(run.dir(dir)));
+            tests()
+                .into_iter()
+                .for_each(|run| self.add_run(run.dir(dir)));
         } else {
-            tests().map(|run| self.add_run(run));
+            tests().into_iter().for_each(|run| self.add_run(run));
         }
 
         self
     }
 
     pub fn release_tests(mut self, workspace_dir: Option<&str>) -> Self {
-        let test = || cmd("cargo", ["test", "--benches", "--tests", "--release"]);
+        let test =
+            || cmd("cargo", ["test", "--benches", "--tests", "--release"]).label("release-tests");
 
         if let Some(dir) = workspace_dir {
             self.add_run(test().dir(dir));
@@ -329,8 +1919,8 @@ impl Tasks {
     }
 
     pub fn lints(mut self, udeps_version: &str, extra_workspace_dirs: &[&str]) -> Self {
-        let fmt = || cmd("cargo", ["fmt", "--all", "--", "--check"]);
-        let udeps = || cmd("cargo", ["udeps", "--all-targets"]);
+        let fmt = || cmd("cargo", ["fmt", "--all", "--", "--check"]).label("fmt");
+        let udeps = || cmd("cargo", ["udeps", "--all-targets"]).label("udeps");
 
         self.add_run(fmt());
 
@@ -338,7 +1928,7 @@ impl Tasks {
             self.add_run(fmt().dir(dir));
         }
 
-        self.add_step(install("cargo-udeps", udeps_version));
+        self.add_labelled_tool("udeps", "cargo-udeps", udeps_version);
 
         self.add_run(udeps());
 
@@ -349,6 +1939,410 @@ impl Tasks {
         self
     }
 
+    /// Check there's only one semver compatible version of each dependency,
+    /// using `cargo xtask duplicates`.
+    ///
+    /// Use this in a lints job. Crates can be allowed to have more than one
+    /// version with the `allowed-duplicates` config. See [`crate::config`].
+    pub fn duplicates(self) -> Self {
+        self.run(cmd("cargo", ["xtask", "duplicates"]).label("duplicates"))
+    }
+
+    /// Report on the unsafe code in each package and its dependencies, with
+    /// `cargo xtask geiger`.
+    ///
+    /// The reports are uploaded as the `geiger` artifact, and a summary is
+    /// added to the job summary, so unsafe usage can be tracked over time.
+    pub fn geiger(mut self, geiger_version: &str) -> Self {
+        self.add_labelled_tool("geiger", "cargo-geiger", geiger_version);
+        self.run(cmd("cargo", ["xtask", "geiger"]).label("geiger"))
+            .step(upload_artifact("geiger", "target/xtask/geiger"))
+    }
+
+    /// Run the tests with `cargo xtask nextest`, retrying each failed test up
+    /// to `retries` times.
+    ///
+    /// Tests that only pass on a retry don't fail the job, but they're listed
+    /// in the job summary and the `nextest` artifact, along with any
+    /// persistent failures. The artifact also has the duration of each test in
+    /// `timings.json`, which [`Self::nextest_partition`] uses to balance
+    /// test shards, and nextest's JUnit report in `junit.xml`.
+    pub fn nextest(mut self, nextest_version: &str, retries: u32) -> Self {
+        self.add_labelled_tool("nextest", "cargo-nextest", nextest_version);
+        let retries = retries.to_string();
+        self.run(cmd("cargo", ["xtask", "nextest", "--retries", &retries]).label("nextest"))
+            .finally(upload_artifact("nextest", "target/xtask/nextest"))
+    }
+
+    /// Like [`Self::nextest`], but only run one shard of the tests.
+    ///
+    /// The shards are balanced using the `timings.json` files in
+    /// `timings_files`, which would usually be downloaded from earlier runs.
+    /// Add a job for each shard, like `1/3`, `2/3` and `3/3`. Each shard
+    /// uploads its own artifact, like `nextest-2-of-3`.
+    pub fn nextest_partition(
+        mut self,
+        nextest_version: &str,
+        retries: u32,
+        partition: Partition,
+        timings_files: &[&str],
+    ) -> Self {
+        self.add_labelled_tool("nextest", "cargo-nextest", nextest_version);
+        let retries = retries.to_string();
+        let partition_arg = partition.to_string();
+        let mut args = vec![
+            "xtask",
+            "nextest",
+            "--retries",
+            &retries,
+            "--partition",
+            &partition_arg,
+        ];
+
+        for file in timings_files {
+            args.extend(["--timings", file]);
+        }
+
+        let artifact = format!("nextest-{}-of-{}", partition.index, partition.count);
+        self.run(cmd("cargo", args).label("nextest"))
+            .finally(upload_artifact(&artifact, "target/xtask/nextest"))
+    }
+
+    /// Fail if any `insta` snapshots don't match, using `cargo insta`.
+    ///
+    /// Pending snapshots aren't written, so run `cargo xtask snapshots
+    /// --review` locally to update them.
+    pub fn snapshots(mut self, insta_version: &str) -> Self {
+        self.add_labelled_tool("snapshots", "cargo-insta", insta_version);
+        self.run(
+            cmd(
+                "cargo",
+                [
+                    "insta",
+                    "test",
+                    "--workspace",
+                    "--check",
+                    "--unreferenced=reject",
+                ],
+            )
+            .label("snapshots"),
+        )
+    }
+
+    /// Check the docs build on docs.rs, using `cargo xtask docs-rs`.
+    ///
+    /// This needs a nightly toolchain.
+    pub fn docs_rs(self) -> Self {
+        self.run(cmd("cargo", ["xtask", "docs-rs"]).label("docs-rs"))
+    }
+
+    /// Check the publishable packages can be packaged and built, using `cargo
+    /// xtask publish-check`.
+    ///
+    /// This runs `cargo publish --dry-run`, which needs cargo 1.90 or later
+    /// when workspace packages depend on each other.
+    pub fn publish_check(self) -> Self {
+        self.run(cmd("cargo", ["xtask", "publish-check"]).label("publish-check"))
+    }
+
+    /// Run the Criterion benchmarks, saving the results as the `ci` baseline,
+    /// and upload them as the `benchmarks` artifact.
+    ///
+    /// Download the artifact into `target/criterion` and run `cargo xtask bench
+    /// --compare ci` to compare local results against it.
+    pub fn bench(self) -> Self {
+        self.run(cmd("cargo", bench::bench_args(Some("ci"), None)).label("bench"))
+            .step(upload_artifact("benchmarks", "target/criterion"))
+    }
+
+    /// Build a universal macOS binary for each of `bins`, and upload them as
+    /// the `universal-apple-darwin` artifact.
+    ///
+    /// Each binary is built for `x86_64-apple-darwin` and
+    /// `aarch64-apple-darwin`, merged with `lipo`, and archived as
+    /// `target/universal-apple-darwin/{bin}-universal-apple-darwin.tar.gz`.
+    /// When the binary has the same name as its package, this is the name
+    /// `cargo binstall` expects. See [`crate::generate_binstall_metadata`].
+    /// The runs are only added for macOS, so they'll also run locally on a
+    /// Mac.
+    pub fn universal_macos_binary(mut self, bins: &[&str]) -> Self {
+        let out_dir = "target/universal-apple-darwin";
+        let macos = |run: Run| run.only_on(Platform::MacOSLatest);
+
+        self.add_run(macos(cmd(
+            "rustup",
+            ["target", "add"].into_iter().chain(MACOS_TARGETS),
+        )));
+
+        for target in MACOS_TARGETS {
+            let bin_args = bins.iter().flat_map(|bin| ["--bin", bin]);
+            self.add_run(macos(cmd(
+                "cargo",
+                ["build", "--release", "--target", target]
+                    .into_iter()
+                    .chain(bin_args),
+            )));
+        }
+
+        self.add_run(macos(cmd("mkdir", ["-p", &format!("{out_dir}/release")])));
+
+        for bin in bins {
+            let target_bins = MACOS_TARGETS.map(|target| format!("target/{target}/release/{bin}"));
+            self.add_run(macos(cmd(
+                "lipo",
+                ["-create", "-output", &format!("{out_dir}/release/{bin}")]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .chain(target_bins),
+            )));
+            self.add_run(macos(cmd(
+                "tar",
+                [
+                    "-czf",
+                    &format!(
+                        "{out_dir}/{}",
+                        binstall::archive_name(bin, "universal-apple-darwin")
+                    ),
+                    "-C",
+                    &format!("{out_dir}/release"),
+                    bin,
+                ],
+            )));
+        }
+
+        if self.platform == Platform::MacOSLatest {
+            self.add_step(upload_artifact(
+                "universal-apple-darwin",
+                &format!("{out_dir}/*.tar.gz"),
+            ));
+        }
+
+        self
+    }
+
+    /// Free disk space on hosted Ubuntu runners, by removing preinstalled
+    /// tools that Rust jobs don't need, like .NET, the Android SDK and GHC,
+    /// along with any Docker images.
+    ///
+    /// Large workspaces that build coverage and release artifacts can
+    /// otherwise run out of disk. This is the first step of the job, whenever
+    /// it's called. It only runs in CI, and does nothing on other platforms.
+    pub fn free_disk_space(mut self) -> Self {
+        if self.platform == Platform::UbuntuLatest {
+            let remove = ["sudo", "rm", "-rf"]
+                .into_iter()
+                .chain(UNNEEDED_RUNNER_TOOLS.iter().copied());
+            let step = script([
+                remove.collect::<Vec<_>>(),
+                vec!["sudo", "docker", "image", "prune", "--all", "--force"],
+                vec!["df", "-h", "/"],
+            ])
+            .stage("free disk space");
+            self.tasks.insert(0, Task::Install(step.into()));
+        }
+
+        self
+    }
+
+    /// Prune stale artifacts from the target directory before it's cached,
+    /// using `cargo sweep`.
+    ///
+    /// The target directory is stamped after the cache is restored, before
+    /// the first run, and anything the job didn't use is removed at the end,
+    /// so the `rust-cache` entry only has artifacts that are still needed, and
+    /// restores quickly. This only runs in CI. Use `cargo xtask sweep` locally.
+    pub fn sweep_target(mut self, sweep_version: &str) -> Self {
+        let install = install_with(self.installer, "cargo-sweep", sweep_version);
+        let stamp = cmd("cargo", ["sweep", "--stamp"]).stage("stamp target directory");
+        let first_run = self
+            .tasks
+            .iter()
+            .position(|task| matches!(task, Task::Run(_)))
+            .unwrap_or(self.tasks.len());
+        self.tasks.splice(
+            first_run..first_run,
+            [Task::Install(install), Task::Install(stamp.into())],
+        );
+        self.ci_finally.push(
+            cmd("cargo", ["sweep", "--file"])
+                .stage("sweep target directory")
+                .into(),
+        );
+        self
+    }
+
+    /// Build and bundle a Tauri app with `cargo tauri build`, and upload the
+    /// bundles as the `tauri-{platform}` artifact.
+    ///
+    /// On Linux, the system libraries Tauri 2 needs are installed with
+    /// `apt-get` first. This only happens in CI. Locally, the packages are
+    /// listed, so they can be installed by hand.
+    pub fn tauri_build(mut self, tauri_cli_version: &str) -> Self {
+        let artifact = format!("tauri-{}", self.platform.as_str());
+
+        if self.platform == Platform::UbuntuLatest {
+            let install = ["sudo", "apt-get", "install", "-y"]
+                .into_iter()
+                .chain(TAURI_LINUX_DEPS);
+            let step = script([
+                vec!["sudo", "apt-get", "update"],
+                install.collect::<Vec<_>>(),
+            ])
+            .stage("install system packages");
+            self.add_step(step);
+            self.system_packages
+                .extend(TAURI_LINUX_DEPS.map(String::from));
+        }
+
+        self.tool("tauri-cli", tauri_cli_version)
+            .cmd("cargo", ["tauri", "build"])
+            .step(upload_artifact(&artifact, "target/release/bundle"))
+    }
+
+    /// Build the Trunk app in `app_dir` in release mode, and deploy its `dist`
+    /// directory.
+    ///
+    /// The toolchain should include the `wasm32-unknown-unknown` target. See
+    /// [`Rust::wasm`]. Trunk runs `wasm-opt` on release builds, with the level
+    /// set by the `data-wasm-opt` attribute on the `<link data-trunk
+    /// rel="rust">` element in `index.html`. For project Pages sites, set
+    /// `public_url` in `Trunk.toml` to `/<repository name>/`.
+    pub fn trunk(self, trunk_version: &str, app_dir: &str, deploy: TrunkDeploy) -> Self {
+        let dist = format!("{}/dist", app_dir.replace('\\', "/"));
+        let tasks = self
+            .tool("trunk", trunk_version)
+            .run(cmd("trunk", ["build", "--release"]).dir(app_dir));
+
+        match deploy {
+            TrunkDeploy::Artifact => tasks.step(upload_artifact("dist", &dist)),
+            TrunkDeploy::Pages => tasks.deploy_pages(&dist),
+        }
+    }
+
+    /// Deploy the directory at `path` to GitHub Pages, for pushes to the
+    /// default branch.
+    ///
+    /// The job runs in the `github-pages` environment, and needs the
+    /// permissions set by [`CI::pages_permissions`]. The repository's Pages
+    /// source must be set to "GitHub Actions".
+    pub fn deploy_pages(self, path: &str) -> Self {
+        let default_branch = "github.event_name == 'push' && github.ref_name == \
+                              github.event.repository.default_branch";
+
+        self.environment("github-pages")
+            .step(
+                action("actions/upload-pages-artifact@v3")
+                    .condition(default_branch)
+                    .with("path", path),
+            )
+            .step(action("actions/deploy-pages@v4").condition(default_branch))
+    }
+
+    /// Build the docs for the whole workspace, with all features, into
+    /// `target/doc`.
+    ///
+    /// Like docs.rs, `--cfg docsrs` is passed to `rustdoc`, so `doc_cfg`
+    /// annotations are shown. An `index.html` linking to each crate is
+    /// generated as well. This needs a nightly toolchain.
+    pub fn workspace_docs(self) -> Self {
+        self.run(
+            cmd(
+                "cargo",
+                ["doc", "--workspace", "--no-deps", "--all-features"],
+            )
+            .env(
+                "RUSTDOCFLAGS",
+                "--cfg docsrs -Zunstable-options --enable-index-page",
+            )
+            .label("workspace-docs"),
+        )
+    }
+
+    /// Post the markdown file at `path` as a sticky comment on the pull
+    /// request.
+    ///
+    /// See [`actions::sticky_pr_comment`].
+    pub fn pr_comment(self, header: &str, path: &str) -> Self {
+        self.step(actions::sticky_pr_comment(header, path))
+    }
+
+    /// Test each combination of features in `powerset`.
+    pub fn feature_powerset(mut self, powerset: &FeaturePowerset) -> Self {
+        for args in powerset.cargo_args() {
+            self.add_run(cmd("cargo", args).label("feature-powerset"));
+        }
+
+        self
+    }
+
+    /// Run the tests with code coverage using `cargo-llvm-cov`, and upload an
+    /// LCOV report as the `coverage` artifact. The percentage of lines covered
+    /// is added to the job summary.
+    ///
+    /// The toolchain should include the `llvm-tools-preview` component. See
+    /// [`Rust::llvm_tools`].
+    pub fn coverage(self, llvm_cov_version: &str) -> Self {
+        self.coverage_with(CoverageTool::LlvmCov, llvm_cov_version)
+    }
+
+    /// Like [`Self::coverage`], but measure coverage with `tool`, installing
+    /// `version` of it.
+    ///
+    /// The report is uploaded in the same place, whichever tool is used.
+    /// `llvm-tools-preview` is needed by all the tools except
+    /// [`CoverageTool::Tarpaulin`].
+    pub fn coverage_with(mut self, tool: CoverageTool, version: &str) -> Self {
+        self.add_labelled_tool("coverage", tool.crate_name(), version);
+        let tool = tool
+            .to_possible_value()
+            .expect("Coverage tools are never skipped");
+        self.add_run(
+            cmd(
+                "cargo",
+                ["xtask", "coverage", "--tool", tool.get_name(), "--lcov"],
+            )
+            .label("coverage"),
+        );
+
+        self.tasks.push(Task::LabelledInstall(
+            "coverage".to_string(),
+            upload_artifact("coverage", coverage::CI_OUTPUT_DIR),
+        ));
+        self
+    }
+
+    /// Run the tests with `cargo careful`, which builds the standard library
+    /// with debug assertions and extra checks for undefined behaviour.
+    ///
+    /// This is much faster than Miri, but catches fewer problems. It needs a
+    /// nightly toolchain with the `rust-src` component. See [`Rust::rust_src`].
+    pub fn careful(mut self, careful_version: &str) -> Self {
+        self.add_labelled_tool("careful", "cargo-careful", careful_version);
+        self.run(cmd("cargo", ["careful", "test", "--workspace"]).label("careful"))
+    }
+
+    /// Check the links in `README.md` and the generated docs, using `lychee`.
+    pub fn link_check(mut self, lychee_version: &str) -> Self {
+        self.add_labelled_tool("link-check", "lychee", lychee_version);
+        self.run(cmd("cargo", ["doc", "--no-deps"]).label("link-check"))
+            .run(cmd("lychee", ["--no-progress", "README.md", "target/doc"]).label("link-check"))
+    }
+
+    /// Check the dependency tables in every `Cargo.toml` are sorted, using
+    /// `cargo-sort`.
+    pub fn sort(mut self, sort_version: &str, extra_workspace_dirs: &[&str]) -> Self {
+        let sort = || cmd("cargo", ["sort", "--workspace", "--check"]).label("sort");
+
+        self.add_labelled_tool("sort", "cargo-sort", sort_version);
+        self.add_run(sort());
+
+        for dir in extra_workspace_dirs {
+            self.add_run(sort().dir(dir));
+        }
+
+        self
+    }
+
     fn when<T>(self, condition: bool, f: impl FnOnce(Self, T) -> Self, x: T) -> Self {
         if condition {
             f(self, x)
@@ -358,15 +2352,97 @@ impl Tasks {
     }
 }
 
+fn env_label(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .join(", ")
+}
+
+/// Run a step, with a pass/fail status line on `stderr`.
+fn run_step(cmd: &Run, options: RunOptions) -> WorkflowResult<()> {
+    let progress = options.progress;
+
+    if let Some(progress) = progress {
+        progress.start_step(cmd.command_line());
+    }
+
+    let start = Instant::now();
+    let result = cmd.run_with(options);
+    let elapsed = format!("({:.1}s)", start.elapsed().as_secs_f64());
+    let status = match &result {
+        Ok(()) => style::paint("passed", Color::Green),
+        Err(_) => style::paint("failed", Color::Red),
+    };
+    progress::println(progress, format_args!("{status} {elapsed}"));
+
+    if let Some(progress) = progress {
+        progress.finish_step();
+    }
+
+    result
+}
+
+/// Print a header when a run starts a new stage, and the time taken when a
+/// stage finishes.
+#[derive(Default)]
+struct StageTimer {
+    current: Option<(String, Instant)>,
+}
+
+impl StageTimer {
+    fn enter(&mut self, stage: Option<&str>, progress: &JobProgress) {
+        if self.current.as_ref().map(|(name, _)| name.as_str()) == stage {
+            return;
+        }
+
+        self.finish(progress);
+
+        if let Some(stage) = stage {
+            progress.println(style::paint(format_args!("==> Stage {stage}"), Color::Cyan));
+            self.current = Some((stage.to_string(), Instant::now()));
+        }
+    }
+
+    fn finish(&mut self, progress: &JobProgress) {
+        if let Some((name, start)) = self.current.take() {
+            progress.println(format_args!(
+                "Stage {name} finished in {:.1}s",
+                start.elapsed().as_secs_f64()
+            ));
+        }
+    }
+}
+
+#[derive(Clone)]
 enum Task {
     Install(Step),
+    /// A step that installs something only needed by runs with this label
+    LabelledInstall(String, Step),
     Run(Run),
 }
 
+impl Task {
+    fn is_in_profile(&self, profile: &Profile) -> bool {
+        match self {
+            Task::Install(_) => true,
+            Task::LabelledInstall(label, _) => profile.includes(label),
+            Task::Run(run) => run.label_str().is_some_and(|label| profile.includes(label)),
+        }
+    }
+
+    fn label(&self) -> Option<&str> {
+        match self {
+            Task::Install(_) => None,
+            Task::LabelledInstall(label, _) => Some(label),
+            Task::Run(run) => run.label_str(),
+        }
+    }
+}
+
 impl From<Task> for Step {
     fn from(value: Task) -> Self {
         match value {
-            Task::Install(step) => step,
+            Task::Install(step) | Task::LabelledInstall(_, step) => step,
             Task::Run(cmd) => cmd.into(),
         }
     }
@@ -0,0 +1,95 @@
+//! Local copies of the artifacts a job uploads
+//!
+//! When a job is run locally, any paths it would upload with
+//! `actions/upload-artifact` are copied to `artifacts/{job}/{artifact}` in
+//! the [`Workspace::artifact_dir`], so the files are available without
+//! pushing.
+use std::{fs, path::Path};
+
+use cargo_metadata::MetadataCommand;
+
+use crate::{github::actions::Step, progress::JobProgress, WorkflowResult, Workspace};
+
+/// Copy the artifacts uploaded by `steps` to the directory for `job`.
+///
+/// Like `actions/upload-artifact`, directories are copied with their contents
+/// at the top level of the artifact, and paths can be globs. Exclusions and
+/// paths containing Github Actions expressions are skipped.
+pub(crate) fn copy<'a>(
+    job: &str,
+    steps: impl IntoIterator<Item = &'a Step>,
+    progress: &JobProgress,
+) -> WorkflowResult<()> {
+    let mut artifacts = steps
+        .into_iter()
+        .flat_map(Step::uploaded_artifacts)
+        .peekable();
+
+    if artifacts.peek().is_none() {
+        return Ok(());
+    }
+
+    // Jobs run in the workspace root, but we don't have the [`Workspace`]
+    // here, so look up the target directory.
+    let workspace = Workspace(MetadataCommand::new().no_deps().exec()?);
+    let artifacts_dir = workspace.artifact_dir("artifacts")?;
+
+    for (name, paths) in artifacts {
+        let dest = artifacts_dir.join(job).join(name);
+
+        if dest.exists() {
+            fs::remove_dir_all(&dest)?;
+        }
+
+        let patterns = paths
+            .lines()
+            .map(str::trim)
+            .filter(|path| !path.is_empty() && !path.starts_with('!') && !path.contains("${{"));
+
+        for pattern in patterns {
+            let matches = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+
+            if matches.is_empty() {
+                progress.println(format_args!(
+                    "No files found for artifact \"{name}\" at `{pattern}`"
+                ));
+            }
+
+            for path in matches {
+                if path.is_dir() {
+                    copy_dir(&path, &dest)?;
+                } else if let Some(file_name) = path.file_name() {
+                    fs::create_dir_all(&dest)?;
+                    fs::copy(&path, dest.join(file_name))?;
+                }
+            }
+        }
+
+        if dest.exists() {
+            progress.println(format_args!(
+                "Artifact \"{name}\" copied to `{}`",
+                dest.display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the contents of `src` into `dest`, recursively.
+fn copy_dir(src: &Path, dest: &Path) -> WorkflowResult<()> {
+    fs::create_dir_all(dest)?;
+
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        let target = dest.join(path.file_name().unwrap_or_default());
+
+        if path.is_dir() {
+            copy_dir(&path, &target)?;
+        } else {
+            fs::copy(&path, &target)?;
+        }
+    }
+
+    Ok(())
+}
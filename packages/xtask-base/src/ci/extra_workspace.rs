@@ -0,0 +1,66 @@
+/// A separate cargo workspace in a subdirectory, checked alongside the main
+/// one
+///
+/// For example, a workspace of examples with their own dependencies, or one
+/// with a different toolchain. The standard CI jobs test each extra
+/// workspace, and commands like `cargo xtask fmt` include them.
+///
+/// ```
+/// # use xtask_base::ci::ExtraWorkspace;
+/// let examples = ExtraWorkspace::new("examples", "examples")
+///     .toolchain("nightly-2024-02-24")
+///     .features(["serde"]);
+///
+/// assert_eq!(examples.dir(), "examples");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ExtraWorkspace {
+    name: String,
+    dir: String,
+    toolchain: Option<String>,
+    features: Vec<String>,
+}
+
+impl ExtraWorkspace {
+    /// The workspace in `dir`, relative to the main workspace root
+    ///
+    /// `name` is used in job names, like `tests-{name}`.
+    pub fn new(name: &str, dir: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            dir: dir.to_string(),
+            toolchain: None,
+            features: Vec::new(),
+        }
+    }
+
+    /// Test with `toolchain`, instead of the main workspace's stable toolchain.
+    pub fn toolchain(mut self, toolchain: &str) -> Self {
+        self.toolchain = Some(toolchain.to_string());
+        self
+    }
+
+    /// Also test with `features` enabled.
+    pub fn features(mut self, features: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.features.extend(features.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    /// The toolchain to test with, defaulting to `rustc_version`.
+    pub(crate) fn toolchain_or<'a>(&'a self, rustc_version: &'a str) -> &'a str {
+        self.toolchain.as_deref().unwrap_or(rustc_version)
+    }
+
+    /// The features to test with, as a comma separated list, if there are any.
+    pub(crate) fn feature_list(&self) -> Option<String> {
+        (!self.features.is_empty()).then(|| self.features.join(","))
+    }
+}
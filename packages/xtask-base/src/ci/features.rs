@@ -0,0 +1,236 @@
+use itertools::Itertools;
+
+/// Combinations of features to test with.
+///
+/// The full powerset of features grows exponentially, so this can be limited
+/// by:
+///
+/// - [`Self::max_depth`]: the maximum number of features in a combination.
+/// - [`Self::always_include`]: features that are in every combination.
+/// - [`Self::mutually_exclusive`]: groups of features that can't be combined.
+/// - [`Self::sample`]: a random, but reproducible, subset of combinations.
+///
+/// ```
+/// # use xtask_base::ci::FeaturePowerset;
+/// let powerset = FeaturePowerset::new(["std", "serde", "async-std", "tokio"])
+///     .always_include(["std"])
+///     .mutually_exclusive(["async-std", "tokio"])
+///     .max_depth(2);
+///
+/// assert_eq!(powerset.combinations().len(), 6);
+/// ```
+#[derive(Clone, Debug)]
+pub struct FeaturePowerset {
+    package: Option<String>,
+    features: Vec<String>,
+    always: Vec<String>,
+    exclusive_groups: Vec<Vec<String>>,
+    max_depth: Option<usize>,
+    sample: Option<(usize, u64)>,
+}
+
+impl FeaturePowerset {
+    pub fn new(features: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            package: None,
+            features: features.into_iter().map(Into::into).collect(),
+            always: Vec::new(),
+            exclusive_groups: Vec::new(),
+            max_depth: None,
+            sample: None,
+        }
+    }
+
+    /// Only test `package`, rather than the package in the current directory.
+    pub fn package(mut self, package: impl Into<String>) -> Self {
+        self.package = Some(package.into());
+        self
+    }
+
+    /// Don't combine more than `depth` features, excluding those that are
+    /// always included.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Include `features` in every combination.
+    pub fn always_include(mut self, features: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.always.extend(features.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only allow one of `features` in any combination.
+    pub fn mutually_exclusive(
+        mut self,
+        features: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.exclusive_groups
+            .push(features.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Only use `count` of the combinations, chosen pseudo-randomly using
+    /// `seed`.
+    ///
+    /// The same seed always chooses the same combinations, so generated
+    /// workflows are stable.
+    pub fn sample(mut self, count: usize, seed: u64) -> Self {
+        self.sample = Some((count, seed));
+        self
+    }
+
+    /// All the feature combinations to test with.
+    pub fn combinations(&self) -> Vec<Vec<String>> {
+        let optional = self
+            .features
+            .iter()
+            .filter(|feature| !self.always.contains(feature))
+            .collect::<Vec<_>>();
+        let max_depth = self.max_depth.unwrap_or(optional.len()).min(optional.len());
+
+        let mut combinations = (0..=max_depth)
+            .flat_map(|depth| optional.iter().copied().combinations(depth))
+            .filter(|combination| self.is_allowed(combination))
+            .map(|combination| {
+                self.always
+                    .iter()
+                    .chain(combination)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((count, seed)) = self.sample {
+            combinations = sample(combinations, count, seed);
+        }
+
+        combinations
+    }
+
+    /// The `cargo` arguments to test with each combination.
+    pub(crate) fn cargo_args(&self) -> Vec<Vec<String>> {
+        self.combinations()
+            .into_iter()
+            .map(|combination| {
+                let mut args = vec!["test".to_owned()];
+
+                if let Some(package) = &self.package {
+                    args.extend(["--package".to_owned(), package.clone()]);
+                }
+
+                args.push("--no-default-features".to_owned());
+
+                if !combination.is_empty() {
+                    args.extend(["--features".to_owned(), combination.join(",")]);
+                }
+
+                args
+            })
+            .collect()
+    }
+
+    fn is_allowed(&self, combination: &[&String]) -> bool {
+        self.exclusive_groups.iter().all(|group| {
+            combination
+                .iter()
+                .filter(|feature| group.contains(feature))
+                .count()
+                <= 1
+        })
+    }
+}
+
+/// Choose `count` items with a seeded shuffle, keeping them in their original
+/// order.
+fn sample<T>(items: Vec<T>, count: usize, seed: u64) -> Vec<T> {
+    if count >= items.len() {
+        return items;
+    }
+
+    let mut indices = (0..items.len()).collect::<Vec<_>>();
+    // xorshift64 doesn't work with a zero state
+    let mut state = seed | 1;
+
+    for i in (1..indices.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let j = usize::try_from(state % (i as u64 + 1)).expect("Index should fit in usize");
+        indices.swap(i, j);
+    }
+
+    let mut chosen = indices[..count].to_vec();
+    chosen.sort_unstable();
+
+    let mut items = items.into_iter().map(Some).collect::<Vec<_>>();
+    chosen
+        .into_iter()
+        .filter_map(|index| items[index].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeaturePowerset;
+
+    fn combinations(powerset: &FeaturePowerset) -> Vec<String> {
+        powerset
+            .combinations()
+            .into_iter()
+            .map(|combination| combination.join(","))
+            .collect()
+    }
+
+    #[test]
+    fn ordered_by_size() {
+        let powerset = FeaturePowerset::new(["a", "b", "c"]);
+
+        assert_eq!(
+            combinations(&powerset),
+            ["", "a", "b", "c", "a,b", "a,c", "b,c", "a,b,c"]
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        let powerset = FeaturePowerset::new(["a", "b", "c"])
+            .always_include(["a"])
+            .max_depth(1);
+
+        assert_eq!(combinations(&powerset), ["a", "a,b", "a,c"]);
+        assert_eq!(
+            FeaturePowerset::new(["a"])
+                .max_depth(5)
+                .combinations()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn mutually_exclusive() {
+        let powerset = FeaturePowerset::new(["a", "b", "c"]).mutually_exclusive(["a", "b"]);
+
+        assert_eq!(combinations(&powerset), ["", "a", "b", "c", "a,c", "b,c"]);
+    }
+
+    #[test]
+    fn sample_size_and_order() {
+        let powerset = FeaturePowerset::new(["a", "b", "c", "d"]);
+        let all = combinations(&powerset);
+        let sampled = combinations(&powerset.clone().sample(5, 42));
+
+        assert_eq!(sampled.len(), 5);
+        assert_eq!(sampled, combinations(&powerset.clone().sample(5, 42)));
+
+        // Sampled combinations stay in their original order
+        let positions = sampled
+            .iter()
+            .map(|combination| all.iter().position(|c| c == combination).unwrap())
+            .collect::<Vec<_>>();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert_eq!(combinations(&powerset.sample(100, 42)), all);
+    }
+}
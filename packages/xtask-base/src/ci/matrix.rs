@@ -0,0 +1,71 @@
+use itertools::Itertools;
+
+/// A matrix of environment variables to run a job with.
+///
+/// Use this for dimensions that aren't part of the Rust toolchain, like the
+/// version of a database the tests run against. The generated workflow uses
+/// a job matrix, with each variable set in the job's environment. Local
+/// execution runs the job once for each combination, on the current platform.
+///
+/// Commands can also use the values with `${{ matrix.NAME }}`, in arguments,
+/// working directories and environment variables. Local execution substitutes
+/// them, as Github Actions would.
+///
+/// ```
+/// # use xtask_base::ci::EnvMatrix;
+/// let matrix = EnvMatrix::new()
+///     .var("POSTGRES_VERSION", ["15", "16"])
+///     .var("TLS", ["on", "off"]);
+///
+/// assert_eq!(matrix.combinations().len(), 4);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct EnvMatrix {
+    vars: Vec<(String, Vec<String>)>,
+}
+
+impl EnvMatrix {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dimension to the matrix, with the variable `name` taking each of
+    /// `values`.
+    pub fn var(
+        mut self,
+        name: impl Into<String>,
+        values: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.vars
+            .push((name.into(), values.into_iter().map(Into::into).collect()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// Each combination of values, as a list of `(name, value)` pairs.
+    ///
+    /// An empty matrix has a single, empty, combination.
+    pub fn combinations(&self) -> Vec<Vec<(String, String)>> {
+        if self.vars.is_empty() {
+            return vec![Vec::new()];
+        }
+
+        self.vars
+            .iter()
+            .map(|(name, values)| {
+                values
+                    .iter()
+                    .map(|value| (name.clone(), value.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .multi_cartesian_product()
+            .collect()
+    }
+
+    pub(crate) fn vars(&self) -> &[(String, Vec<String>)] {
+        &self.vars
+    }
+}
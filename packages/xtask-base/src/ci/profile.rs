@@ -0,0 +1,59 @@
+use std::collections::BTreeSet;
+
+/// A named subset of CI steps, selected by label.
+///
+/// Run a profile locally with `cargo xtask ci --profile <name>`. Each job
+/// only keeps the steps with a selected label, and jobs with nothing left to
+/// run are dropped. Unlabelled setup steps, like checking out the code and
+/// installing Rust, are always kept.
+///
+/// The standard jobs label their steps `codegen`, `fmt`, `clippy`, `tests`,
+/// `build`, `doc`, `test-readme`, `release-tests`, `udeps`, `docs-rs` and
+/// `sort`. Other
+/// presets, like [`Tasks::coverage`](crate::ci::Tasks::coverage), use their
+/// name in kebab case. Label your own steps with
+/// [`Run::label`](crate::github::actions::Run::label).
+///
+/// ```
+/// # use xtask_base::ci::{Profile, StandardVersions, CI};
+/// let ci = CI::standard_workflow(StandardVersions::default(), &[])
+///     .profile(Profile::new("quick", ["fmt", "clippy", "tests"]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Profile {
+    name: String,
+    labels: BTreeSet<String>,
+    generate_jobs: bool,
+}
+
+impl Profile {
+    pub fn new(
+        name: impl Into<String>,
+        labels: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            labels: labels.into_iter().map(Into::into).collect(),
+            generate_jobs: false,
+        }
+    }
+
+    /// Also add the profile's jobs to the generated workflow, as
+    /// `{profile}-{job}`.
+    pub fn generate_jobs(mut self) -> Self {
+        self.generate_jobs = true;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn includes(&self, label: &str) -> bool {
+        self.labels.contains(label)
+    }
+
+    pub(crate) fn has_jobs(&self) -> bool {
+        self.generate_jobs
+    }
+}
@@ -0,0 +1,152 @@
+use std::{env, fs, io, path::Path};
+
+use cargo_metadata::MetadataCommand;
+
+use crate::{cmd, cmd_in_dir, install_on_demand, WorkflowResult, Workspace};
+
+/// Remove the target directories for the workspace and any extra workspaces,
+/// optionally pruning the cargo cache, and report how much space was
+/// reclaimed.
+pub fn clean_all<'a>(
+    workspace: &Workspace,
+    extra_workspace_dirs: impl IntoIterator<Item = &'a Path>,
+    prune_cache: bool,
+) -> WorkflowResult<()> {
+    let mut reclaimed = remove_dir(workspace.target_dir())?;
+
+    for dir in extra_workspace_dirs {
+        let metadata = MetadataCommand::new().current_dir(dir).no_deps().exec()?;
+        reclaimed += remove_dir(metadata.target_directory.as_std_path())?;
+    }
+
+    if prune_cache {
+        install_on_demand("cargo-cache", &["cargo", "cache"])?;
+        cmd("cargo", ["cache", "--autoclean"])?;
+    }
+
+    println!("Reclaimed {}", human_readable(reclaimed));
+
+    Ok(())
+}
+
+/// Remove artifacts that haven't been used for `days` from the target
+/// directories for the workspace and any extra workspaces, and report how much
+/// space was reclaimed.
+pub fn sweep<'a>(
+    workspace: &Workspace,
+    extra_workspace_dirs: impl IntoIterator<Item = &'a Path>,
+    days: u32,
+) -> WorkflowResult<()> {
+    install_on_demand("cargo-sweep", &["cargo", "sweep"])?;
+    let days = days.to_string();
+    let mut reclaimed = 0;
+    let root = workspace.0.workspace_root.as_std_path();
+    let dirs = [(root, workspace.target_dir().to_path_buf())]
+        .into_iter()
+        .map(Ok)
+        .chain(extra_workspace_dirs.into_iter().map(|dir| {
+            let metadata = MetadataCommand::new().current_dir(dir).no_deps().exec()?;
+            WorkflowResult::Ok((dir, metadata.target_directory.into_std_path_buf()))
+        }));
+
+    for dir in dirs {
+        let (dir, target_dir) = dir?;
+
+        if !target_dir.exists() {
+            continue;
+        }
+
+        let before = dir_size(&target_dir)?;
+        cmd_in_dir(Some(dir), "cargo", ["sweep", "--time", &days])?;
+        reclaimed += before.saturating_sub(dir_size(&target_dir)?);
+    }
+
+    println!("Reclaimed {}", human_readable(reclaimed));
+
+    Ok(())
+}
+
+/// Remove `dir`, returning the number of bytes freed.
+///
+/// Windows can't remove a running executable, so if this `xtask` is running
+/// from `dir`, it's kept, along with the directories it's in.
+fn remove_dir(dir: &Path) -> WorkflowResult<u64> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let size = dir_size(dir)?;
+    let dir = dir.canonicalize()?;
+    let running_exe = env::current_exe()
+        .and_then(|exe| exe.canonicalize())
+        .ok()
+        .filter(|exe| cfg!(windows) && exe.starts_with(&dir));
+
+    let freed = match running_exe {
+        Some(exe) => {
+            remove_all_except(&dir, &exe)?;
+            println!("Kept the running `{}`", exe.display());
+            size - dir_size(&dir)?
+        }
+        None => {
+            fs::remove_dir_all(&dir)?;
+            size
+        }
+    };
+
+    println!("Removed `{}` ({})", dir.display(), human_readable(freed));
+
+    Ok(freed)
+}
+
+/// Remove everything in `dir`, apart from `keep` and its parent directories.
+fn remove_all_except(dir: &Path, keep: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path == keep {
+            continue;
+        }
+
+        if keep.starts_with(&path) {
+            remove_all_except(&path, keep)?;
+        } else if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
+}
+
+fn human_readable(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
@@ -0,0 +1,118 @@
+//! `clippy.toml` generation
+use cargo_metadata::MetadataCommand;
+use toml::{Table, Value};
+
+use crate::{codegen, update_file, WorkflowResult, Workspace};
+
+/// Generate `clippy.toml`
+///
+/// The `msrv` defaults to [`Workspace::msrv`], which is also the stable Rust
+/// version the CI uses unless it's overridden, so lints won't suggest
+/// anything newer than the CI tests with.
+///
+/// ```no_run
+/// # use xtask_base::{ClippyConfig, WorkflowResult};
+/// # fn codegen(check: bool) -> WorkflowResult<()> {
+/// ClippyConfig::default()
+///     .disallow_method("std::env::set_var", "It isn't thread safe")
+///     .disallow_type(
+///         "std::collections::HashMap",
+///         "Use `BTreeMap` for a stable order",
+///     )
+///     .cognitive_complexity_threshold(30)
+///     .generate(check)
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ClippyConfig {
+    msrv: Option<String>,
+    disallowed_methods: Vec<(String, String)>,
+    disallowed_types: Vec<(String, String)>,
+    cognitive_complexity_threshold: Option<u32>,
+}
+
+impl ClippyConfig {
+    /// Set the MSRV, instead of using the workspace `rust-version`.
+    pub fn msrv(mut self, version: impl Into<String>) -> Self {
+        self.msrv = Some(version.into());
+        self
+    }
+
+    /// Disallow the method or function at `path`, with the `reason` shown in
+    /// the lint.
+    pub fn disallow_method(mut self, path: impl Into<String>, reason: impl Into<String>) -> Self {
+        self.disallowed_methods.push((path.into(), reason.into()));
+        self
+    }
+
+    /// Disallow the type at `path`, with the `reason` shown in the lint.
+    pub fn disallow_type(mut self, path: impl Into<String>, reason: impl Into<String>) -> Self {
+        self.disallowed_types.push((path.into(), reason.into()));
+        self
+    }
+
+    /// Set the threshold for the `cognitive_complexity` lint.
+    pub fn cognitive_complexity_threshold(mut self, threshold: u32) -> Self {
+        self.cognitive_complexity_threshold = Some(threshold);
+        self
+    }
+
+    /// Generate `clippy.toml` in the workspace root, or check it's up to date
+    /// if `check` is set.
+    pub fn generate(&self, check: bool) -> WorkflowResult<()> {
+        if !codegen::is_selected(None) {
+            return Ok(());
+        }
+
+        let msrv = match &self.msrv {
+            Some(msrv) => Some(msrv.clone()),
+            None => Workspace(MetadataCommand::new().exec()?).msrv(),
+        };
+        let mut config = Table::new();
+
+        if let Some(msrv) = msrv {
+            config.insert("msrv".to_string(), msrv.into());
+        }
+
+        if let Some(threshold) = self.cognitive_complexity_threshold {
+            config.insert(
+                "cognitive-complexity-threshold".to_string(),
+                i64::from(threshold).into(),
+            );
+        }
+
+        let lists = [
+            ("disallowed-methods", &self.disallowed_methods),
+            ("disallowed-types", &self.disallowed_types),
+        ];
+
+        for (key, disallowed) in lists {
+            if !disallowed.is_empty() {
+                config.insert(key.to_string(), disallowed_list(disallowed));
+            }
+        }
+
+        update_file("clippy.toml", &toml::to_string(&config)?, check)
+    }
+}
+
+/// Generate `clippy.toml` in the workspace root, with the `msrv` from the
+/// workspace.
+///
+/// Use [`ClippyConfig`] to customize it.
+pub fn generate_clippy_config(check: bool) -> WorkflowResult<()> {
+    ClippyConfig::default().generate(check)
+}
+
+fn disallowed_list(disallowed: &[(String, String)]) -> Value {
+    disallowed
+        .iter()
+        .map(|(path, reason)| {
+            let mut entry = Table::new();
+            entry.insert("path".to_string(), path.clone().into());
+            entry.insert("reason".to_string(), reason.clone().into());
+            Value::Table(entry)
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
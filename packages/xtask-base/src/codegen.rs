@@ -0,0 +1,164 @@
+//! Select which generated files `cargo xtask codegen` updates, and check
+//! they're generated deterministically
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    style::{self, Color},
+    template, WorkflowResult, Workspace,
+};
+
+/// Set for each pass of `--verify-deterministic`, to the file that generated
+/// contents are captured in.
+const CAPTURE_ENV_VAR: &str = "XTASK_CODEGEN_CAPTURE";
+
+static ONLY: Mutex<Option<CodegenTarget>> = Mutex::new(None);
+static CAPTURED: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+/// A group of generated files, for `cargo xtask codegen --only`
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CodegenTarget {
+    /// Github workflows, and the manifests generated with them
+    Workflows,
+    /// Licenses, `NOTICE`, `THIRD_PARTY_LICENSES.md` and SPDX headers
+    Licenses,
+    /// `README.md`, from `README.tmpl.md`
+    Readme,
+}
+
+impl CodegenTarget {
+    /// Is this group of files being generated?
+    ///
+    /// Everything is generated, unless `--only` selects another group. Custom
+    /// codegen can use this to skip slow generators.
+    pub fn is_selected(self) -> bool {
+        is_selected(Some(self))
+    }
+}
+
+/// Is `target` being generated? Files that aren't in any target, like
+/// `rustfmt.toml`, use `None` and are only generated without `--only`.
+pub(crate) fn is_selected(target: Option<CodegenTarget>) -> bool {
+    match *ONLY.lock().expect("Codegen target lock poisoned") {
+        Some(only) => target == Some(only),
+        None => true,
+    }
+}
+
+/// Run `codegen` with only the files for `only` selected, or everything if
+/// it's `None`.
+pub(crate) fn with_only<T>(only: Option<CodegenTarget>, codegen: impl FnOnce() -> T) -> T {
+    *ONLY.lock().expect("Codegen target lock poisoned") = only;
+    let result = codegen();
+    *ONLY.lock().expect("Codegen target lock poisoned") = None;
+    result
+}
+
+/// Record `contents` instead of writing `path`, if this is a pass of
+/// `--verify-deterministic`.
+pub(crate) fn capture(path: &Path, contents: &str) -> bool {
+    if env::var_os(CAPTURE_ENV_VAR).is_none() {
+        return false;
+    }
+
+    CAPTURED
+        .lock()
+        .expect("Captured files lock poisoned")
+        .insert(path.to_path_buf(), contents.to_string());
+    true
+}
+
+/// Save the captured contents, if this is a pass of `--verify-deterministic`.
+pub(crate) fn save_captured() -> WorkflowResult<()> {
+    if let Some(file) = env::var_os(CAPTURE_ENV_VAR) {
+        let captured = CAPTURED.lock().expect("Captured files lock poisoned");
+        fs::write(file, serde_json::to_string(&*captured)?)?;
+    }
+
+    Ok(())
+}
+
+/// Run codegen twice, and fail if any generated file differs between the runs.
+///
+/// Each pass reruns the current command in a new process, with `--check`
+/// instead of `--verify-deterministic`, capturing the generated files without
+/// writing them. The second pass starts at least
+/// a second later, with the environment variables in reverse order, and a
+/// different time zone and locale. The template cache is disabled for both.
+pub(crate) fn verify_deterministic(workspace: &Workspace) -> WorkflowResult<()> {
+    let dir = workspace.artifact_dir("deterministic")?;
+    let first = pass(&dir.join("first.json"), false)?;
+    // Make sure timestamps with second resolution are different
+    thread::sleep(Duration::from_millis(1100));
+    let second = pass(&dir.join("second.json"), true)?;
+
+    let paths: BTreeSet<&PathBuf> = first.keys().chain(second.keys()).collect();
+    let mut different = Vec::new();
+
+    for path in paths {
+        let first = first.get(path).map_or("", String::as_str);
+        let second = second.get(path).map_or("", String::as_str);
+
+        if first != second {
+            eprintln!(
+                "{} in file \"{}\":\n{}\n",
+                style::paint("Different output on the second pass", Color::Red),
+                path.display(),
+                style::diff(first, second)
+            );
+            different.push(format!("  {}", path.display()));
+        }
+    }
+
+    if different.is_empty() {
+        println!("Codegen is deterministic");
+        return Ok(());
+    }
+
+    Err(format!(
+        "{} generated file(s) aren't deterministic:\n{}",
+        different.len(),
+        different.join("\n")
+    )
+    .into())
+}
+
+/// Run a codegen pass in a new process, and read the captured files.
+fn pass(capture_file: &Path, perturb_env: bool) -> WorkflowResult<BTreeMap<PathBuf, String>> {
+    let perturbed = ["TZ", "LANG", "LC_ALL"];
+    let mut vars: Vec<(OsString, OsString)> = env::vars_os()
+        .filter(|(name, _)| name != template::CACHE_ENV_VAR)
+        .filter(|(name, _)| !perturb_env || !perturbed.iter().any(|var| name == var))
+        .collect();
+
+    if perturb_env {
+        vars.reverse();
+        vars.extend(
+            [("TZ", "Pacific/Kiritimati"), ("LANG", "C"), ("LC_ALL", "C")]
+                .map(|(name, value)| (name.into(), value.into())),
+        );
+    }
+
+    vars.push((CAPTURE_ENV_VAR.into(), capture_file.into()));
+
+    // Keep any other arguments, like `--only`, or arguments for a custom xtask.
+    let mut args: Vec<OsString> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--verify-deterministic")
+        .collect();
+    args.push("--check".into());
+
+    duct::cmd(env::current_exe()?, args).full_env(vars).run()?;
+
+    Ok(serde_json::from_str(&fs::read_to_string(capture_file)?)?)
+}
@@ -0,0 +1,88 @@
+use std::{
+    env, fs,
+    io::stdout,
+    path::{Path, PathBuf},
+};
+
+use clap::Command;
+use clap_complete::Shell;
+
+use crate::WorkflowResult;
+
+const BIN_NAME: &str = "cargo-xtask";
+
+/// Where to put the generated completions
+pub enum Destination<'a> {
+    /// A directory, using the standard file name for the shell
+    Dir(&'a Path),
+    /// The standard per-user completions directory for the shell
+    Install,
+    /// `stdout`
+    Print,
+}
+
+/// Generate completions for `shell`, or the current user's shell if `shell` is
+/// `None`.
+pub fn generate(
+    shell: Option<Shell>,
+    mut command: Command,
+    destination: Destination,
+) -> WorkflowResult<()> {
+    let shell = shell
+        .or_else(Shell::from_env)
+        .ok_or("Unable to detect your shell. Please specify one.")?;
+
+    match destination {
+        Destination::Dir(dir) => {
+            let file = clap_complete::generate_to(shell, &mut command, BIN_NAME, dir)?;
+            println!("Completions file generated in `{}`", file.display());
+        }
+        Destination::Install => {
+            let dir = install_dir(shell)?;
+            fs::create_dir_all(&dir)?;
+            let file = clap_complete::generate_to(shell, &mut command, BIN_NAME, &dir)?;
+            println!("Completions installed to `{}`", file.display());
+
+            if shell == Shell::Zsh {
+                println!(
+                    "Make sure `{}` is in your `fpath`, and `compinit` is called in `.zshrc`",
+                    dir.display()
+                );
+            }
+        }
+        Destination::Print => clap_complete::generate(shell, &mut command, BIN_NAME, &mut stdout()),
+    }
+
+    Ok(())
+}
+
+/// The standard per-user completions directory for `shell`.
+fn install_dir(shell: Shell) -> WorkflowResult<PathBuf> {
+    let home = || -> WorkflowResult<PathBuf> {
+        env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| "`HOME` is not set".into())
+    };
+    let xdg_dir = |var: &str, default: &[&str]| -> WorkflowResult<PathBuf> {
+        match env::var_os(var) {
+            Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+            _ => Ok(default.iter().fold(home()?, |path, dir| path.join(dir))),
+        }
+    };
+
+    Ok(match shell {
+        Shell::Bash => xdg_dir("XDG_DATA_HOME", &[".local", "share"])?
+            .join("bash-completion")
+            .join("completions"),
+        Shell::Fish => xdg_dir("XDG_CONFIG_HOME", &[".config"])?
+            .join("fish")
+            .join("completions"),
+        Shell::Zsh => home()?.join(".zfunc"),
+        _ => {
+            return Err(format!(
+                "Installing completions for {shell} is not supported. Use `--print` instead."
+            )
+            .into())
+        }
+    })
+}
@@ -0,0 +1,328 @@
+//! Project configuration from `xtask.toml` or `Cargo.toml`
+//!
+//! Configuration is read from `[workspace.metadata.xtask]` and
+//! `[package.metadata.xtask]` tables. For example:
+//!
+//! ```toml
+//! [package.metadata.xtask]
+//! features = ["serde", "async"]
+//! targets = ["wasm32-unknown-unknown"]
+//! skip = ["udeps"]
+//!
+//! [package.metadata.xtask.codegen]
+//! readme = true
+//!
+//! [workspace.metadata.xtask.versions]
+//! rustc-stable = "1.76"
+//! rustc-nightly = "nightly-2024-02-24"
+//! nightly-policy = "auto-bump"
+//! udeps = "0.1.46"
+//! sort = "1.0.9"
+//!
+//! [workspace.metadata.xtask.versions.tools]
+//! cargo-llvm-cov = "0.6.5"
+//! ```
+//!
+//! The workspace configuration can be put in an `xtask.toml` file in the
+//! workspace root instead, without the `workspace.metadata.xtask` prefix:
+//!
+//! ```toml
+//! jobs = ["tests", "lints"]
+//! platforms = ["ubuntu-latest", "windows-latest"]
+//! features = ["serde"]
+//! readme = true
+//! license-start-year = 2022
+//! license-holder = "Example Corp"
+//! notice = true
+//! spdx-headers = "MIT OR Apache-2.0"
+//! binstall = true
+//! third-party-licenses = true
+//! allowed-duplicates = ["windows-sys"]
+//!
+//! [versions]
+//! rustc-stable = "1.76"
+//!
+//! [env]
+//! allow = ["CARGO_*", "RUSTUP_*"]
+//! deny = ["RUSTFLAGS"]
+//! ```
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::OsString,
+    fs,
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{ci::NightlyPolicy, github::actions::Platform, WorkflowResult};
+
+/// The name of the workspace config file
+pub const CONFIG_FILE: &str = "xtask.toml";
+
+/// An `xtask` metadata table
+#[derive(Default, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct XtaskConfig {
+    /// Features to test with
+    pub features: Vec<String>,
+    /// Extra targets to build for
+    pub targets: Vec<String>,
+    /// Names of tasks to skip, like `udeps` or `clippy`. These are the labels
+    /// of the CI steps. See [`Run::label`](crate::github::actions::Run::label).
+    pub skip: BTreeSet<String>,
+    /// Options for the project's own codegen
+    pub codegen: Value,
+    /// Pinned Rust and tool versions
+    pub versions: VersionsConfig,
+    /// The standard CI jobs to generate. Defaults to all of them.
+    pub jobs: Option<BTreeSet<String>>,
+    /// The platforms to generate CI jobs for. Defaults to all of them.
+    pub platforms: Option<Vec<Platform>>,
+    /// Build `README.md` from `README.tmpl.md` during codegen
+    pub readme: bool,
+    /// Generate license files during codegen, with the copyright starting
+    /// from this year
+    pub license_start_year: Option<i32>,
+    /// The copyright holder for generated license files
+    pub license_holder: Option<String>,
+    /// Generate a `NOTICE` file with third party attributions, along with the
+    /// licenses
+    pub notice: bool,
+    /// Add SPDX headers with this license expression to source files, along
+    /// with the licenses
+    pub spdx_headers: Option<String>,
+    /// Add `cargo binstall` metadata to packages with binaries during codegen.
+    /// See [`generate_binstall_metadata`](crate::generate_binstall_metadata).
+    pub binstall: bool,
+    /// Generate `THIRD_PARTY_LICENSES.md` during codegen. See
+    /// [`generate_third_party_licenses`](crate::generate_third_party_licenses).
+    pub third_party_licenses: bool,
+    /// Crates that can have more than one semver incompatible version, for
+    /// `cargo xtask duplicates`
+    pub allowed_duplicates: BTreeSet<String>,
+    /// The environment variables passed to commands
+    pub env: EnvConfig,
+}
+
+/// Version overrides for [`StandardVersions`](crate::ci::StandardVersions)
+#[derive(Default, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct VersionsConfig {
+    pub rustc_stable: Option<String>,
+    pub rustc_nightly: Option<String>,
+    pub udeps: Option<String>,
+    pub sort: Option<String>,
+    pub nightly_policy: Option<NightlyPolicy>,
+    /// Other pinned tools, by crate name. See
+    /// [`WorkspaceVersions::tool`](crate::ci::WorkspaceVersions::tool).
+    pub tools: BTreeMap<String, String>,
+}
+
+/// The filter set with [`EnvConfig::apply`]
+static ENV_FILTER: Mutex<Option<EnvConfig>> = Mutex::new(None);
+
+/// Which environment variables are passed to commands run locally, and by the
+/// `shell` template helper
+///
+/// This stops generated files and local CI runs from silently depending on
+/// the developer's environment. Names ending in `*` match any variable with
+/// that prefix. Names are matched ignoring case on Windows, where variable
+/// names aren't case sensitive, and exactly everywhere else. The variables in
+/// [`EnvConfig::ESSENTIAL`] are always passed, so commands can be found and
+/// run. Otherwise, `deny` takes precedence over `allow`.
+#[derive(Default, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EnvConfig {
+    /// Only pass these variables. Everything is passed if this isn't set.
+    pub allow: Option<Vec<String>>,
+    /// Never pass these variables
+    pub deny: Vec<String>,
+}
+
+impl EnvConfig {
+    /// Variables the OS, `cargo` and `rustup` need, which are always passed.
+    pub const ESSENTIAL: [&'static str; 14] = [
+        "PATH",
+        "PATHEXT",
+        "HOME",
+        "USERPROFILE",
+        "TMPDIR",
+        "TEMP",
+        "TMP",
+        "SYSTEMROOT",
+        "WINDIR",
+        "COMSPEC",
+        "APPDATA",
+        "LOCALAPPDATA",
+        "CARGO_HOME",
+        "RUSTUP_HOME",
+    ];
+
+    /// Should the variable `name` be passed to commands?
+    pub fn allows(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name
+                .get(..prefix.len())
+                .is_some_and(|start| same_name(start, prefix)),
+            None => same_name(name, pattern),
+        };
+
+        if Self::ESSENTIAL
+            .iter()
+            .any(|essential| same_name(name, essential))
+        {
+            return true;
+        }
+
+        let allowed = match &self.allow {
+            Some(allow) => allow.iter().any(matches),
+            None => true,
+        };
+
+        allowed && !self.deny.iter().any(matches)
+    }
+
+    /// Filter the environment of commands run from now on.
+    ///
+    /// This is done for you with the workspace config, by
+    /// [`in_workspace`](crate::in_workspace).
+    pub fn apply(self) {
+        *ENV_FILTER.lock().unwrap() = Some(self);
+    }
+
+    /// The environment to run commands with, or `None` to inherit all of it
+    pub(crate) fn filtered_env() -> Option<Vec<(OsString, OsString)>> {
+        let filter = ENV_FILTER.lock().unwrap();
+        let filter = filter
+            .as_ref()
+            .filter(|filter| filter.allow.is_some() || !filter.deny.is_empty())?;
+
+        Some(
+            env::vars_os()
+                .filter(|(name, _)| name.to_str().is_some_and(|name| filter.allows(name)))
+                .collect(),
+        )
+    }
+}
+
+/// Are `a` and `b` the same variable name on this platform?
+fn same_name(a: &str, b: &str) -> bool {
+    if cfg!(windows) {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+impl XtaskConfig {
+    /// Is the task called `task` in the skip list?
+    pub fn is_skipped(&self, task: &str) -> bool {
+        self.skip.contains(task)
+    }
+
+    /// Is the standard CI job called `job` enabled?
+    pub fn is_job_enabled(&self, job: &str) -> bool {
+        match &self.jobs {
+            Some(jobs) => jobs.contains(job),
+            None => true,
+        }
+    }
+
+    /// Should CI jobs be generated for `platform`?
+    pub fn is_platform_enabled(&self, platform: Platform) -> bool {
+        match &self.platforms {
+            Some(platforms) => platforms.contains(&platform),
+            None => true,
+        }
+    }
+
+    /// Read the workspace config from `xtask.toml` in `workspace_root`, if it
+    /// exists, or the `xtask` table in the workspace `metadata`.
+    pub(crate) fn for_workspace(workspace_root: &Path, metadata: &Value) -> WorkflowResult<Self> {
+        let config_file = workspace_root.join(CONFIG_FILE);
+
+        if !config_file.exists() {
+            return Self::from_metadata(metadata, "workspace");
+        }
+
+        if metadata.get("xtask").is_some() {
+            return Err(format!(
+                "Workspace config found in both `{CONFIG_FILE}` and \
+                 `[workspace.metadata.xtask]`. Use one or the other."
+            )
+            .into());
+        }
+
+        toml::from_str(&fs::read_to_string(&config_file)?)
+            .map_err(|e| format!("Invalid `{CONFIG_FILE}`: {e}").into())
+    }
+
+    /// Parse the `xtask` table from a `metadata` table, if present.
+    pub(crate) fn from_metadata(metadata: &Value, context: &str) -> WorkflowResult<Self> {
+        match metadata.get("xtask") {
+            Some(xtask) => XtaskConfig::deserialize(xtask)
+                .map_err(|e| format!("Invalid `{context}.metadata.xtask`: {e}").into()),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvConfig;
+
+    fn env_config(allow: Option<&[&str]>, deny: &[&str]) -> EnvConfig {
+        let strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        EnvConfig {
+            allow: allow.map(strings),
+            deny: strings(deny),
+        }
+    }
+
+    #[test]
+    fn allow_everything_by_default() {
+        assert!(EnvConfig::default().allows("RUSTFLAGS"));
+    }
+
+    #[test]
+    fn prefixes() {
+        let config = env_config(Some(&["CARGO_*"]), &["CARGO_TARGET_*"]);
+
+        assert!(config.allows("CARGO_INCREMENTAL"));
+        assert!(!config.allows("CARGO_TARGET_DIR"));
+        assert!(!config.allows("CARGO"));
+        assert!(!config.allows("RUSTFLAGS"));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        let config = env_config(Some(&["RUSTFLAGS", "RUSTDOCFLAGS"]), &["RUSTFLAGS"]);
+
+        assert!(!config.allows("RUSTFLAGS"));
+        assert!(config.allows("RUSTDOCFLAGS"));
+    }
+
+    #[test]
+    fn essentials_are_always_allowed() {
+        let config = env_config(Some(&[]), &["PATH", "CARGO_*"]);
+
+        assert!(config.allows("PATH"));
+        assert!(config.allows("CARGO_HOME"));
+        assert!(!config.allows("CARGO_INCREMENTAL"));
+    }
+
+    #[test]
+    fn case_is_ignored_on_windows() {
+        let config = env_config(Some(&["RUST_LOG", "CARGO_*"]), &[]);
+
+        assert!(config.allows("RUST_LOG"));
+        assert_eq!(config.allows("Path"), cfg!(windows));
+        assert_eq!(config.allows("rust_log"), cfg!(windows));
+        assert_eq!(config.allows("Cargo_Incremental"), cfg!(windows));
+    }
+}
@@ -0,0 +1,218 @@
+//! Code coverage, shared by `cargo xtask coverage` and [`Tasks::coverage`]
+//!
+//! [`Tasks::coverage`]: crate::ci::Tasks::coverage
+use std::{ffi::OsString, fs, path::Path};
+
+use clap::ValueEnum;
+
+use crate::{
+    cmd,
+    docs::open_browser,
+    github::actions::{self, Run},
+    install_on_demand, summary, WorkflowResult,
+};
+
+/// Where CI writes coverage reports, matching `cargo xtask coverage`
+pub(crate) const CI_OUTPUT_DIR: &str = "target/xtask/coverage";
+
+/// The coverage report format
+#[derive(Copy, Clone, Eq, PartialEq, Default)]
+pub enum CoverageReport {
+    /// Print a summary to `stdout`
+    #[default]
+    Summary,
+    /// An HTML report in `{output_dir}/html`
+    Html,
+    /// An LCOV file at `{output_dir}/lcov.info`
+    Lcov,
+}
+
+impl CoverageReport {
+    /// The `cargo llvm-cov` arguments to write this report to `output_dir`.
+    ///
+    /// Use [`CoverageTool::runs`] to support other coverage tools.
+    pub fn args(self, output_dir: impl AsRef<Path>) -> Vec<OsString> {
+        let output_dir = output_dir.as_ref();
+        let mut args: Vec<OsString> = vec!["llvm-cov".into(), "--workspace".into()];
+
+        match self {
+            CoverageReport::Summary => (),
+            CoverageReport::Html => {
+                args.extend(["--html".into(), "--output-dir".into()]);
+                args.push(output_dir.into());
+            }
+            CoverageReport::Lcov => {
+                args.extend(["--lcov".into(), "--output-path".into()]);
+                args.push(output_dir.join("lcov.info").into());
+            }
+        }
+
+        args
+    }
+}
+
+/// The tool used to measure coverage
+///
+/// Each tool writes reports to the same locations, so the rest of the build
+/// doesn't need to know which one is used.
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum CoverageTool {
+    /// `cargo-llvm-cov`
+    #[default]
+    LlvmCov,
+    /// `cargo-tarpaulin`, which doesn't need the LLVM tools. It's best
+    /// supported on Linux.
+    Tarpaulin,
+    /// `grcov`, using the compiler's source based coverage
+    Grcov,
+}
+
+impl CoverageTool {
+    /// The crate to install the tool from
+    pub fn crate_name(self) -> &'static str {
+        match self {
+            CoverageTool::LlvmCov => "cargo-llvm-cov",
+            CoverageTool::Tarpaulin => "cargo-tarpaulin",
+            CoverageTool::Grcov => "grcov",
+        }
+    }
+
+    /// Does the tool need the `llvm-tools-preview` rustup component?
+    pub fn needs_llvm_tools(self) -> bool {
+        self != CoverageTool::Tarpaulin
+    }
+
+    /// The commands to run the tests and write `report` to `output_dir`.
+    pub fn runs(self, report: CoverageReport, output_dir: impl AsRef<Path>) -> Vec<Run> {
+        let output_dir = output_dir.as_ref();
+
+        match self {
+            CoverageTool::LlvmCov => vec![actions::cmd("cargo", report.args(output_dir))],
+            CoverageTool::Tarpaulin => {
+                let (format, dir) = match report {
+                    CoverageReport::Summary => ("Stdout", output_dir.to_owned()),
+                    CoverageReport::Html => ("Html", output_dir.join("html")),
+                    CoverageReport::Lcov => ("Lcov", output_dir.to_owned()),
+                };
+                let mut args: Vec<OsString> = ["tarpaulin", "--workspace", "--out", format]
+                    .map(Into::into)
+                    .to_vec();
+                args.extend(["--output-dir".into(), dir.into()]);
+
+                vec![actions::cmd("cargo", args)]
+            }
+            CoverageTool::Grcov => {
+                let profile = output_dir.join("profraw").join("cargo-test-%p-%m.profraw");
+                let test = actions::cmd("cargo", ["test", "--workspace"])
+                    .env("RUSTFLAGS", "-Cinstrument-coverage")
+                    .env("LLVM_PROFILE_FILE", profile.display());
+                // Profiles are found by searching the whole workspace, as tests
+                // run in their package's directory.
+                let mut args: Vec<OsString> = [
+                    ".",
+                    "--binary-path",
+                    "target/debug/deps",
+                    "--source-dir",
+                    ".",
+                    "--branch",
+                    "--ignore-not-existing",
+                    "--output-type",
+                ]
+                .map(Into::into)
+                .to_vec();
+
+                match report {
+                    CoverageReport::Summary => args.push("markdown".into()),
+                    CoverageReport::Html => {
+                        args.extend(["html".into(), "--output-path".into()]);
+                        args.push(output_dir.join("html").into_os_string());
+                    }
+                    CoverageReport::Lcov => {
+                        args.extend(["lcov".into(), "--output-path".into()]);
+                        args.push(output_dir.join("lcov.info").into_os_string());
+                    }
+                }
+
+                vec![test, actions::cmd("grcov", args)]
+            }
+        }
+    }
+}
+
+/// Run the tests with coverage, writing reports to `output_dir`.
+pub(crate) fn run(
+    tool: CoverageTool,
+    output_dir: &Path,
+    report: CoverageReport,
+    open: bool,
+) -> WorkflowResult<()> {
+    if open && report != CoverageReport::Html {
+        return Err("`--open` is only supported for HTML reports".into());
+    }
+
+    if tool.needs_llvm_tools() {
+        cmd("rustup", ["component", "add", "llvm-tools-preview"])?;
+    }
+
+    let program = match tool {
+        CoverageTool::Grcov => vec!["grcov"],
+        CoverageTool::LlvmCov => vec!["cargo", "llvm-cov"],
+        CoverageTool::Tarpaulin => vec!["cargo", "tarpaulin"],
+    };
+    install_on_demand(tool.crate_name(), &program)?;
+
+    let profile_dir = output_dir.join("profraw");
+
+    if profile_dir.exists() {
+        fs::remove_dir_all(&profile_dir)?;
+    }
+
+    for run in tool.runs(report, output_dir) {
+        run.run()?;
+    }
+
+    if report != CoverageReport::Summary {
+        println!("Coverage report generated in `{}`", output_dir.display());
+    }
+
+    if report == CoverageReport::Lcov {
+        summary::add(lcov_summary(&output_dir.join("lcov.info"))?)?;
+    }
+
+    if open {
+        let html_dir = output_dir.join("html");
+        let index = match tool {
+            CoverageTool::Tarpaulin => html_dir.join("tarpaulin-report.html"),
+            CoverageTool::LlvmCov | CoverageTool::Grcov => html_dir.join("index.html"),
+        };
+
+        open_browser(&index.to_string_lossy())?;
+    }
+
+    Ok(())
+}
+
+/// A markdown summary of the line coverage in the LCOV file at `path`
+fn lcov_summary(path: &Path) -> WorkflowResult<String> {
+    let mut found = 0;
+    let mut hit = 0;
+
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(count) = line.strip_prefix("LF:") {
+            found += count.trim().parse::<u64>()?;
+        } else if let Some(count) = line.strip_prefix("LH:") {
+            hit += count.trim().parse::<u64>()?;
+        }
+    }
+
+    let coverage = if found == 0 {
+        "No lines were instrumented".to_owned()
+    } else {
+        format!(
+            "{:.1}% of lines covered ({hit} of {found})",
+            100.0 * hit as f64 / found as f64
+        )
+    };
+
+    Ok(format!("### Coverage\n\n{coverage}\n"))
+}
@@ -0,0 +1,185 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    thread,
+};
+
+use crate::{ci::DOC_ARGS, cmd, watch, WorkflowResult, Workspace};
+
+/// Build the docs with the same flags as CI, and serve them on `localhost`.
+///
+/// If `watch` is true, the docs are rebuilt whenever a file changes.
+pub fn serve(workspace: &Workspace, port: u16, open: bool, watch: bool) -> WorkflowResult<()> {
+    cmd("cargo", DOC_ARGS)?;
+
+    let doc_dir = workspace.target_dir().join("doc");
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let url = format!("http://{}/", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let doc_dir = doc_dir.clone();
+            thread::spawn(move || {
+                if let Err(e) = respond(&doc_dir, stream) {
+                    eprintln!("Error serving docs: {e}");
+                }
+            });
+        }
+    });
+
+    println!("Serving docs on {url}");
+
+    if open {
+        open_browser(&url)?;
+    }
+
+    if watch {
+        watch::on_change(
+            workspace.0.workspace_root.as_std_path(),
+            workspace.target_dir(),
+            || {
+                if let Err(e) = cmd("cargo", DOC_ARGS) {
+                    eprintln!("{e}");
+                }
+            },
+        )
+    } else {
+        loop {
+            thread::park();
+        }
+    }
+}
+
+fn respond(doc_dir: &Path, mut stream: TcpStream) -> WorkflowResult<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split(['?', '#']).next().unwrap_or("/");
+
+    let (status, content_type, body) = match resolve(doc_dir, &percent_decode(path)) {
+        Some(file) => (
+            "200 OK",
+            content_type(&file),
+            fs::read(&file).map_err(|e| format!("{}: {e}", file.display()))?,
+        ),
+        None if path == "/" => ("200 OK", "text/html", crate_index(doc_dir)?.into_bytes()),
+        None => ("404 Not Found", "text/plain", b"Not found".to_vec()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: \
+         close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+
+    Ok(())
+}
+
+/// Find the file for a request path, without escaping `doc_dir`.
+fn resolve(doc_dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut file = doc_dir.to_path_buf();
+
+    for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        // Only allow plain names, so requests can't escape `doc_dir`. Windows
+        // also treats `\` and `:` as path separators.
+        let mut components = Path::new(segment).components();
+        let is_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+
+        if !is_name || segment.contains(['\\', ':']) {
+            return None;
+        }
+
+        file.push(segment);
+    }
+
+    if file.is_dir() {
+        file.push("index.html");
+    }
+
+    file.is_file().then_some(file)
+}
+
+/// An index page linking to the docs for each crate.
+fn crate_index(doc_dir: &Path) -> WorkflowResult<String> {
+    let mut crates = Vec::new();
+
+    for entry in fs::read_dir(doc_dir)? {
+        let path = entry?.path();
+
+        if path.join("index.html").is_file() {
+            if let Some(name) = path.file_name() {
+                crates.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    crates.sort();
+
+    let links = crates
+        .iter()
+        .map(|name| format!("<li><a href=\"{name}/index.html\">{name}</a></li>"))
+        .collect::<String>();
+
+    Ok(format!(
+        "<!DOCTYPE html><html><head><title>Crates</title></head><body><h1>Crates</h1><ul>{links}</ul></body></html>"
+    ))
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("txt" | "md") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Open `url` in the default browser.
+pub fn open_browser(url: &str) -> WorkflowResult<()> {
+    if cfg!(target_os = "windows") {
+        cmd("cmd", ["/C", "start", "", url])
+    } else if cfg!(target_os = "macos") {
+        cmd("open", [url])
+    } else {
+        cmd("xdg-open", [url])
+    }
+}
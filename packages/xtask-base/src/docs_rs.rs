@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use cargo_metadata::Package;
+use serde::Deserialize;
+
+use crate::{
+    github::actions::{Cmd, RunOptions},
+    is_publishable, WorkflowResult, Workspace,
+};
+
+/// The `[package.metadata.docs.rs]` table.
+///
+/// See <https://docs.rs/about/metadata>. Keys that don't affect whether the
+/// docs build are ignored.
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct DocsRsMetadata {
+    all_features: bool,
+    features: Vec<String>,
+    no_default_features: bool,
+    default_target: Option<String>,
+    rustc_args: Vec<String>,
+    rustdoc_args: Vec<String>,
+    cargo_args: Vec<String>,
+}
+
+/// Build the docs for each publishable package as docs.rs would, or just for
+/// `package` if it's specified.
+///
+/// This uses a nightly toolchain, with `--cfg docsrs` and the options from
+/// `[package.metadata.docs.rs]`. The docs are built in `target/docs-rs`, so
+/// they don't invalidate the normal build.
+pub fn check(workspace: &Workspace, package: Option<&str>) -> WorkflowResult<()> {
+    let packages = workspace
+        .0
+        .workspace_packages()
+        .into_iter()
+        .filter(|p| match package {
+            Some(package) => p.name == package,
+            None => is_publishable(p),
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(package) = package {
+        if packages.is_empty() {
+            return Err(format!("Package \"{package}\" not found in workspace").into());
+        }
+    }
+
+    let target_dir = workspace.target_dir().join("docs-rs");
+    // Use the current toolchain if it's already nightly, for example under
+    // `cargo xtask ci` or with a nightly `rust-toolchain.toml`.
+    let is_nightly = duct::cmd("rustc", ["-V"]).read()?.contains("-nightly");
+
+    for package in packages {
+        build_docs(package, &target_dir, is_nightly)?;
+    }
+
+    Ok(())
+}
+
+fn build_docs(package: &Package, target_dir: &Path, is_nightly: bool) -> WorkflowResult<()> {
+    let metadata = match package.metadata.get("docs").and_then(|docs| docs.get("rs")) {
+        Some(metadata) => DocsRsMetadata::deserialize(metadata).map_err(|e| {
+            format!(
+                "Invalid `[package.metadata.docs.rs]` for \"{}\": {e}",
+                package.name
+            )
+        })?,
+        None => DocsRsMetadata::default(),
+    };
+
+    let mut cmd = Cmd::new("cargo");
+
+    if !is_nightly {
+        cmd = cmd.arg("+nightly");
+    }
+
+    cmd = cmd
+        .args(["doc", "--no-deps", "--package", &package.name])
+        .arg("--target-dir")
+        .arg(target_dir);
+
+    if metadata.all_features {
+        cmd = cmd.arg("--all-features");
+    }
+
+    if !metadata.features.is_empty() {
+        cmd = cmd.args(["--features", &metadata.features.join(",")]);
+    }
+
+    if metadata.no_default_features {
+        cmd = cmd.arg("--no-default-features");
+    }
+
+    if let Some(target) = &metadata.default_target {
+        cmd = cmd.args(["--target", target]);
+    }
+
+    // `docsrs` is only set for `rustdoc`, as dependencies might not build with
+    // it on a newer nightly than docs.rs used for them.
+    let rustdoc_flags = ["--cfg", "docsrs"]
+        .into_iter()
+        .chain(metadata.rustdoc_args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut env = vec![("RUSTDOCFLAGS".to_owned(), rustdoc_flags)];
+
+    // An empty `RUSTFLAGS` would override flags from the cargo config.
+    if !metadata.rustc_args.is_empty() {
+        env.push(("RUSTFLAGS".to_owned(), metadata.rustc_args.join(" ")));
+    }
+
+    cmd.args(&metadata.cargo_args)
+        .run_in_dir(None::<&Path>, &env, RunOptions::default())
+}
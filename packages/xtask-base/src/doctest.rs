@@ -0,0 +1,197 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use cargo_metadata::{DependencyKind, Message, PackageId};
+
+use crate::{summary, WorkflowResult, Workspace};
+
+/// Compile and run the Rust code blocks in `README.md` and any markdown files
+/// under `docs/`.
+///
+/// Code blocks are tested with `rustdoc --test`, so they can use the same
+/// attributes as doc tests (`ignore`, `no_run`, `should_panic`, etc.). All the
+/// library crates in the workspace, and their direct dependencies and dev
+/// dependencies, are available to the examples.
+pub fn test_markdown(workspace: &Workspace) -> WorkflowResult<()> {
+    let mut files = Vec::new();
+
+    if Path::new("README.md").exists() {
+        files.push(PathBuf::from("README.md"));
+    }
+
+    markdown_files(Path::new("docs"), &mut files)?;
+
+    if files.is_empty() {
+        println!("No markdown files found");
+        return Ok(());
+    }
+
+    let externs = build_libraries(workspace)?;
+    let deps_dir = workspace.target_dir().join("debug").join("deps");
+    let edition = workspace
+        .0
+        .root_package()
+        .or_else(|| workspace.0.workspace_packages().into_iter().next())
+        .map_or_else(|| "2021".to_owned(), |package| package.edition.to_string());
+
+    let mut dependency = OsString::from("dependency=");
+    dependency.push(&deps_dir);
+    let mut args = vec!["--edition".into(), edition.into(), "-L".into(), dependency];
+
+    for (name, rlib) in &externs {
+        let mut arg = OsString::from(format!("{name}="));
+        arg.push(rlib);
+        args.push("--extern".into());
+        args.push(arg);
+    }
+
+    let mut results = Vec::new();
+
+    for file in files {
+        println!("Testing `{}`", file.display());
+        let test_args = [OsString::from("--test"), file.clone().into_os_string()];
+        let status = duct::cmd("rustdoc", args.iter().cloned().chain(test_args))
+            .unchecked()
+            .run()?
+            .status;
+        results.push((file, status.success()));
+    }
+
+    if summary::is_github_actions() {
+        summary::add(format!(
+            "### Markdown examples\n\n{}",
+            summary::table(
+                ["File", "Result"],
+                results.iter().map(|(file, success)| {
+                    [
+                        format!("`{}`", file.display()),
+                        if *success { "passed" } else { "failed" }.to_owned(),
+                    ]
+                })
+            )
+        ))?;
+    }
+
+    let failed = results
+        .iter()
+        .filter(|(_, success)| !success)
+        .map(|(file, _)| format!("`{}`", file.display()))
+        .collect::<Vec<_>>();
+
+    if !failed.is_empty() {
+        return Err(format!("Examples in {} failed", failed.join(", ")).into());
+    }
+
+    Ok(())
+}
+
+fn markdown_files(dir: &Path, files: &mut Vec<PathBuf>) -> WorkflowResult<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            markdown_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Build all the workspace libraries and their dependencies, including dev
+/// dependencies, returning the crate names and library paths to pass with
+/// `--extern`.
+fn build_libraries(workspace: &Workspace) -> WorkflowResult<BTreeMap<String, PathBuf>> {
+    let extern_names = extern_names(workspace);
+    let mut cargo = Command::new("cargo")
+        .args([
+            "build",
+            "--workspace",
+            "--lib",
+            "--tests",
+            "--message-format=json-render-diagnostics",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let stdout = cargo.stdout.take().expect("Stdout should be piped");
+    let mut externs = BTreeMap::new();
+
+    for message in Message::parse_stream(BufReader::new(stdout)) {
+        let Message::CompilerArtifact(artifact) = message? else {
+            continue;
+        };
+        let Some(name) = extern_names.get(&artifact.package_id) else {
+            continue;
+        };
+        let is_proc_macro = artifact.target.kind.iter().any(|kind| kind == "proc-macro");
+        let library = artifact
+            .filenames
+            .into_iter()
+            .find(|file| is_proc_macro || file.extension() == Some("rlib"));
+
+        if let Some(library) = library {
+            externs
+                .entry(name.clone())
+                .or_insert_with(|| library.into());
+        }
+    }
+
+    if !cargo.wait()?.success() {
+        return Err("Failed to build workspace libraries".into());
+    }
+
+    Ok(externs)
+}
+
+/// The crate names of the workspace libraries, and their direct normal and dev
+/// dependencies, by package.
+fn extern_names(workspace: &Workspace) -> HashMap<&PackageId, String> {
+    let mut names = HashMap::new();
+
+    for package in workspace.0.workspace_packages() {
+        if let Some(lib) = package
+            .targets
+            .iter()
+            .find(|target| target.kind.iter().any(|kind| kind == "lib"))
+        {
+            names.insert(&package.id, lib.name.replace('-', "_"));
+        }
+    }
+
+    for node in workspace
+        .0
+        .resolve
+        .iter()
+        .flat_map(|resolve| &resolve.nodes)
+    {
+        if !workspace.0.workspace_members.contains(&node.id) {
+            continue;
+        }
+
+        for dep in &node.deps {
+            let is_build_only = dep
+                .dep_kinds
+                .iter()
+                .all(|info| info.kind == DependencyKind::Build);
+
+            if !is_build_only {
+                names
+                    .entry(&dep.pkg)
+                    .or_insert_with(|| dep.name.replace('-', "_"));
+            }
+        }
+    }
+
+    names
+}
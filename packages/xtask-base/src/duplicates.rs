@@ -0,0 +1,77 @@
+//! Check for more than one semver incompatible version of a crate
+use std::collections::{BTreeMap, BTreeSet};
+
+use cargo_metadata::semver::Version;
+use itertools::Itertools;
+
+use crate::{WorkflowResult, Workspace};
+
+/// Fail if the lockfile has semver incompatible versions of the same crate,
+/// unless they're in the `allowed-duplicates` config.
+///
+/// Versions are found with `cargo tree --duplicates`, for all targets.
+pub(crate) fn check(workspace: &Workspace) -> WorkflowResult<()> {
+    let allowed = workspace.config()?.allowed_duplicates;
+    let tree = duct::cmd!(
+        "cargo",
+        "tree",
+        "--workspace",
+        "--duplicates",
+        "--target",
+        "all",
+        "--depth",
+        "0",
+        "--prefix",
+        "none",
+        "--format",
+        "{p}"
+    )
+    .read()?;
+
+    let mut versions = BTreeMap::<&str, BTreeSet<Version>>::new();
+
+    for line in tree.lines() {
+        let mut fields = line.split_whitespace();
+
+        if let (Some(name), Some(version)) = (fields.next(), fields.next()) {
+            let version = Version::parse(version.trim_start_matches('v'))?;
+            versions.entry(name).or_default().insert(version);
+        }
+    }
+
+    for name in &allowed {
+        if !versions.contains_key(name.as_str()) {
+            println!("`{name}` is in `allowed-duplicates`, but isn't duplicated");
+        }
+    }
+
+    let duplicates = versions
+        .into_iter()
+        .filter(|(name, versions)| {
+            !allowed.contains(*name) && versions.iter().map(compatibility).unique().count() > 1
+        })
+        .map(|(name, versions)| format!("  {name}: {}", versions.iter().join(", ")))
+        .collect::<Vec<_>>();
+
+    if duplicates.is_empty() {
+        println!("No duplicate dependencies");
+        return Ok(());
+    }
+
+    Err(format!(
+        "Found semver incompatible versions of the same crate:\n{}\nUse `cargo tree --invert \
+         <crate>` to see where they come from, or add them to `allowed-duplicates` in the xtask \
+         config.",
+        duplicates.join("\n")
+    )
+    .into())
+}
+
+/// Versions with the same key are semver compatible.
+fn compatibility(version: &Version) -> (u64, u64, u64) {
+    match (version.major, version.minor) {
+        (0, 0) => (0, 0, version.patch),
+        (0, minor) => (0, minor, 0),
+        (major, _) => (major, 0, 0),
+    }
+}
@@ -0,0 +1,146 @@
+//! Macro expansion with `cargo expand`
+use std::{ffi::OsStr, fs, path::Path};
+
+use scopeguard::defer;
+
+use crate::{cmd, github::actions::Platform, WorkflowResult, Workspace};
+
+/// Show the expanded macros for `package`
+///
+/// The expansion is written to `out` if it's set, otherwise it's shown in a
+/// pager. With `diff_ref`, the package is also expanded at that git revision,
+/// and the difference is shown instead.
+pub(crate) fn run(
+    workspace: &Workspace,
+    package: &str,
+    out: Option<&Path>,
+    diff_ref: Option<&str>,
+) -> WorkflowResult<()> {
+    match (out, diff_ref) {
+        (None, None) => page(package),
+        (Some(out), None) => {
+            fs::write(out, expand(package, None)?)?;
+            println!("Expanded `{package}` to `{}`", out.display());
+            Ok(())
+        }
+        (out, Some(diff_ref)) => diff(workspace, package, out, diff_ref),
+    }
+}
+
+fn page(package: &str) -> WorkflowResult<()> {
+    if Platform::current() == Platform::WindowsLatest {
+        // `less` isn't generally available on Windows
+        cmd("cargo", ["expand", "--package", package])
+    } else {
+        duct::cmd("cargo", ["expand", "--color=always", "--package", package])
+            .pipe(duct::cmd("less", ["-r"]))
+            .run()?;
+        Ok(())
+    }
+}
+
+/// Expand `package`, from the current workspace, or from another checkout
+/// given as `(manifest_path, target_dir)`.
+fn expand(package: &str, checkout: Option<(&Path, &Path)>) -> WorkflowResult<String> {
+    let mut args = vec!["expand".into(), "--package".into(), package.into()];
+
+    if let Some((manifest_path, target_dir)) = checkout {
+        args.extend([
+            "--manifest-path".into(),
+            manifest_path.as_os_str().to_owned(),
+            "--target-dir".into(),
+            target_dir.as_os_str().to_owned(),
+        ]);
+    }
+
+    Ok(duct::cmd("cargo", args).read()?)
+}
+
+/// Expand `package` in the working tree and at `diff_ref`, and show the
+/// difference with `git diff`.
+///
+/// The old revision is checked out in a temporary git worktree, under
+/// `target/xtask/expand`, which is removed afterwards. Both expansions are
+/// kept in that directory.
+fn diff(
+    workspace: &Workspace,
+    package: &str,
+    out: Option<&Path>,
+    diff_ref: &str,
+) -> WorkflowResult<()> {
+    let expand_dir = workspace.artifact_dir("expand")?;
+    let file_stem = |revision: &str| {
+        let revision: String = revision
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        format!("{package}-{revision}.rs")
+    };
+    let old_file = expand_dir.join(file_stem(diff_ref));
+    let new_file = expand_dir.join(file_stem("working-tree"));
+    let worktree = expand_dir.join("worktree");
+
+    // The workspace root might not be the root of the git repository.
+    let prefix = duct::cmd!("git", "rev-parse", "--show-prefix").read()?;
+
+    if worktree.exists() {
+        cmd(
+            "git",
+            [
+                OsStr::new("worktree"),
+                "remove".as_ref(),
+                "--force".as_ref(),
+                worktree.as_os_str(),
+            ],
+        )?;
+    }
+
+    cmd(
+        "git",
+        [
+            OsStr::new("worktree"),
+            "add".as_ref(),
+            "--detach".as_ref(),
+            worktree.as_os_str(),
+            diff_ref.as_ref(),
+        ],
+    )?;
+
+    {
+        defer! {
+            if let Err(e) = duct::cmd!("git", "worktree", "remove", "--force", &worktree).run() {
+                eprintln!("Failed to remove worktree `{}`: {e}", worktree.display());
+            }
+        }
+
+        // Keep the build outside the worktree, so it can be reused next time.
+        let manifest_path = worktree.join(prefix.trim()).join("Cargo.toml");
+        let target_dir = expand_dir.join("target");
+        let expanded = expand(package, Some((&manifest_path, &target_dir)))?;
+        fs::write(&old_file, expanded)?;
+    }
+
+    fs::write(&new_file, expand(package, None)?)?;
+
+    let mut diff_args = vec!["diff".into(), "--no-index".into()];
+
+    if let Some(out) = out {
+        diff_args.extend(["--output".into(), out.as_os_str().to_owned()]);
+    }
+
+    diff_args.extend([old_file.into_os_string(), new_file.into_os_string()]);
+    let status = duct::cmd("git", diff_args).unchecked().run()?.status;
+
+    // `git diff` exits with 1 if there are differences
+    match status.code() {
+        Some(0) => println!("The expansion of `{package}` hasn't changed since `{diff_ref}`"),
+        Some(1) => {
+            if let Some(out) = out {
+                println!("Diff written to `{}`", out.display());
+            }
+        }
+        _ => return Err(format!("`git diff` failed: {status}").into()),
+    }
+
+    Ok(())
+}
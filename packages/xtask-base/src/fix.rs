@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use crate::{
+    ci::CI, cmd, cmd_in_dir, fmt, generate_cargo_config, github::actions::prune_stale_workflows,
+    install_on_demand, sort, style, WorkflowResult,
+};
+
+/// Run the fixing counterpart of each CI check that has one.
+///
+/// Code is formatted last, so it includes any changes from the other fixes.
+pub fn fix_all(
+    extra_workspace_dirs: &[&Path],
+    ci: CI,
+    codegen: impl FnOnce(bool) -> WorkflowResult<()>,
+) -> WorkflowResult<()> {
+    style::header("Codegen");
+    generate_cargo_config(false)?;
+    ci.write(false)?;
+    codegen(false)?;
+    prune_stale_workflows(false)?;
+
+    style::header("Clippy fixes");
+    let clippy_fix = [
+        "clippy",
+        "--fix",
+        "--allow-dirty",
+        "--allow-staged",
+        "--workspace",
+        "--all-targets",
+    ];
+
+    for dir in extra_workspace_dirs {
+        cmd_in_dir(Some(dir), "cargo", clippy_fix)?;
+    }
+
+    cmd("cargo", clippy_fix)?;
+
+    style::header("Sort dependencies");
+    sort(extra_workspace_dirs.iter().copied(), false)?;
+
+    style::header("Format TOML");
+    install_on_demand("taplo-cli", &["taplo"])?;
+    cmd("taplo", ["fmt"])?;
+
+    style::header("Format Rust");
+    fmt(extra_workspace_dirs.iter().copied())
+}
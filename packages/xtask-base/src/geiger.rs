@@ -0,0 +1,90 @@
+//! Unsafe code metrics, using `cargo-geiger`
+use std::fs;
+
+use serde_json::Value;
+
+use crate::{install_on_demand, summary, WorkflowResult, Workspace};
+
+/// Run `cargo geiger` on each workspace package, saving the JSON reports and
+/// a markdown summary in `target/xtask/geiger`.
+///
+/// Under Github Actions, the summary is also added to the job summary.
+pub(crate) fn run(workspace: &Workspace) -> WorkflowResult<()> {
+    install_on_demand("cargo-geiger", &["cargo", "geiger"])?;
+    let output_dir = workspace.artifact_dir("geiger")?;
+    let mut markdown = "### Unsafe code\n".to_string();
+
+    for package in workspace.0.workspace_packages() {
+        let name = &package.name;
+        eprintln!("Scanning `{name}` with `cargo geiger`");
+        let report = duct::cmd!(
+            "cargo",
+            "geiger",
+            "--package",
+            name,
+            "--output-format",
+            "Json"
+        )
+        .read()?;
+        fs::write(output_dir.join(format!("{name}.json")), &report)?;
+
+        markdown.push_str(&format!("\n#### `{name}`\n\n{}", package_summary(&report)?));
+    }
+
+    fs::write(output_dir.join("summary.md"), &markdown)?;
+
+    if summary::is_github_actions() {
+        summary::add(&markdown)?;
+    }
+
+    println!("Reports written to `{}`", output_dir.display());
+    Ok(())
+}
+
+/// A table of the crates that use unsafe code, from a JSON report.
+fn package_summary(report: &str) -> WorkflowResult<String> {
+    let report: Value = serde_json::from_str(report)?;
+    let mut forbid_count = 0;
+    let mut rows = Vec::new();
+
+    for entry in report["packages"].as_array().into_iter().flatten() {
+        let id = &entry["package"]["id"];
+        let unsafety = &entry["unsafety"];
+
+        if unsafety["forbids_unsafe"].as_bool() == Some(true) {
+            forbid_count += 1;
+        }
+
+        let used = &unsafety["used"];
+        let counts = ["functions", "exprs", "item_impls", "item_traits", "methods"]
+            .map(|kind| used[kind]["unsafe_"].as_u64().unwrap_or_default());
+
+        if counts.iter().any(|count| *count > 0) {
+            let name = id["name"].as_str().unwrap_or_default();
+            let version = id["version"].as_str().unwrap_or_default();
+            rows.push((format!("{name} {version}"), counts));
+        }
+    }
+
+    rows.sort();
+    let table = summary::table(
+        [
+            "Crate",
+            "Functions",
+            "Expressions",
+            "Impls",
+            "Traits",
+            "Methods",
+        ],
+        rows.into_iter().map(|(name, counts)| {
+            [name]
+                .into_iter()
+                .chain(counts.map(|count| count.to_string()))
+        }),
+    );
+
+    Ok(format!(
+        "Unsafe code used by each crate. {forbid_count} crates forbid unsafe \
+         code.\n\n{table}"
+    ))
+}
@@ -1,11 +1,42 @@
-use std::{env::consts::OS, fmt, path::PathBuf};
+use std::{
+    collections::BTreeSet,
+    env::{self, consts::OS},
+    error::Error,
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    mem,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    slice,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    config::EnvConfig,
+    progress::{self, JobProgress},
+    report,
+    style::{self, Color},
+    update_file, WorkflowResult,
+};
+
+mod parse;
+
+pub use parse::{parse_workflow, read_workflow, ImportedWorkflow};
+
+pub fn install_rust(mut rust: Rust) -> Step {
+    let cache = rust.cache.take();
+    let checkout = mem::take(&mut rust.checkout);
 
-use crate::{update_file, WorkflowResult};
-
-pub fn install_rust(rust: Rust) -> Step {
     Step(StepEnum::Multi(
-        [checkout(), rust.into(), rust_cache()]
+        [checkout.into(), rust.into()]
             .into_iter()
+            .chain(cache.map(Step::from))
             .collect(),
     ))
 }
@@ -14,6 +45,7 @@ pub fn install_rust(rust: Rust) -> Step {
 pub struct Workflow {
     name: String,
     triggers: Vec<Event>,
+    permissions: Vec<(String, String)>,
     jobs: Vec<Job>,
 }
 
@@ -21,6 +53,7 @@ pub fn workflow(name: &str) -> Workflow {
     Workflow {
         name: name.to_string(),
         triggers: Vec::new(),
+        permissions: Vec::new(),
         jobs: Vec::new(),
     }
 }
@@ -31,6 +64,15 @@ impl Workflow {
         self
     }
 
+    /// Grant the `GITHUB_TOKEN` `access` to a scope.
+    ///
+    /// For example, `permission("contents", "write")`. If the scope already
+    /// has higher access, it's kept.
+    pub fn permission(mut self, scope: &str, access: &str) -> Self {
+        add_permission(&mut self.permissions, scope, access);
+        self
+    }
+
     pub fn add_job(
         &mut self,
         name: &str,
@@ -40,6 +82,66 @@ impl Workflow {
         self.jobs.push(Job::new(name, runs_on, steps));
     }
 
+    /// Add a job that only runs when the Github Actions expression
+    /// `condition` is true.
+    pub fn add_conditional_job(
+        &mut self,
+        name: &str,
+        runs_on: Platform,
+        condition: &str,
+        steps: impl IntoIterator<Item = impl Into<Step>>,
+    ) {
+        let mut job = Job::new(name, runs_on, steps);
+        job.condition = Some(condition.to_string());
+        self.jobs.push(job);
+    }
+
+    pub(crate) fn push_job(&mut self, job: Job) {
+        self.jobs.push(job);
+    }
+
+    /// The IDs of all the jobs so far
+    pub(crate) fn job_ids(&self) -> Vec<String> {
+        self.jobs.iter().map(Job::id).collect()
+    }
+
+    /// The secrets required by any step
+    pub(crate) fn required_secrets(&self) -> BTreeSet<String> {
+        self.jobs
+            .iter()
+            .flat_map(|job| &job.steps)
+            .flat_map(Step::required_secrets)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The status check names of the jobs that always run
+    ///
+    /// Conditional jobs are left out, as a required check that never runs
+    /// would block every pull request.
+    pub(crate) fn check_names(&self) -> Vec<String> {
+        self.jobs
+            .iter()
+            .filter(|job| job.condition.is_none())
+            .flat_map(Job::check_names)
+            .collect()
+    }
+
+    /// Add a job that deploys to `environment`.
+    ///
+    /// Some actions, like `actions/deploy-pages`, require this.
+    pub fn add_deployment_job(
+        &mut self,
+        name: &str,
+        runs_on: Platform,
+        environment: &str,
+        steps: impl IntoIterator<Item = impl Into<Step>>,
+    ) {
+        let mut job = Job::new(name, runs_on, steps);
+        job.environment = Some(environment.to_string());
+        self.jobs.push(job);
+    }
+
     pub fn job(
         mut self,
         name: &str,
@@ -52,18 +154,74 @@ impl Workflow {
 
     pub fn write(&self, check: bool) -> WorkflowResult<()> {
         update_file(
-            [".github", "workflows", &format!("{}.yml", self.name)]
-                .into_iter()
-                .collect::<PathBuf>(),
+            workflows_dir().join(format!("{}.yml", self.name)),
             &self.to_string(),
             check,
         )
     }
 }
 
+/// The first line of every generated workflow
+const GENERATED_HEADER: &str =
+    "# This file was generated by [xtask-base](https://github.com/simon-bourne/rust-xtask-base).";
+
+fn workflows_dir() -> PathBuf {
+    [".github", "workflows"].into_iter().collect()
+}
+
+/// Remove generated workflows that weren't written by this run of codegen
+///
+/// A workflow is generated if it starts with the generated header, so hand
+/// written workflows are left alone. In check mode, stale workflows are
+/// reported as out of date instead.
+pub(crate) fn prune_stale_workflows(check: bool) -> WorkflowResult<()> {
+    let dir = workflows_dir();
+
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let current = report::file_reports()
+        .into_iter()
+        .map(|file| file.path)
+        .collect::<Vec<_>>();
+
+    for entry in dir.read_dir()? {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yml" || ext == "yaml");
+
+        if !is_yaml || current.contains(&path) || !is_generated(&path)? {
+            continue;
+        }
+
+        if check {
+            report::record_file(&path, true);
+            report::check_failure(format!(
+                "{} \"{}\" is no longer generated, and should be deleted",
+                style::paint("Stale workflow", Color::Red),
+                path.display()
+            ))?;
+        } else {
+            println!("Removing stale workflow \"{}\"", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_generated(path: &Path) -> WorkflowResult<bool> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .next()
+        .is_some_and(|line| line == GENERATED_HEADER))
+}
+
 impl fmt::Display for Workflow {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("# This file was generated by [xtask-base](https://github.com/simon-bourne/rust-xtask-base).\n")?;
+        writeln!(f, "{GENERATED_HEADER}")?;
         f.write_str("# Please do not edit!\n")?;
         writeln!(f, "name: {}", self.name)?;
         writeln!(f, "on:")?;
@@ -72,6 +230,14 @@ impl fmt::Display for Workflow {
             trigger.0.fmt(f)?;
         }
 
+        if !self.permissions.is_empty() {
+            f.write_str("permissions:\n")?;
+
+            for (scope, access) in &self.permissions {
+                writeln!(f, "  {scope}: {access}")?;
+            }
+        }
+
         f.write_str("jobs:\n")?;
 
         for job in &self.jobs {
@@ -81,14 +247,43 @@ impl fmt::Display for Workflow {
     }
 }
 
-struct Job {
+/// Grant `access` to `scope` in `permissions`.
+///
+/// If the scope already has higher access, it's kept, so granting `read` never
+/// downgrades a `write` needed elsewhere.
+pub(crate) fn add_permission(permissions: &mut Vec<(String, String)>, scope: &str, access: &str) {
+    let level = |access: &str| match access {
+        "write" => 2,
+        "read" => 1,
+        _ => 0,
+    };
+
+    match permissions
+        .iter_mut()
+        .find(|(existing, _)| existing == scope)
+    {
+        Some((_, existing)) => {
+            if level(access) > level(existing) {
+                *existing = access.to_string();
+            }
+        }
+        None => permissions.push((scope.to_string(), access.to_string())),
+    }
+}
+
+pub(crate) struct Job {
     name: String,
     runs_on: Platform,
+    needs: Vec<String>,
+    condition: Option<String>,
+    environment: Option<String>,
+    permissions: Vec<(String, String)>,
+    env_matrix: Vec<(String, Vec<String>)>,
     steps: Vec<Step>,
 }
 
 impl Job {
-    fn new(
+    pub(crate) fn new(
         name: &str,
         runs_on: Platform,
         steps: impl IntoIterator<Item = impl Into<Step>>,
@@ -96,16 +291,110 @@ impl Job {
         Self {
             name: name.to_string(),
             runs_on,
+            needs: Vec::new(),
+            condition: None,
+            environment: None,
+            permissions: Vec::new(),
+            env_matrix: Vec::new(),
             steps: steps.into_iter().map(Into::into).collect(),
         }
     }
+
+    /// The job's ID in the workflow
+    pub(crate) fn id(&self) -> String {
+        format!("{}-{}", self.name, self.runs_on.as_str())
+    }
+
+    /// The name of each status check this job reports, one per matrix
+    /// combination
+    fn check_names(&self) -> Vec<String> {
+        let id = self.id();
+
+        if self.env_matrix.is_empty() {
+            return vec![id];
+        }
+
+        self.env_matrix
+            .iter()
+            .map(|(_, values)| values.iter())
+            .multi_cartesian_product()
+            .map(|values| format!("{id} ({})", values.iter().join(", ")))
+            .collect()
+    }
+
+    /// Only run after the jobs with these IDs have succeeded.
+    pub(crate) fn needs(mut self, job_ids: Vec<String>) -> Self {
+        self.needs = job_ids;
+        self
+    }
+
+    pub(crate) fn condition(mut self, condition: Option<String>) -> Self {
+        self.condition = condition;
+        self
+    }
+
+    pub(crate) fn environment(mut self, environment: Option<String>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Set the `GITHUB_TOKEN`'s permissions for this job. These replace the
+    /// workflow's permissions.
+    pub(crate) fn permissions(mut self, permissions: Vec<(String, String)>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Run the job for each combination of environment variable values.
+    pub(crate) fn env_matrix(mut self, vars: Vec<(String, Vec<String>)>) -> Self {
+        self.env_matrix = vars;
+        self
+    }
 }
 
 impl fmt::Display for Job {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let runs_on = self.runs_on.as_str();
-        writeln!(f, "  {}-{}:", self.name, runs_on)?;
+        writeln!(f, "  {}:", self.id())?;
         writeln!(f, "    runs-on: {}", runs_on)?;
+
+        if !self.needs.is_empty() {
+            writeln!(f, "    needs: [{}]", self.needs.join(", "))?;
+        }
+
+        if let Some(condition) = &self.condition {
+            writeln!(f, "    if: {}", yaml_scalar(condition, 6))?;
+        }
+
+        if let Some(environment) = &self.environment {
+            writeln!(f, "    environment: {}", yaml_scalar(environment, 6))?;
+        }
+
+        if !self.permissions.is_empty() {
+            f.write_str("    permissions:\n")?;
+
+            for (scope, access) in &self.permissions {
+                writeln!(f, "      {scope}: {access}")?;
+            }
+        }
+
+        if !self.env_matrix.is_empty() {
+            f.write_str("    strategy:\n")?;
+            f.write_str("      fail-fast: false\n")?;
+            f.write_str("      matrix:\n")?;
+
+            for (name, values) in &self.env_matrix {
+                let values = values.iter().map(|value| single_quoted(value)).join(", ");
+                writeln!(f, "        {name}: [{values}]")?;
+            }
+
+            f.write_str("    env:\n")?;
+
+            for (name, _) in &self.env_matrix {
+                writeln!(f, "      {name}: ${{{{ matrix.{name} }}}}")?;
+            }
+        }
+
         f.write_str("    steps:\n")?;
 
         for step in &self.steps {
@@ -121,6 +410,9 @@ pub struct Event(EventEnum);
 enum EventEnum {
     Push(Push),
     PullRequest(PullRequest),
+    PullRequestTarget(PullRequestTarget),
+    MergeGroup(MergeGroup),
+    Schedule(Schedule),
 }
 
 impl fmt::Display for EventEnum {
@@ -128,25 +420,104 @@ impl fmt::Display for EventEnum {
         match self {
             EventEnum::Push(push) => {
                 f.write_str("  push:\n")?;
+                list("branches", &push.branches, f)?;
+                quoted_list("paths", &push.paths, f)?;
+            }
+            EventEnum::PullRequest(pull_request) => {
+                f.write_str("  pull_request:\n")?;
+                quoted_list("paths", &pull_request.paths, f)?;
+            }
+            EventEnum::PullRequestTarget(_) => f.write_str("  pull_request_target:\n")?,
+            EventEnum::MergeGroup(_) => f.write_str("  merge_group:\n")?,
+            EventEnum::Schedule(schedule) => {
+                f.write_str("  schedule:\n")?;
 
-                if !push.branches.is_empty() {
-                    f.write_str("    branches:\n")?;
-
-                    for branch in &push.branches {
-                        writeln!(f, "    - {branch}")?;
-                    }
+                for cron in &schedule.crons {
+                    writeln!(f, "  - cron: '{cron}'")?;
                 }
             }
-            EventEnum::PullRequest(_) => f.write_str("  pull_request:\n")?,
         }
 
         Ok(())
     }
 }
 
+fn list(name: &str, items: &[String], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if !items.is_empty() {
+        writeln!(f, "    {name}:")?;
+
+        for item in items {
+            writeln!(f, "    - {item}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A list of single quoted strings, so globs like `**` aren't parsed as YAML
+/// aliases.
+fn quoted_list(name: &str, items: &[String], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let items = items
+        .iter()
+        .map(|item| single_quoted(item))
+        .collect::<Vec<_>>();
+    list(name, &items, f)
+}
+
+fn single_quoted(item: &str) -> String {
+    format!("'{}'", item.replace('\'', "''"))
+}
+
+/// Format `value` as a YAML scalar, quoting it if it would be misread as
+/// plain text, or as another type like a number or boolean.
+///
+/// Multi-line values are written as literal blocks, with each line indented
+/// by `indent` spaces.
+fn yaml_scalar(value: &str, indent: usize) -> String {
+    if value.contains('\n') {
+        let indent = " ".repeat(indent);
+        let lines = value
+            .lines()
+            .map(|line| format!("\n{indent}{line}"))
+            .join("");
+        return format!("|{lines}");
+    }
+
+    let needs_quotes = value.is_empty()
+        || value.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c) || c.is_whitespace())
+        || value.ends_with(|c: char| c == ':' || c.is_whitespace())
+        || value.contains(": ")
+        || value.contains(" #")
+        || !is_plain_string(value);
+
+    if needs_quotes {
+        single_quoted(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Would `value` be read as a string, if it's not quoted?
+///
+/// As well as YAML 1.2's types, this checks for YAML 1.1 booleans like `on`
+/// and `yes`, as some parsers still support them.
+fn is_plain_string(value: &str) -> bool {
+    const YAML_1_1_BOOLS: [&str; 8] = ["y", "n", "yes", "no", "on", "off", "true", "false"];
+
+    if YAML_1_1_BOOLS.contains(&value.to_ascii_lowercase().as_str()) {
+        return false;
+    }
+
+    matches!(
+        serde_yaml::from_str::<serde_yaml::Value>(value),
+        Ok(serde_yaml::Value::String(parsed)) if parsed == value
+    )
+}
+
 #[derive(Default)]
 pub struct Push {
     branches: Vec<String>,
+    paths: Vec<String>,
 }
 
 pub fn push() -> Push {
@@ -158,6 +529,14 @@ impl Push {
         self.branches.push(branch.into());
         self
     }
+
+    /// Only trigger when files matching one of `paths` change.
+    ///
+    /// See [`Workspace::path_filters`](crate::Workspace::path_filters).
+    pub fn paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
 }
 
 impl From<Push> for Event {
@@ -166,10 +545,23 @@ impl From<Push> for Event {
     }
 }
 
-pub struct PullRequest;
+#[derive(Default)]
+pub struct PullRequest {
+    paths: Vec<String>,
+}
 
 pub fn pull_request() -> PullRequest {
-    PullRequest
+    PullRequest::default()
+}
+
+impl PullRequest {
+    /// Only trigger when files matching one of `paths` change.
+    ///
+    /// See [`Workspace::path_filters`](crate::Workspace::path_filters).
+    pub fn paths(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.paths.extend(paths.into_iter().map(Into::into));
+        self
+    }
 }
 
 impl From<PullRequest> for Event {
@@ -178,10 +570,67 @@ impl From<PullRequest> for Event {
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+/// Runs in the context of the base branch, so has access to secrets and a
+/// writable `GITHUB_TOKEN`, even for pull requests from forks.
+pub struct PullRequestTarget;
+
+pub fn pull_request_target() -> PullRequestTarget {
+    PullRequestTarget
+}
+
+impl From<PullRequestTarget> for Event {
+    fn from(value: PullRequestTarget) -> Self {
+        Self(EventEnum::PullRequestTarget(value))
+    }
+}
+
+/// Runs when a pull request is added to a merge queue.
+pub struct MergeGroup;
+
+pub fn merge_group() -> MergeGroup {
+    MergeGroup
+}
+
+impl From<MergeGroup> for Event {
+    fn from(value: MergeGroup) -> Self {
+        Self(EventEnum::MergeGroup(value))
+    }
+}
+
+pub struct Schedule {
+    crons: Vec<String>,
+}
+
+/// Trigger on a schedule, using POSIX cron syntax.
+pub fn schedule(cron: &str) -> Schedule {
+    Schedule {
+        crons: vec![cron.to_string()],
+    }
+}
+
+impl Schedule {
+    pub fn cron(mut self, cron: &str) -> Self {
+        self.crons.push(cron.to_string());
+        self
+    }
+}
+
+impl From<Schedule> for Event {
+    fn from(value: Schedule) -> Self {
+        Self(EventEnum::Schedule(value))
+    }
+}
+
+/// The platform set with [`Platform::emulate`]
+static EMULATED_PLATFORM: Mutex<Option<Platform>> = Mutex::new(None);
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Deserialize)]
 pub enum Platform {
+    #[serde(rename = "ubuntu-latest")]
     UbuntuLatest,
+    #[serde(rename = "macos-latest")]
     MacOSLatest,
+    #[serde(rename = "windows-latest")]
     WindowsLatest,
 }
 
@@ -204,7 +653,24 @@ impl Platform {
         }
     }
 
+    /// Execute jobs for this platform locally, as if it were the current one.
+    ///
+    /// This is for debugging jobs for other platforms. Steps that depend on
+    /// the real platform, like installing platform specific tools, may still
+    /// fail.
+    pub fn emulate(self) {
+        *EMULATED_PLATFORM.lock().unwrap() = Some(self);
+    }
+
+    /// Are jobs for this platform executed locally?
+    ///
+    /// This is the platform we're running on, unless another one is being
+    /// emulated. See [`Self::emulate`].
     pub fn is_current(self) -> bool {
+        if let Some(emulated) = *EMULATED_PLATFORM.lock().unwrap() {
+            return self == emulated;
+        }
+
         match self {
             Platform::UbuntuLatest => OS == "linux",
             Platform::MacOSLatest => OS == "macos",
@@ -212,7 +678,7 @@ impl Platform {
         }
     }
 
-    fn as_str(self) -> &'static str {
+    pub fn as_str(self) -> &'static str {
         match self {
             Platform::UbuntuLatest => "ubuntu-latest",
             Platform::MacOSLatest => "macos-latest",
@@ -221,13 +687,54 @@ impl Platform {
     }
 }
 
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::latest()
+            .find(|platform| platform.as_str() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown platform \"{s}\". Expected one of: {}",
+                    Self::latest().map(Self::as_str).join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Clone)]
 pub struct Action {
     uses: String,
+    condition: Option<String>,
     with: Vec<(String, String)>,
     env: Vec<(String, String)>,
+    secrets: Vec<String>,
 }
 
 impl Action {
+    /// The artifact name and path, if this is `actions/upload-artifact`
+    fn uploaded_artifact(&self) -> Option<(&str, &str)> {
+        if !self.uses.starts_with("actions/upload-artifact@") {
+            return None;
+        }
+
+        let input = |key| {
+            self.with
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.as_str())
+        };
+
+        Some((input("name").unwrap_or("artifact"), input("path")?))
+    }
+
+    /// Only run this action when the Github Actions expression `condition` is
+    /// true.
+    pub fn condition(mut self, condition: &str) -> Self {
+        self.condition = Some(condition.to_string());
+        self
+    }
+
     pub fn with(mut self, key: &str, value: impl fmt::Display) -> Self {
         self.add_with(key, value);
         self
@@ -246,20 +753,14 @@ impl Action {
         self.env.push((key.to_string(), value.to_string()));
     }
 
-    fn key_values(
-        name: &str,
-        key_values: &Vec<(String, String)>,
-        f: &mut fmt::Formatter<'_>,
-    ) -> Result<(), fmt::Error> {
-        if !key_values.is_empty() {
-            writeln!(f, "      {name}:")?;
-
-            for (key, value) in key_values {
-                writeln!(f, "        {key}: {value}")?;
-            }
-        };
-
-        Ok(())
+    /// Declare that the action needs the repository secret `name`, so it's
+    /// listed in `.github/required-secrets.json`.
+    ///
+    /// Pass the secret to the action with [`Self::with`] or [`Self::env`],
+    /// like `.with("token", "${{ secrets.NAME }}")`.
+    pub fn requires_secret(mut self, name: &str) -> Self {
+        self.secrets.push(name.to_string());
+        self
     }
 }
 
@@ -267,23 +768,128 @@ impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "    - uses: {}", self.uses)?;
 
-        Self::key_values("with", &self.with, f)?;
-        Self::key_values("env", &self.env, f)?;
+        if let Some(condition) = &self.condition {
+            writeln!(f, "      if: {}", yaml_scalar(condition, 8))?;
+        }
+
+        key_values("with", &self.with, f)?;
+        key_values("env", &self.env, f)?;
 
         Ok(())
     }
 }
 
+fn key_values(
+    name: &str,
+    key_values: &Vec<(String, String)>,
+    f: &mut fmt::Formatter<'_>,
+) -> Result<(), fmt::Error> {
+    if !key_values.is_empty() {
+        writeln!(f, "      {name}:")?;
+
+        for (key, value) in key_values {
+            writeln!(f, "        {key}: {}", yaml_scalar(value, 10))?;
+        }
+    };
+
+    Ok(())
+}
+
 pub fn action(uses: &str) -> Action {
     Action {
         uses: uses.to_string(),
+        condition: None,
         with: Vec::new(),
         env: Vec::new(),
+        secrets: Vec::new(),
     }
 }
 
-pub fn checkout() -> Step {
-    action("actions/checkout@v3").into()
+pub fn checkout() -> Checkout {
+    Checkout::default()
+}
+
+/// Options for `actions/checkout`
+///
+/// The defaults match the action's: a shallow clone of a single commit,
+/// without submodules or LFS files.
+#[derive(Clone, Default)]
+pub struct Checkout {
+    fetch_depth: Option<u32>,
+    submodules: Option<&'static str>,
+    lfs: bool,
+    token: Option<String>,
+    sparse_checkout: Vec<String>,
+}
+
+impl Checkout {
+    /// Fetch `depth` commits. `0` fetches all history, for all branches and
+    /// tags, which is needed by tools that look at previous releases.
+    pub fn fetch_depth(mut self, depth: u32) -> Self {
+        self.fetch_depth = Some(depth);
+        self
+    }
+
+    /// Check out submodules, and their submodules if `recursive` is true.
+    pub fn submodules(mut self, recursive: bool) -> Self {
+        self.submodules = Some(if recursive { "recursive" } else { "true" });
+        self
+    }
+
+    /// Download Git LFS files.
+    pub fn lfs(mut self) -> Self {
+        self.lfs = true;
+        self
+    }
+
+    /// Fetch with the token from the Github Actions expression `token`,
+    /// instead of `GITHUB_TOKEN`.
+    ///
+    /// For example, `token("secrets.CHECKOUT_TOKEN")`.
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Only check out `path`, along with files in the repository root. This
+    /// can be called more than once.
+    pub fn sparse_checkout(mut self, path: &str) -> Self {
+        self.sparse_checkout.push(path.to_string());
+        self
+    }
+}
+
+impl From<Checkout> for Step {
+    fn from(value: Checkout) -> Self {
+        let mut action = action("actions/checkout@v3");
+
+        if let Some(depth) = value.fetch_depth {
+            action.add_with("fetch-depth", depth);
+        }
+
+        if let Some(submodules) = value.submodules {
+            action.add_with("submodules", submodules);
+        }
+
+        if value.lfs {
+            action.add_with("lfs", true);
+        }
+
+        if let Some(token) = value.token {
+            action.add_with("token", format!("${{{{ {token} }}}}"));
+        }
+
+        if !value.sparse_checkout.is_empty() {
+            let paths = value
+                .sparse_checkout
+                .iter()
+                .map(|path| format!("{path}\n"))
+                .join("");
+            action.add_with("sparse-checkout", paths);
+        }
+
+        action.into()
+    }
 }
 
 impl From<Action> for Step {
@@ -292,6 +898,7 @@ impl From<Action> for Step {
     }
 }
 
+#[derive(Clone)]
 pub struct Step(StepEnum);
 
 pub fn multi_step(steps: impl IntoIterator<Item = impl Into<Step>>) -> Step {
@@ -299,8 +906,58 @@ pub fn multi_step(steps: impl IntoIterator<Item = impl Into<Step>>) -> Step {
 }
 
 impl Step {
+    /// Only run this step if an earlier step failed.
     pub fn if_failed(self) -> Self {
-        self
+        self.with_condition("failure()")
+    }
+
+    /// Run this step even if an earlier step failed, or the job was
+    /// cancelled.
+    pub(crate) fn always(self) -> Self {
+        self.with_condition("always()")
+    }
+
+    fn with_condition(self, condition: &str) -> Self {
+        Self(match self.0 {
+            StepEnum::Empty => StepEnum::Empty,
+            StepEnum::Multi(steps) => StepEnum::Multi(
+                steps
+                    .into_iter()
+                    .map(|step| step.with_condition(condition))
+                    .collect(),
+            ),
+            StepEnum::Action(action) => StepEnum::Action(action.condition(condition)),
+            StepEnum::Run(run) => StepEnum::Run(run.condition(condition)),
+        })
+    }
+
+    /// All the commands in this step, in order.
+    pub(crate) fn runs(&self) -> Vec<&Run> {
+        match &self.0 {
+            StepEnum::Empty | StepEnum::Action(_) => Vec::new(),
+            StepEnum::Multi(steps) => steps.iter().flat_map(Self::runs).collect(),
+            StepEnum::Run(run) => vec![run],
+        }
+    }
+
+    /// The secrets declared with `requires_secret`
+    pub(crate) fn required_secrets(&self) -> Vec<&str> {
+        match &self.0 {
+            StepEnum::Empty => Vec::new(),
+            StepEnum::Multi(steps) => steps.iter().flat_map(Self::required_secrets).collect(),
+            StepEnum::Action(action) => action.secrets.iter().map(String::as_str).collect(),
+            StepEnum::Run(run) => run.secrets.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// The name and path of each artifact uploaded with
+    /// `actions/upload-artifact`, in order.
+    pub(crate) fn uploaded_artifacts(&self) -> Vec<(&str, &str)> {
+        match &self.0 {
+            StepEnum::Empty | StepEnum::Run(_) => Vec::new(),
+            StepEnum::Multi(steps) => steps.iter().flat_map(Self::uploaded_artifacts).collect(),
+            StepEnum::Action(action) => action.uploaded_artifact().into_iter().collect(),
+        }
     }
 }
 
@@ -321,6 +978,7 @@ impl fmt::Display for Step {
     }
 }
 
+#[derive(Clone)]
 enum StepEnum {
     Empty,
     Multi(Vec<Step>),
@@ -335,22 +993,150 @@ pub fn upload_artifact(name: &str, path: &str) -> Step {
         .into()
 }
 
-pub fn rust_cache() -> Step {
-    action("Swatinem/rust-cache@v2").into()
+/// Post `path` as a comment on the pull request, or update the comment from a
+/// previous run.
+///
+/// Comments are identified by `header`, so each use needs a unique one. This
+/// only runs for pull requests, and needs `pull-requests: write` permission.
+/// See [`CI::permission`](crate::ci::CI::permission).
+pub fn sticky_pr_comment(header: &str, path: &str) -> Step {
+    action("marocchino/sticky-pull-request-comment@v2")
+        .condition("github.event_name == 'pull_request'")
+        .with("header", header)
+        .with("path", path)
+        .into()
+}
+
+/// Cache the cargo registry and target directory with `Swatinem/rust-cache`.
+///
+/// The defaults cache the root workspace, keyed on the job. Use
+/// [`Rust::cache`] to set the policy for a job.
+pub fn rust_cache() -> RustCache {
+    RustCache::default()
+}
+
+#[derive(Clone, Default)]
+pub struct RustCache {
+    shared_key: Option<String>,
+    workspaces: Vec<String>,
+    cache_on_failure: bool,
+    save_if: Option<String>,
+}
+
+impl RustCache {
+    /// Share the cache between all jobs with the same `key`, instead of
+    /// keying it on the job.
+    pub fn shared_key(mut self, key: &str) -> Self {
+        self.shared_key = Some(key.to_string());
+        self
+    }
+
+    /// Cache the workspace in `dir`, instead of the root workspace.
+    ///
+    /// This can be called more than once to cache multiple workspaces.
+    pub fn workspace(mut self, dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref().to_string_lossy().replace('\\', "/");
+        self.workspaces.push(dir);
+        self
+    }
+
+    /// Save the cache even if the job fails.
+    pub fn cache_on_failure(mut self) -> Self {
+        self.cache_on_failure = true;
+        self
+    }
+
+    /// Only save the cache when the Github Actions expression `condition` is
+    /// true.
+    ///
+    /// For example, `save_if("github.ref == 'refs/heads/main'")`.
+    pub fn save_if(mut self, condition: &str) -> Self {
+        self.save_if = Some(condition.to_string());
+        self
+    }
+}
+
+impl From<RustCache> for Step {
+    fn from(value: RustCache) -> Self {
+        let mut action = action("Swatinem/rust-cache@v2");
+
+        if let Some(key) = value.shared_key {
+            action.add_with("shared-key", key);
+        }
+
+        if !value.workspaces.is_empty() {
+            let workspaces = value
+                .workspaces
+                .iter()
+                .map(|dir| format!("{dir} -> target\n"))
+                .join("");
+            action.add_with("workspaces", workspaces);
+        }
+
+        if value.cache_on_failure {
+            action.add_with("cache-on-failure", true);
+        }
+
+        if let Some(condition) = value.save_if {
+            action.add_with("save-if", format!("${{{{ {condition} }}}}"));
+        }
+
+        action.into()
+    }
 }
 
 pub fn install(crate_name: &str, version: &str) -> Step {
-    cmd(
-        "cargo",
-        ["install", crate_name, "--locked", "--version", version],
-    )
-    .into()
+    install_with(Installer::Cargo, crate_name, version)
+}
+
+/// Install version `version` of the cargo tool `crate_name`, using
+/// `installer`.
+pub fn install_with(installer: Installer, crate_name: &str, version: &str) -> Step {
+    let tool = format!("{crate_name}@{version}");
+
+    match installer {
+        Installer::Cargo => cmd(
+            "cargo",
+            ["install", crate_name, "--locked", "--version", version],
+        )
+        .into(),
+        Installer::InstallAction => action("taiki-e/install-action@v2")
+            .with("tool", tool)
+            .into(),
+        Installer::Binstall => multi_step([
+            Step::from(action("cargo-bins/cargo-binstall@v1.6.4")),
+            cmd("cargo", ["binstall", "--no-confirm", "--locked", &tool]).into(),
+        ]),
+    }
+}
+
+/// How to install cargo tools in a workflow. See [`install_with`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Installer {
+    /// Build the tool from source, with `cargo install --locked`.
+    ///
+    /// [`rust_cache`] caches `~/.cargo/bin`, and `cargo install` skips tools
+    /// that are already installed at the requested version, so this is only
+    /// slow when the cache is cold.
+    #[default]
+    Cargo,
+    /// Install a prebuilt binary with `taiki-e/install-action`.
+    ///
+    /// Tools it doesn't have a manifest for are installed with
+    /// `cargo-binstall`.
+    InstallAction,
+    /// Install a prebuilt binary with `cargo-binstall`.
+    ///
+    /// This falls back to `cargo install` if there's no prebuilt binary.
+    Binstall,
 }
 
 pub struct Rust {
     toolchain: String,
     components: Vec<&'static str>,
     targets: Option<Vec<String>>,
+    cache: Option<RustCache>,
+    checkout: Checkout,
 }
 
 pub fn rust_toolchain(version: &str) -> Rust {
@@ -358,6 +1144,8 @@ pub fn rust_toolchain(version: &str) -> Rust {
         toolchain: version.to_string(),
         components: Vec::new(),
         targets: None,
+        cache: Some(rust_cache()),
+        checkout: checkout(),
     }
 }
 
@@ -366,10 +1154,19 @@ impl Rust {
         self.toolchain.starts_with("nightly")
     }
 
-    pub fn wasm(mut self) -> Self {
+    pub(crate) fn toolchain(&self) -> &str {
+        &self.toolchain
+    }
+
+    pub fn wasm(self) -> Self {
+        self.target("wasm32-unknown-unknown")
+    }
+
+    /// Install the standard library for `target`.
+    pub fn target(mut self, target: &str) -> Self {
         self.targets
             .get_or_insert_with(Vec::new)
-            .push("wasm32-unknown-unknown".to_string());
+            .push(target.to_string());
         self
     }
 
@@ -382,6 +1179,38 @@ impl Rust {
         self.components.push("rustfmt");
         self
     }
+
+    /// Add the `rust-src` component, needed to build the standard library.
+    pub fn rust_src(mut self) -> Self {
+        self.components.push("rust-src");
+        self
+    }
+
+    /// Add the `llvm-tools-preview` component, needed for code coverage.
+    pub fn llvm_tools(mut self) -> Self {
+        self.components.push("llvm-tools-preview");
+        self
+    }
+
+    /// Set the caching policy, used by [`install_rust`] and
+    /// [`Tasks::new`](crate::ci::Tasks::new).
+    pub fn cache(mut self, cache: RustCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Don't cache anything.
+    pub fn no_cache(mut self) -> Self {
+        self.cache = None;
+        self
+    }
+
+    /// Set the checkout options, used by [`install_rust`] and
+    /// [`Tasks::new`](crate::ci::Tasks::new).
+    pub fn checkout(mut self, checkout: Checkout) -> Self {
+        self.checkout = checkout;
+        self
+    }
 }
 
 impl From<Rust> for Step {
@@ -400,50 +1229,228 @@ impl From<Rust> for Step {
     }
 }
 
+#[derive(Clone)]
 pub struct Run {
     script: RunEnum,
-    directory: Option<String>,
+    label: Option<String>,
+    stage: Option<String>,
+    condition: Option<String>,
+    directory: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    secrets: Vec<String>,
+    only_on: Option<Vec<Platform>>,
+    except: Vec<Platform>,
 }
 
-pub fn cmd(program: impl Into<String>, args: impl IntoIterator<Item = impl AsRef<str>>) -> Run {
-    Run {
-        script: RunEnum::Single(Cmd::new(program).args(args)),
-        directory: None,
+/// Quote `arg` for a POSIX shell, if it needs it
+///
+/// Command arguments are quoted with this when they're written to `run`
+/// lines, so they're passed exactly as they are locally. See [`cmd`].
+///
+/// ```
+/// # use xtask_base::github::actions::shell_quote;
+/// assert_eq!(shell_quote("--release"), "--release");
+/// assert_eq!(shell_quote("it's here"), "'it'\\''s here'");
+/// ```
+pub fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c));
+
+    if is_safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
     }
 }
 
+/// Run `program` with `args`
+///
+/// In the workflow, arguments are quoted with [`shell_quote`], so they're
+/// passed to `program` exactly as they are locally, and steps with quoted
+/// arguments run with `bash` on every platform. Arguments containing Github
+/// Actions expressions, like `${{ matrix.target }}`, aren't quoted, so they're
+/// still expanded. Use [`shell_script`] for shell syntax, like `"$VAR"` or
+/// globs.
+pub fn cmd(program: impl Into<String>, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Run {
+    Run::new(RunEnum::Single(Cmd::new(program).args(args)))
+}
+
 pub fn script<Cmds, Cmd, Arg>(lines: Cmds) -> Run
 where
     Cmds: IntoIterator<Item = Cmd>,
     Cmd: IntoIterator<Item = Arg>,
     Arg: AsRef<str>,
 {
-    Run {
-        script: RunEnum::Multi(lines.into_iter().map(Into::into).collect()),
-        directory: None,
-    }
+    Run::new(RunEnum::Multi(lines.into_iter().map(Into::into).collect()))
+}
+
+/// A script for the runner's default shell, with each line written as it is.
+///
+/// That's `bash` on Linux and macOS, and PowerShell on Windows. Locally, it's
+/// run with the same shell. Use [`shell_quote`] for any literal arguments.
+pub fn shell_script(lines: impl IntoIterator<Item = impl Into<String>>) -> Run {
+    Run::new(RunEnum::Shell(lines.into_iter().map(Into::into).collect()))
 }
 
 impl Run {
-    pub fn dir(mut self, directory: &str) -> Self {
-        self.directory = Some(directory.to_string());
+    fn new(script: RunEnum) -> Self {
+        Self {
+            script,
+            label: None,
+            stage: None,
+            condition: None,
+            directory: None,
+            env: Vec::new(),
+            secrets: Vec::new(),
+            only_on: None,
+            except: Vec::new(),
+        }
+    }
+
+    /// Label this, so it can be selected by a [`Profile`](crate::ci::Profile).
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_string());
         self
     }
 
+    pub(crate) fn label_str(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Put this in a named stage. The workflow step is named after the stage,
+    /// and local runs print a header for each stage.
+    ///
+    /// See [`Tasks::stage`](crate::ci::Tasks::stage).
+    pub fn stage(mut self, name: &str) -> Self {
+        self.stage = Some(name.to_string());
+        self
+    }
+
+    pub(crate) fn stage_name(&self) -> Option<&str> {
+        self.stage.as_deref()
+    }
+
+    /// Only run this when the Github Actions expression `condition` is true.
+    ///
+    /// The condition only applies to the generated workflow.
+    pub fn condition(mut self, condition: &str) -> Self {
+        self.condition = Some(condition.to_string());
+        self
+    }
+
+    /// Set the working directory.
+    ///
+    /// Windows path separators are converted to `/` in the generated workflow,
+    /// so it's the same on every platform.
+    pub fn dir(mut self, directory: impl AsRef<Path>) -> Self {
+        self.directory = Some(directory.as_ref().to_owned());
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: impl fmt::Display) -> Self {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Pass the repository secret `name` in the environment variable of the
+    /// same name.
+    ///
+    /// Local runs use the environment variable from the current process, and
+    /// print a warning if it isn't set. Secrets are listed in
+    /// `.github/required-secrets.json` by [`CI::write`](crate::ci::CI::write).
+    pub fn requires_secret(mut self, name: &str) -> Self {
+        self.secrets.push(name.to_string());
+        self
+    }
+
+    /// Only run on `platform`.
+    ///
+    /// This can be called more than once to allow multiple platforms. When
+    /// added to [`Tasks`](crate::ci::Tasks) for any other platform, the run is
+    /// left out of both the generated workflow and local execution.
+    pub fn only_on(mut self, platform: Platform) -> Self {
+        self.only_on.get_or_insert_with(Vec::new).push(platform);
+        self
+    }
+
+    /// Don't run on `platform`. See [`Self::only_on`].
+    pub fn except(mut self, platform: Platform) -> Self {
+        self.except.push(platform);
+        self
+    }
+
+    /// Should this run on `platform`?
+    pub fn is_enabled_on(&self, platform: Platform) -> bool {
+        let included = match &self.only_on {
+            Some(platforms) => platforms.contains(&platform),
+            None => true,
+        };
+
+        included && !self.except.contains(&platform)
+    }
+
     pub fn run(&self) -> WorkflowResult<()> {
         self.rustup_run(false)
     }
 
     pub fn rustup_run(&self, is_nightly: bool) -> WorkflowResult<()> {
+        self.run_with(RunOptions {
+            toolchain: is_nightly.then_some("nightly"),
+            ..RunOptions::default()
+        })
+    }
+
+    /// The command line(s) this will run, for reporting.
+    pub(crate) fn command_line(&self) -> String {
+        match &self.script {
+            RunEnum::Single(cmd) => cmd.to_string(),
+            RunEnum::Multi(multi) => multi.iter().join("; "),
+            RunEnum::Shell(lines) => lines.join("; "),
+        }
+    }
+
+    pub(crate) fn run_with(&self, options: RunOptions) -> WorkflowResult<()> {
         let dir = self.directory.as_ref();
+        let mut env = self.env.clone();
+
+        for secret in &self.secrets {
+            // Pass secrets explicitly, so they aren't filtered out of the
+            // environment.
+            if let Ok(value) = env::var(secret) {
+                env.push((secret.clone(), value));
+            } else {
+                progress::println(
+                    options.progress,
+                    style::paint(
+                        format_args!(
+                            "Warning: secret `{secret}` isn't set. Set the `{secret}` environment \
+                             variable to pass it to `{}`.",
+                            self.command_line()
+                        ),
+                        Color::Yellow,
+                    ),
+                );
+            }
+        }
 
         match &self.script {
-            RunEnum::Single(single) => single.run_in_dir(dir, is_nightly)?,
+            RunEnum::Single(single) => single.run_in_dir(dir, &env, options)?,
             RunEnum::Multi(multi) => {
                 for cmd in multi {
-                    cmd.run_in_dir(dir, is_nightly)?;
+                    cmd.run_in_dir(dir, &env, options)?;
                 }
             }
+            RunEnum::Shell(lines) => {
+                let script = lines.join("\n");
+                let shell = if cfg!(windows) {
+                    Cmd::new("pwsh").args(["-Command", &script])
+                } else {
+                    Cmd::new("bash").args(["-e", "-c", &script])
+                };
+                shell.run_in_dir(dir, &env, options)?;
+            }
         }
 
         Ok(())
@@ -454,13 +1461,41 @@ impl fmt::Display for Run {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str("    - ")?;
 
+        if let Some(stage) = &self.stage {
+            let name = match &self.script {
+                RunEnum::Single(cmd) => format!("{stage} / {cmd}"),
+                RunEnum::Multi(_) | RunEnum::Shell(_) => stage.clone(),
+            };
+            writeln!(f, "name: {}", yaml_scalar(&name, 8))?;
+
+            f.write_str("      ")?;
+        }
+
+        if let Some(condition) = &self.condition {
+            writeln!(f, "if: {}", yaml_scalar(condition, 8))?;
+            f.write_str("      ")?;
+        }
+
         if let Some(directory) = &self.directory {
-            writeln!(f, "working-directory: {directory}")?;
+            let directory = directory.to_string_lossy().replace('\\', "/");
+            writeln!(f, "working-directory: {}", yaml_scalar(&directory, 8))?;
+            f.write_str("      ")?;
+        }
+
+        let cmds = match &self.script {
+            RunEnum::Single(cmd) => slice::from_ref(cmd),
+            RunEnum::Multi(multi) => multi.as_slice(),
+            RunEnum::Shell(_) => &[],
+        };
+
+        // The default shell on Windows is `pwsh`, which quotes differently.
+        if cmds.iter().any(Cmd::has_quoted_args) {
+            f.write_str("shell: bash\n")?;
             f.write_str("      ")?;
         }
 
         match &self.script {
-            RunEnum::Single(cmd) => writeln!(f, "run: {cmd}")?,
+            RunEnum::Single(cmd) => writeln!(f, "run: {}", yaml_scalar(&cmd.to_string(), 8))?,
             RunEnum::Multi(multi) => {
                 f.write_str("run: |\n")?;
 
@@ -468,8 +1503,28 @@ impl fmt::Display for Run {
                     writeln!(f, "        {cmd}")?;
                 }
             }
+            RunEnum::Shell(lines) => match lines.as_slice() {
+                [line] => writeln!(f, "run: {}", yaml_scalar(line, 8))?,
+                lines => {
+                    f.write_str("run: |\n")?;
+
+                    for line in lines {
+                        if line.is_empty() {
+                            writeln!(f)?;
+                        } else {
+                            writeln!(f, "        {line}")?;
+                        }
+                    }
+                }
+            },
         }
 
+        let secrets = self
+            .secrets
+            .iter()
+            .map(|name| (name.clone(), format!("${{{{ secrets.{name} }}}}")));
+        key_values("env", &self.env.iter().cloned().chain(secrets).collect(), f)?;
+
         Ok(())
     }
 }
@@ -480,15 +1535,34 @@ impl From<Run> for Step {
     }
 }
 
+/// Options for running commands locally
+#[derive(Copy, Clone, Default)]
+pub(crate) struct RunOptions<'a> {
+    /// Run with this toolchain, instead of the default
+    pub toolchain: Option<&'a str>,
+    /// Redirect `stdout` to `stderr`, so `stdout` can be used for
+    /// machine-readable output.
+    pub stdout_to_stderr: bool,
+    /// Append all output to this file, as well as the terminal
+    pub log_file: Option<&'a Path>,
+    /// The job's matrix values, set as environment variables
+    pub env: &'a [(String, String)],
+    /// Print output above this job's progress bar
+    pub progress: Option<&'a JobProgress>,
+}
+
+#[derive(Clone)]
 enum RunEnum {
     Single(Cmd),
     Multi(Vec<Cmd>),
+    Shell(Vec<String>),
 }
 
 #[doc(hidden)]
+#[derive(Clone)]
 pub struct Cmd {
     program: String,
-    args: Vec<String>,
+    args: Vec<OsString>,
 }
 
 impl Cmd {
@@ -499,21 +1573,43 @@ impl Cmd {
         }
     }
 
-    pub fn arg(mut self, arg: impl AsRef<str>) -> Self {
+    /// Add an argument. Paths are passed as they are, so they can contain
+    /// spaces or non UTF-8 characters.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
         self.args.push(arg.as_ref().to_owned());
         self
     }
 
-    pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+    pub fn args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
         self.args
             .extend(args.into_iter().map(|s| s.as_ref().to_owned()));
         self
     }
 
+    /// The arguments as they're written in a workflow. See [`cmd`].
+    fn workflow_args(&self) -> impl Iterator<Item = String> + '_ {
+        self.args.iter().map(|arg| {
+            let arg = arg.to_string_lossy();
+
+            if arg.contains("${{") {
+                arg.into_owned()
+            } else {
+                shell_quote(&arg)
+            }
+        })
+    }
+
+    /// Are any of the arguments quoted in the workflow?
+    fn has_quoted_args(&self) -> bool {
+        self.workflow_args()
+            .zip(&self.args)
+            .any(|(quoted, arg)| quoted.as_str() != arg.to_string_lossy())
+    }
+
     #[doc(hidden)]
     pub fn __extend_arg(mut self, arg_tail: &str) -> Self {
         if let Some(last_arg) = self.args.last_mut() {
-            last_arg.push_str(arg_tail);
+            last_arg.push(arg_tail);
         } else {
             self.program.push_str(arg_tail);
         }
@@ -521,34 +1617,238 @@ impl Cmd {
         self
     }
 
-    fn run_in_dir(&self, dir: Option<impl Into<PathBuf>>, is_nightly: bool) -> WorkflowResult<()> {
-        let cmd = if is_nightly {
+    /// Echo the command, then run it.
+    ///
+    /// `${{ matrix.NAME }}` expressions in the command, working directory and
+    /// environment are replaced with values from the matrix in `options`, as
+    /// they would be on a CI runner.
+    pub(crate) fn run_in_dir(
+        &self,
+        dir: Option<impl Into<PathBuf>>,
+        env: &[(String, String)],
+        options: RunOptions,
+    ) -> WorkflowResult<()> {
+        let matrix = options.env;
+        let substitute_os = |value: &OsStr| match value.to_str() {
+            Some(value) => substitute_matrix(value, matrix).map(OsString::from),
+            None => Ok(value.to_owned()),
+        };
+        let cmd = Self {
+            program: substitute_matrix(&self.program, matrix)?,
+            args: self
+                .args
+                .iter()
+                .map(|arg| substitute_os(arg))
+                .collect::<WorkflowResult<_>>()?,
+        };
+        let dir = dir
+            .map(|dir| substitute_os(dir.into().as_os_str()).map(PathBuf::from))
+            .transpose()?;
+        let env = env
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), substitute_matrix(value, matrix)?)))
+            .collect::<WorkflowResult<Vec<_>>>()?;
+
+        cmd.run_resolved(dir, &env, options)
+    }
+
+    /// Echo the command, then run it, with any expressions already resolved.
+    ///
+    /// Failures are reported with the full command line, working directory,
+    /// toolchain and exit code.
+    fn run_resolved(
+        &self,
+        dir: Option<PathBuf>,
+        env: &[(String, String)],
+        options: RunOptions,
+    ) -> WorkflowResult<()> {
+        // A toolchain file in the directory takes precedence, as it would on a
+        // CI runner.
+        let toolchain_file = dir.as_deref().and_then(toolchain_file);
+        let toolchain_override = options.toolchain.filter(|_| toolchain_file.is_none());
+        let toolchain = match (&toolchain_file, toolchain_override) {
+            (Some(file), _) => file.display().to_string(),
+            (None, Some(toolchain)) => toolchain.to_string(),
+            (None, None) => "default".to_string(),
+        };
+        let location = dir
+            .as_ref()
+            .map(|dir| format!(" (in {})", dir.display()))
+            .unwrap_or_default();
+        let progress = options.progress.filter(|progress| progress.is_visible());
+        progress::println(
+            progress,
+            format_args!(
+                "{}{location}",
+                style::paint(format_args!("$ {self}"), Color::Cyan)
+            ),
+        );
+
+        let mut cmd = if let Some(toolchain) = toolchain_override {
             duct::cmd(
                 "rustup",
-                ["run", "nightly", &self.program]
+                ["run", "--install", toolchain, &self.program]
                     .into_iter()
-                    .chain(self.args.iter().map(|s| s.as_str())),
+                    .map(OsStr::new)
+                    .chain(self.args.iter().map(OsString::as_os_str)),
             )
         } else {
             duct::cmd(&self.program, &self.args)
         };
 
-        if let Some(dir) = dir {
-            cmd.dir(dir)
+        if toolchain_file.is_some() {
+            // Cargo sets this for `cargo xtask`, and it overrides toolchain
+            // files
+            cmd = cmd.env_remove("RUSTUP_TOOLCHAIN");
+        }
+
+        for (key, value) in options.env.iter().chain(env) {
+            cmd = cmd.env(key, value);
+        }
+
+        // Outer `duct` expressions are applied first, so this needs to go after
+        // any variables that are set or removed.
+        if let Some(vars) = EnvConfig::filtered_env() {
+            cmd = cmd.full_env(vars);
+        }
+
+        if options.stdout_to_stderr {
+            cmd = cmd.stdout_to_stderr();
+        }
+
+        if let Some(dir) = &dir {
+            cmd = cmd.dir(dir);
+        }
+
+        let log_file = options
+            .log_file
+            .map(|path| {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                writeln!(file, "$ {self}{location}")?;
+                io::Result::Ok(file)
+            })
+            .transpose()?;
+
+        let failure = |reason: String| -> Box<dyn Error> {
+            let dir = match &dir {
+                Some(dir) => dir.clone(),
+                None => env::current_dir().unwrap_or_default(),
+            };
+
+            format!(
+                "Command `{self}` {reason}\n  directory: {}\n  toolchain: {toolchain}",
+                dir.display()
+            )
+            .into()
+        };
+
+        let status = if log_file.is_some() || progress.is_some() {
+            tee(cmd, log_file, options.stdout_to_stderr, progress)
         } else {
-            cmd
+            cmd.unchecked().run().map(|output| output.status)
+        }
+        .map_err(|e| failure(format!("couldn't be run: {e}")))?;
+
+        if !status.success() {
+            return Err(failure(match status.code() {
+                Some(code) => format!("failed with exit code {code}"),
+                None => "was terminated by a signal".to_string(),
+            }));
         }
-        .run()?;
 
         Ok(())
     }
 }
 
+/// Replace each `${{ matrix.NAME }}` expression in `text` with the value of
+/// `NAME` in `matrix`.
+///
+/// Other expressions, like `${{ secrets.TOKEN }}`, are left as they are.
+fn substitute_matrix(text: &str, matrix: &[(String, String)]) -> WorkflowResult<String> {
+    let mut result = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let end = start + len + 2;
+        let expression = &rest[start..end];
+        result.push_str(&rest[..start]);
+
+        match expression[3..len].trim().strip_prefix("matrix.") {
+            Some(name) => {
+                let (_, value) = matrix
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .ok_or_else(|| format!("Unknown matrix variable in `{expression}`"))?;
+                result.push_str(value);
+            }
+            None => result.push_str(expression),
+        }
+
+        rest = &rest[end..];
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The `rust-toolchain.toml` or `rust-toolchain` file for the relative
+/// directory `dir`, if it's in `dir` or one of its relative parents.
+///
+/// The current directory isn't checked, as its toolchain is already in use.
+fn toolchain_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .filter(|dir| !dir.as_os_str().is_empty() && dir != &Path::new("."))
+        .flat_map(|dir| ["rust-toolchain.toml", "rust-toolchain"].map(|file| dir.join(file)))
+        .find(|file| file.is_file())
+}
+
+/// Run `cmd`, copying `stdout` and `stderr` to both the terminal and
+/// `log_file`, if there is one.
+///
+/// Output is copied a line at a time, so it can be printed above `progress`.
+fn tee(
+    cmd: duct::Expression,
+    mut log_file: Option<File>,
+    to_stderr: bool,
+    progress: Option<&JobProgress>,
+) -> io::Result<ExitStatus> {
+    let mut reader = BufReader::new(cmd.stderr_to_stdout().unchecked().reader()?);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+
+        if let Some(log_file) = &mut log_file {
+            log_file.write_all(&line)?;
+        }
+
+        match progress {
+            Some(progress) => progress.println(String::from_utf8_lossy(&line).trim_end()),
+            None if to_stderr => io::stderr().write_all(&line)?,
+            None => io::stdout().write_all(&line)?,
+        }
+    }
+
+    let output = reader
+        .get_ref()
+        .try_wait()?
+        .ok_or_else(|| io::Error::other("Command didn't exit"))?;
+
+    Ok(output.status)
+}
+
 impl fmt::Display for Cmd {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(&self.program)?;
 
-        for arg in &self.args {
+        for arg in self.workflow_args() {
             write!(f, " {arg}")?;
         }
 
@@ -566,16 +1866,13 @@ where
         let program = args
             .next()
             .expect("Can't extract executable from empty argument list");
-        Self::new(program.as_ref()).args(args)
+        Self::new(program.as_ref()).args(args.map(|arg| arg.as_ref().to_owned()))
     }
 }
 
 impl From<Cmd> for Run {
     fn from(value: Cmd) -> Self {
-        Self {
-            script: RunEnum::Single(value),
-            directory: None,
-        }
+        Self::new(RunEnum::Single(value))
     }
 }
 
@@ -599,3 +1896,108 @@ macro_rules! cmd{
         Run::from(cmd)
     }}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{cmd, substitute_matrix, yaml_scalar, Job, Platform};
+
+    fn job_yaml(platform: Platform, args: &[&str]) -> String {
+        Job::new("test", platform, [cmd("echo", args)]).to_string()
+    }
+
+    #[test]
+    fn plain_strings() {
+        for value in [
+            "ubuntu-latest",
+            "cargo test --workspace",
+            "nightly-2024-02-24",
+        ] {
+            assert_eq!(yaml_scalar(value, 0), value);
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        for value in ["1.70", "1", "-1", "0x1f", "1e3", ".5", ".inf", ".nan"] {
+            assert_eq!(yaml_scalar(value, 0), format!("'{value}'"));
+        }
+    }
+
+    #[test]
+    fn booleans_and_null() {
+        for value in ["true", "False", "on", "OFF", "yes", "no", "y", "null", "~"] {
+            assert_eq!(yaml_scalar(value, 0), format!("'{value}'"));
+        }
+    }
+
+    #[test]
+    fn syntax() {
+        assert_eq!(yaml_scalar("", 0), "''");
+        assert_eq!(yaml_scalar("*.rs", 0), "'*.rs'");
+        assert_eq!(yaml_scalar("a: b", 0), "'a: b'");
+        assert_eq!(yaml_scalar("a #b", 0), "'a #b'");
+        assert_eq!(yaml_scalar("it's", 0), "it's");
+        assert_eq!(yaml_scalar("'quoted'", 0), "'''quoted'''");
+    }
+
+    #[test]
+    fn multi_line() {
+        assert_eq!(yaml_scalar("a\nb", 4), "|\n    a\n    b");
+    }
+
+    fn matrix() -> Vec<(String, String)> {
+        vec![
+            ("target".to_string(), "wasm32-unknown-unknown".to_string()),
+            ("features".to_string(), "std".to_string()),
+        ]
+    }
+
+    #[test]
+    fn matrix_in_longer_strings() {
+        assert_eq!(
+            substitute_matrix(
+                "--target=${{ matrix.target }} --features ${{matrix.features}},alloc",
+                &matrix()
+            )
+            .unwrap(),
+            "--target=wasm32-unknown-unknown --features std,alloc"
+        );
+    }
+
+    #[test]
+    fn other_expressions() {
+        let text = "${{ secrets.TOKEN }} ${{ github.ref";
+        assert_eq!(substitute_matrix(text, &matrix()).unwrap(), text);
+    }
+
+    #[test]
+    fn missing_matrix_key() {
+        let error = substitute_matrix("cargo test ${{ matrix.toolchain }}", &matrix())
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Unknown matrix variable in `${{ matrix.toolchain }}`"
+        );
+    }
+
+    #[test]
+    fn quoted_args() {
+        for platform in [Platform::UbuntuLatest, Platform::WindowsLatest] {
+            let yaml = job_yaml(platform, &["it's here"]);
+            let expected = "    - shell: bash\n      run: echo 'it'\\''s here'\n";
+            assert!(yaml.ends_with(expected), "{yaml}");
+        }
+    }
+
+    #[test]
+    fn unquoted_args() {
+        for platform in [Platform::UbuntuLatest, Platform::WindowsLatest] {
+            let yaml = job_yaml(platform, &["--target", "${{ matrix.target }}"]);
+            assert!(
+                yaml.ends_with("    - run: echo --target ${{ matrix.target }}\n"),
+                "{yaml}"
+            );
+        }
+    }
+}
@@ -0,0 +1,320 @@
+use std::{fs, path::Path};
+
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    action, merge_group, pull_request, pull_request_target, push, workflow, Event, Job, Platform,
+    Run, RunEnum, Schedule, Step, StepEnum, Workflow,
+};
+use crate::WorkflowResult;
+
+/// A workflow parsed from YAML, with warnings about anything that couldn't be
+/// represented.
+pub struct ImportedWorkflow {
+    pub workflow: Workflow,
+    pub warnings: Vec<String>,
+}
+
+/// Read a workflow from a YAML file.
+///
+/// See [`parse_workflow`].
+pub fn read_workflow(path: impl AsRef<Path>) -> WorkflowResult<ImportedWorkflow> {
+    let path = path.as_ref();
+    let default_name = path
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    parse_workflow(&fs::read_to_string(path)?, &default_name)
+}
+
+/// Parse a hand written workflow, on a best-effort basis.
+///
+/// This is intended to help with adopting `xtask-base`. Anything that can't be
+/// represented, like job matrices or step names, is left out and reported in
+/// [`ImportedWorkflow::warnings`]. Jobs are only imported if they run on one
+/// of the supported [`Platform`]s, and the platform is appended to job names
+/// when they're written, as with any other job.
+///
+/// `run` scripts are kept as they are, and run locally with the default shell.
+/// See [`shell_script`](super::shell_script).
+pub fn parse_workflow(yaml: &str, default_name: &str) -> WorkflowResult<ImportedWorkflow> {
+    let root: Value = serde_yaml::from_str(yaml)?;
+    let root = root.as_mapping().ok_or("Workflow should be a mapping")?;
+    let mut warnings = Vec::new();
+    let name = root
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or(default_name);
+    let mut imported = workflow(name);
+
+    for (key, value) in root {
+        match key.as_str().unwrap_or_default() {
+            "name" | "jobs" => (),
+            "on" => imported.triggers = events(value, &mut warnings),
+            "permissions" => match value.as_mapping() {
+                Some(permissions) => {
+                    imported.permissions = permissions
+                        .iter()
+                        .map(|(scope, access)| (scalar(scope), scalar(access)))
+                        .collect();
+                }
+                None => warnings.push(format!("Unsupported permissions: {}", scalar(value))),
+            },
+            key => warnings.push(format!("Unsupported workflow key \"{key}\"")),
+        }
+    }
+
+    let jobs = root
+        .get("jobs")
+        .and_then(Value::as_mapping)
+        .ok_or("Workflow has no jobs")?;
+
+    for (name, job) in jobs {
+        let name = scalar(name);
+
+        if let Some(job) = parse_job(&name, job, &mut warnings) {
+            imported.jobs.push(job);
+        }
+    }
+
+    Ok(ImportedWorkflow {
+        workflow: imported,
+        warnings,
+    })
+}
+
+fn events(value: &Value, warnings: &mut Vec<String>) -> Vec<Event> {
+    let events: Vec<(String, Value)> = match value {
+        Value::String(event) => vec![(event.clone(), Value::Null)],
+        Value::Sequence(events) => events
+            .iter()
+            .map(|event| (scalar(event), Value::Null))
+            .collect(),
+        Value::Mapping(events) => events
+            .iter()
+            .map(|(event, config)| (scalar(event), config.clone()))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    events
+        .into_iter()
+        .filter_map(|(event, config)| {
+            let event: Event = match event.as_str() {
+                "push" => {
+                    let mut push = push().paths(strings(config.get("paths")));
+
+                    for branch in strings(config.get("branches")) {
+                        push = push.branch(branch);
+                    }
+
+                    push.into()
+                }
+                "pull_request" => pull_request().paths(strings(config.get("paths"))).into(),
+                "pull_request_target" => pull_request_target().into(),
+                "merge_group" => merge_group().into(),
+                "schedule" => Schedule {
+                    crons: config
+                        .as_sequence()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|cron| cron.get("cron"))
+                        .map(scalar)
+                        .collect(),
+                }
+                .into(),
+                event => {
+                    warnings.push(format!("Unsupported event \"{event}\""));
+                    return None;
+                }
+            };
+
+            Some(event)
+        })
+        .collect()
+}
+
+fn parse_job(name: &str, job: &Value, warnings: &mut Vec<String>) -> Option<Job> {
+    let job = job.as_mapping()?;
+    let runs_on = scalar(job.get("runs-on")?);
+    let Some(platform) = Platform::latest().find(|platform| platform.as_str() == runs_on) else {
+        warnings.push(format!(
+            "Job \"{name}\" runs on unsupported platform \"{runs_on}\""
+        ));
+        return None;
+    };
+    // Generated job names have the platform appended
+    let name = name.strip_suffix(&format!("-{runs_on}")).unwrap_or(name);
+    let steps = job
+        .get("steps")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(|step| parse_step(name, step, warnings))
+        .collect::<Vec<_>>();
+    let mut imported = Job::new(name, platform, steps);
+    imported.condition = job.get("if").map(scalar);
+    imported.environment = job.get("environment").map(scalar);
+
+    for key in job.keys().map(scalar) {
+        if !["runs-on", "steps", "if", "environment"].contains(&key.as_str()) {
+            warnings.push(format!("Unsupported key \"{key}\" in job \"{name}\""));
+        }
+    }
+
+    Some(imported)
+}
+
+fn parse_step(job: &str, step: &Value, warnings: &mut Vec<String>) -> Option<Step> {
+    let step = step.as_mapping()?;
+    let mut supported = vec!["env"];
+    let env = key_values(step.get("env"));
+
+    let imported = if let Some(uses) = step.get("uses") {
+        supported.extend(["uses", "with", "if"]);
+        let mut imported = action(&scalar(uses));
+        imported.condition = step.get("if").map(scalar);
+        imported.with = key_values(step.get("with"));
+        imported.env = env;
+        Step(StepEnum::Action(imported))
+    } else if let Some(script) = step.get("run") {
+        supported.extend(["run", "working-directory", "if"]);
+        // Blank lines are kept, as they can be significant, for example in
+        // heredocs.
+        let lines = scalar(script).lines().map(str::to_owned).collect();
+        let mut run = Run::new(RunEnum::Shell(lines));
+        run.condition = step.get("if").map(scalar);
+        run.directory = step.get("working-directory").map(|dir| scalar(dir).into());
+        run.env = env;
+        Step(StepEnum::Run(run))
+    } else {
+        warnings.push(format!(
+            "Skipped step with no `uses` or `run` in job \"{job}\""
+        ));
+        return None;
+    };
+
+    for key in step.keys().map(scalar) {
+        if !supported.contains(&key.as_str()) {
+            warnings.push(format!("Unsupported step key \"{key}\" in job \"{job}\""));
+        }
+    }
+
+    Some(imported)
+}
+
+fn key_values(value: Option<&Value>) -> Vec<(String, String)> {
+    value
+        .and_then(Value::as_mapping)
+        .map(Mapping::iter)
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| (scalar(key), scalar(value)))
+        .collect()
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Sequence(items)) => items.iter().map(scalar).collect(),
+        Some(item) => vec![scalar(item)],
+        None => Vec::new(),
+    }
+}
+
+/// A scalar as a string, or YAML for anything else.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => value.clone(),
+        _ => serde_yaml::to_string(value)
+            .unwrap_or_default()
+            .trim_end()
+            .to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_workflow;
+    use crate::github::actions::{checkout, push, shell_script, workflow, Platform, Step};
+
+    #[test]
+    fn round_trip() {
+        let yaml = workflow("ci")
+            .on([push().branch("main")])
+            .permission("contents", "read")
+            .job(
+                "test",
+                Platform::UbuntuLatest,
+                [
+                    Step::from(checkout()),
+                    shell_script(["cargo build", "cargo test"]).into(),
+                ],
+            )
+            .to_string();
+        let imported = parse_workflow(&yaml, "default").unwrap();
+
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+        assert_eq!(imported.workflow.to_string(), yaml);
+    }
+
+    #[test]
+    fn unsupported_keys() {
+        let yaml = "\
+name: ci
+on:
+  workflow_dispatch:
+concurrency: ci
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    timeout-minutes: 10
+    steps:
+    - name: build
+      run: cargo build
+    - shell: bash
+";
+        let imported = parse_workflow(yaml, "default").unwrap();
+
+        assert_eq!(
+            imported.warnings,
+            [
+                "Unsupported event \"workflow_dispatch\"",
+                "Unsupported workflow key \"concurrency\"",
+                "Unsupported step key \"name\" in job \"test\"",
+                "Skipped step with no `uses` or `run` in job \"test\"",
+                "Unsupported key \"timeout-minutes\" in job \"test\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_in_run() {
+        let yaml = "\
+name: ci
+on:
+  push:
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+    - run: |
+        cat <<EOF
+        first
+
+        second
+        EOF
+";
+        let imported = parse_workflow(yaml, "default").unwrap();
+        let written = imported.workflow.to_string();
+
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+        assert!(
+            written.ends_with("        first\n\n        second\n        EOF\n"),
+            "{written}"
+        );
+    }
+}
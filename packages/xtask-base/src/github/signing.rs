@@ -0,0 +1,149 @@
+//! Code signing steps for release workflows
+//!
+//! These only run in Github Actions, with certificates and credentials taken
+//! from repository secrets.
+use super::actions::{action, multi_step, shell_quote, shell_script, Step};
+
+/// Sign `files` with `signtool`, on a Windows runner.
+///
+/// Uses these secrets:
+///
+/// - `WINDOWS_CERTIFICATE`: The base64 encoded `.pfx` certificate.
+/// - `WINDOWS_CERTIFICATE_PASSWORD`: The password for the certificate.
+pub fn signtool<'a>(files: impl IntoIterator<Item = &'a str>) -> Step {
+    let files = files.into_iter().map(powershell_quote).collect::<Vec<_>>();
+    let files = files.join(" ");
+
+    shell_script([
+        "$pfx = Join-Path $env:RUNNER_TEMP certificate.pfx",
+        "[IO.File]::WriteAllBytes($pfx, [Convert]::FromBase64String($env:WINDOWS_CERTIFICATE))",
+        "$signtool = (Get-ChildItem \"${env:ProgramFiles(x86)}\\Windows Kits\\10\\bin\" -Recurse -Filter signtool.exe | Where-Object FullName -like '*x64*' | Select-Object -Last 1).FullName",
+        &format!(
+            "& $signtool sign /f $pfx /p $env:WINDOWS_CERTIFICATE_PASSWORD /fd sha256 /tr \
+             http://timestamp.digicert.com /td sha256 {files}"
+        ),
+        "Remove-Item $pfx",
+    ])
+    .env("WINDOWS_CERTIFICATE", "${{ secrets.WINDOWS_CERTIFICATE }}")
+    .env(
+        "WINDOWS_CERTIFICATE_PASSWORD",
+        "${{ secrets.WINDOWS_CERTIFICATE_PASSWORD }}",
+    )
+    .into()
+}
+
+/// Sign the files in `folder` matching `filter` (for example `"exe,dll"`)
+/// with Azure Trusted Signing.
+///
+/// Uses these secrets:
+///
+/// - `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET`: The
+///   credentials for an Azure app registration with the "Trusted Signing
+///   Certificate Profile Signer" role.
+/// - `AZURE_SIGNING_ENDPOINT`: The endpoint for the signing account's region.
+/// - `AZURE_SIGNING_ACCOUNT`: The Trusted Signing account name.
+/// - `AZURE_CERTIFICATE_PROFILE`: The certificate profile name.
+pub fn azure_trusted_signing(folder: &str, filter: &str) -> Step {
+    action("azure/trusted-signing-action@v0.5.0")
+        .with("azure-tenant-id", "${{ secrets.AZURE_TENANT_ID }}")
+        .with("azure-client-id", "${{ secrets.AZURE_CLIENT_ID }}")
+        .with("azure-client-secret", "${{ secrets.AZURE_CLIENT_SECRET }}")
+        .with("endpoint", "${{ secrets.AZURE_SIGNING_ENDPOINT }}")
+        .with(
+            "trusted-signing-account-name",
+            "${{ secrets.AZURE_SIGNING_ACCOUNT }}",
+        )
+        .with(
+            "certificate-profile-name",
+            "${{ secrets.AZURE_CERTIFICATE_PROFILE }}",
+        )
+        .with("files-folder", folder)
+        .with("files-folder-filter", filter)
+        .with("file-digest", "SHA256")
+        .with("timestamp-rfc3161", "http://timestamp.acs.microsoft.com")
+        .with("timestamp-digest", "SHA256")
+        .into()
+}
+
+/// Sign `files` with `codesign`, using the hardened runtime so they can be
+/// notarized, on a macOS runner.
+///
+/// Uses these secrets:
+///
+/// - `MACOS_CERTIFICATE`: The base64 encoded `.p12` "Developer ID Application"
+///   certificate.
+/// - `MACOS_CERTIFICATE_PASSWORD`: The password for the certificate.
+/// - `MACOS_SIGNING_IDENTITY`: The identity to sign with, for example
+///   `Developer ID Application: My Company (TEAMID)`.
+pub fn codesign<'a>(files: impl IntoIterator<Item = &'a str>) -> Step {
+    let files = files.into_iter().map(shell_quote).collect::<Vec<_>>();
+    let files = files.join(" ");
+
+    shell_script([
+        "echo \"$MACOS_CERTIFICATE\" | base64 --decode > \"$RUNNER_TEMP/certificate.p12\"",
+        "security create-keychain -p \"$KEYCHAIN_PASSWORD\" build.keychain",
+        "security default-keychain -s build.keychain",
+        "security unlock-keychain -p \"$KEYCHAIN_PASSWORD\" build.keychain",
+        "security import \"$RUNNER_TEMP/certificate.p12\" -k build.keychain -P \
+         \"$MACOS_CERTIFICATE_PASSWORD\" -T /usr/bin/codesign",
+        "security set-key-partition-list -S apple-tool:,apple:,codesign: -s -k \
+         \"$KEYCHAIN_PASSWORD\" build.keychain",
+        &format!(
+            "codesign --force --options runtime --timestamp --sign \"$MACOS_SIGNING_IDENTITY\" \
+             {files}"
+        ),
+        "rm \"$RUNNER_TEMP/certificate.p12\"",
+    ])
+    .env("MACOS_CERTIFICATE", "${{ secrets.MACOS_CERTIFICATE }}")
+    .env(
+        "MACOS_CERTIFICATE_PASSWORD",
+        "${{ secrets.MACOS_CERTIFICATE_PASSWORD }}",
+    )
+    .env(
+        "MACOS_SIGNING_IDENTITY",
+        "${{ secrets.MACOS_SIGNING_IDENTITY }}",
+    )
+    .env(
+        "KEYCHAIN_PASSWORD",
+        "${{ github.run_id }}-${{ github.run_attempt }}",
+    )
+    .into()
+}
+
+/// Submit `file` to Apple's notary service, and wait for the result.
+///
+/// `file` should be a signed `.app`, `.dmg`, `.pkg` or binary. Uses these
+/// secrets:
+///
+/// - `APPLE_ID`: The Apple ID to submit with.
+/// - `APPLE_TEAM_ID`: The developer team ID.
+/// - `APPLE_APP_PASSWORD`: An app-specific password for the Apple ID.
+pub fn notarize(file: &str) -> Step {
+    let file = shell_quote(file);
+
+    shell_script([
+        &format!("ditto -c -k --keepParent {file} \"$RUNNER_TEMP/notarize.zip\""),
+        "xcrun notarytool submit \"$RUNNER_TEMP/notarize.zip\" --apple-id \"$APPLE_ID\" \
+         --team-id \"$APPLE_TEAM_ID\" --password \"$APPLE_APP_PASSWORD\" --wait",
+    ])
+    .env("APPLE_ID", "${{ secrets.APPLE_ID }}")
+    .env("APPLE_TEAM_ID", "${{ secrets.APPLE_TEAM_ID }}")
+    .env("APPLE_APP_PASSWORD", "${{ secrets.APPLE_APP_PASSWORD }}")
+    .into()
+}
+
+/// Sign and notarize `files` on macOS.
+///
+/// See [`codesign`] and [`notarize`].
+pub fn codesign_and_notarize<'a>(files: impl IntoIterator<Item = &'a str> + Clone) -> Step {
+    multi_step(
+        [codesign(files.clone())]
+            .into_iter()
+            .chain(files.into_iter().map(notarize)),
+    )
+}
+
+/// Quote `arg` as a literal PowerShell string
+fn powershell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "''"))
+}
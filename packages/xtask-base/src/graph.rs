@@ -0,0 +1,146 @@
+//! The workspace dependency graph, as Graphviz DOT or Mermaid
+use std::{collections::BTreeMap, fmt::Write, fs};
+
+use cargo_metadata::{DependencyKind, PackageId};
+use clap::ValueEnum;
+
+use crate::{WorkflowResult, Workspace};
+
+/// The format to render a dependency graph in
+#[derive(ValueEnum, Copy, Clone, Eq, PartialEq, Default, Debug)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// A Mermaid flowchart, which Github renders in markdown
+    Mermaid,
+}
+
+impl GraphFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::Mermaid => "mmd",
+        }
+    }
+}
+
+/// Render the dependency graph of the workspace, including every resolved
+/// package, or only workspace packages if `internal` is set.
+///
+/// Workspace packages are highlighted, and edges for dev only dependencies
+/// are dashed. The graph is written to `target/xtask/graph`, or printed if
+/// `stdout` is set.
+pub(crate) fn run(
+    workspace: &Workspace,
+    format: GraphFormat,
+    internal: bool,
+    stdout: bool,
+) -> WorkflowResult<()> {
+    let graph = render(workspace, format, internal)?;
+
+    if stdout {
+        print!("{graph}");
+    } else {
+        let path = workspace
+            .artifact_dir("graph")?
+            .join(format!("dependencies.{}", format.extension()));
+        fs::write(&path, graph)?;
+        println!("Dependency graph written to `{}`", path.display());
+    }
+
+    Ok(())
+}
+
+fn render(workspace: &Workspace, format: GraphFormat, internal: bool) -> WorkflowResult<String> {
+    let metadata = &workspace.0;
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or("Dependencies weren't resolved by `cargo metadata`")?;
+    let is_member = |id: &PackageId| metadata.workspace_members.contains(id);
+
+    // Nodes are sorted by label, so the output is deterministic
+    let mut nodes = Vec::<(&PackageId, String)>::new();
+
+    for package in &metadata.packages {
+        if is_member(&package.id) {
+            nodes.push((&package.id, package.name.clone()));
+        } else if !internal {
+            nodes.push((&package.id, format!("{} {}", package.name, package.version)));
+        }
+    }
+
+    nodes.sort_by(|(_, x), (_, y)| x.cmp(y));
+    let index: BTreeMap<_, _> = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, (id, _))| (*id, index))
+        .collect();
+
+    let mut edges = Vec::new();
+
+    for node in &resolve.nodes {
+        let Some(&from) = index.get(&node.id) else {
+            continue;
+        };
+
+        for dep in &node.deps {
+            let Some(&to) = index.get(&dep.pkg) else {
+                continue;
+            };
+            let dev_only = dep
+                .dep_kinds
+                .iter()
+                .all(|kind| kind.kind == DependencyKind::Development);
+            edges.push((from, to, dev_only));
+        }
+    }
+
+    edges.sort();
+    edges.dedup();
+
+    let mut graph = String::new();
+
+    match format {
+        GraphFormat::Dot => {
+            graph.push_str("digraph dependencies {\n");
+
+            for (id, label) in &nodes {
+                let style = if is_member(id) {
+                    " shape=box style=bold"
+                } else {
+                    ""
+                };
+                writeln!(graph, "    n{} [label={label:?}{style}]", index[id])?;
+            }
+
+            for (from, to, dev_only) in edges {
+                let style = if dev_only { " [style=dashed]" } else { "" };
+                writeln!(graph, "    n{from} -> n{to}{style}")?;
+            }
+
+            graph.push_str("}\n");
+        }
+        GraphFormat::Mermaid => {
+            graph.push_str("flowchart LR\n");
+
+            for (id, label) in &nodes {
+                let label = label.replace('"', "#quot;");
+
+                if is_member(id) {
+                    writeln!(graph, "    n{}[[\"{label}\"]]", index[id])?;
+                } else {
+                    writeln!(graph, "    n{}(\"{label}\")", index[id])?;
+                }
+            }
+
+            for (from, to, dev_only) in edges {
+                let arrow = if dev_only { "-.->" } else { "-->" };
+                writeln!(graph, "    n{from} {arrow} n{to}")?;
+            }
+        }
+    }
+
+    Ok(graph)
+}
@@ -0,0 +1,80 @@
+use std::{fs, path::Path};
+
+use toml_edit::{value, Array, DocumentMut, Item};
+
+use crate::{boilerplate_cargo_config, WorkflowResult};
+
+/// Scaffold an `xtask` package in the workspace at `dir`
+///
+/// This creates:
+///
+/// - `xtask/Cargo.toml` and `xtask/src/main.rs`, with `main` calling
+///   [`CommonCmds::run`](crate::CommonCmds::run).
+/// - `.cargo/config.toml`, with an alias for `cargo xtask`.
+///
+/// `xtask` is added to the workspace members, and `cargo xtask codegen` is
+/// run to generate the CI workflow.
+pub fn init_project(dir: impl AsRef<Path>) -> WorkflowResult<()> {
+    let dir = dir.as_ref();
+    let xtask_dir = dir.join("xtask");
+    let cargo_config = dir.join(".cargo").join("config.toml");
+
+    for path in [&xtask_dir, &cargo_config] {
+        if path.exists() {
+            return Err(format!("`{}` already exists", path.display()).into());
+        }
+    }
+
+    fs::create_dir_all(xtask_dir.join("src"))?;
+    fs::write(
+        xtask_dir.join("Cargo.toml"),
+        include_str!("boilerplate/xtask/manifest.toml"),
+    )?;
+    fs::write(
+        xtask_dir.join("src").join("main.rs"),
+        include_str!("boilerplate/xtask/main.rs"),
+    )?;
+    fs::create_dir_all(dir.join(".cargo"))?;
+    fs::write(cargo_config, boilerplate_cargo_config())?;
+    add_workspace_member(dir)?;
+
+    duct::cmd("cargo", ["xtask", "codegen"]).dir(dir).run()?;
+    println!("Created `{}`", xtask_dir.display());
+    Ok(())
+}
+
+fn add_workspace_member(dir: &Path) -> WorkflowResult<()> {
+    let manifest_path = dir.join("Cargo.toml");
+    let mut manifest: DocumentMut = fs::read_to_string(&manifest_path)?.parse()?;
+    let workspace = manifest
+        .get_mut("workspace")
+        .and_then(Item::as_table_like_mut)
+        .ok_or_else(|| {
+            format!(
+                "`{}` doesn't define a workspace. Add a `[workspace]` section with `xtask` as a \
+                 member.",
+                manifest_path.display()
+            )
+        })?;
+    let members = workspace
+        .entry("members")
+        .or_insert(value(Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| {
+            format!(
+                "`workspace.members` isn't an array in `{}`",
+                manifest_path.display()
+            )
+        })?;
+
+    if !members
+        .iter()
+        .any(|member| member.as_str() == Some("xtask"))
+    {
+        members.push("xtask");
+    }
+
+    fs::write(&manifest_path, manifest.to_string())?;
+
+    Ok(())
+}
@@ -0,0 +1,338 @@
+//! License, `NOTICE` and SPDX header generation
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
+use cargo_metadata::{DependencyKind, Metadata, MetadataCommand, Package, PackageId};
+use chrono::{Datelike, Utc};
+use itertools::Itertools;
+use serde_json::json;
+
+use crate::{template::TemplateEngine, update_file, CodegenTarget, WorkflowResult};
+
+const SPDX_PREFIX: &str = "// SPDX-License-Identifier:";
+
+/// The file names that usually hold license texts, in upper case
+const LICENSE_FILE_PREFIXES: [&str; 4] = ["LICENSE", "LICENCE", "COPYING", "UNLICENSE"];
+
+/// Generate legal files for the workspace
+///
+/// By default, dual Apache 2 and MIT licenses are generated. Use
+/// [`LegalFiles::notice`] and [`LegalFiles::spdx_headers`] to generate a
+/// `NOTICE` file and SPDX headers as well.
+///
+/// ```no_run
+/// # use xtask_base::{LegalFiles, WorkflowResult};
+/// # fn codegen(check: bool) -> WorkflowResult<()> {
+/// LegalFiles::new(2022)
+///     .holder("Example Corp")
+///     .notice()
+///     .spdx_headers("MIT OR Apache-2.0")
+///     .generate(check)
+/// # }
+/// ```
+pub struct LegalFiles {
+    start_year: i32,
+    holder: String,
+    notice: bool,
+    spdx_expression: Option<String>,
+}
+
+impl LegalFiles {
+    /// Legal files with the copyright starting from `start_year`
+    pub fn new(start_year: i32) -> Self {
+        Self {
+            start_year,
+            holder: "Simon Bourne".to_string(),
+            notice: false,
+            spdx_expression: None,
+        }
+    }
+
+    /// Set the copyright holder.
+    pub fn holder(mut self, name: impl Into<String>) -> Self {
+        self.holder = name.into();
+        self
+    }
+
+    /// Generate a `NOTICE` file, with attributions for all third party
+    /// packages, from the `license` field in their manifest.
+    pub fn notice(mut self) -> Self {
+        self.notice = true;
+        self
+    }
+
+    /// Start each `.rs` file in the workspace packages with an SPDX license
+    /// identifier, like `// SPDX-License-Identifier: MIT OR Apache-2.0`.
+    ///
+    /// An existing identifier on the first line is replaced.
+    pub fn spdx_headers(mut self, expression: impl Into<String>) -> Self {
+        self.spdx_expression = Some(expression.into());
+        self
+    }
+
+    /// Generate the files in the workspace root, or check they're up to date
+    /// if `check` is set.
+    pub fn generate(&self, check: bool) -> WorkflowResult<()> {
+        self.generate_with(&TemplateEngine::default(), check)
+    }
+
+    /// Generate the files, rendering the licenses with `engine`.
+    ///
+    /// The bundled license templates work with either engine.
+    pub fn generate_with(&self, engine: &TemplateEngine, check: bool) -> WorkflowResult<()> {
+        if !CodegenTarget::Licenses.is_selected() {
+            return Ok(());
+        }
+
+        self.license(
+            engine,
+            include_str!("boilerplate/LICENSE-APACHE"),
+            "LICENSE-APACHE",
+            check,
+        )?;
+        self.license(
+            engine,
+            include_str!("boilerplate/LICENSE-MIT"),
+            "LICENSE-MIT",
+            check,
+        )?;
+
+        if self.notice || self.spdx_expression.is_some() {
+            let metadata = MetadataCommand::new().exec()?;
+
+            if self.notice {
+                let third_party = metadata
+                    .packages
+                    .iter()
+                    .filter(|package| !metadata.workspace_members.contains(&package.id));
+                update_file("NOTICE", &self.notice_contents(third_party), check)?;
+            }
+
+            if let Some(expression) = &self.spdx_expression {
+                for package in metadata.workspace_packages() {
+                    let package_dir = package
+                        .manifest_path
+                        .parent()
+                        .ok_or("Manifest should have a parent directory")?;
+                    spdx_headers(
+                        package_dir.as_std_path(),
+                        metadata.target_directory.as_std_path(),
+                        expression,
+                        check,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn license(
+        &self,
+        engine: &TemplateEngine,
+        template: &str,
+        filename: &str,
+        check: bool,
+    ) -> WorkflowResult<()> {
+        let data = json!({
+            "copyright_range": self.copyright_range(),
+            "holder": self.holder
+        });
+
+        update_file(filename, &engine.render_text(template, &data)?, check)
+    }
+
+    fn copyright_range(&self) -> String {
+        let end_year = Utc::now().year();
+
+        if self.start_year == end_year {
+            format!("{}", self.start_year)
+        } else {
+            format!("{}-{}", self.start_year, end_year)
+        }
+    }
+
+    fn notice_contents<'a>(&self, third_party: impl Iterator<Item = &'a Package>) -> String {
+        let attributions = third_party
+            .sorted_by(|x, y| (&x.name, &x.version).cmp(&(&y.name, &y.version)))
+            .map(|package| {
+                let license = match (&package.license, &package.license_file) {
+                    (Some(license), _) => license.clone(),
+                    (None, Some(file)) => format!("see `{}` in the package", file),
+                    (None, None) => "unknown license".to_string(),
+                };
+                let mut line = format!("- {} {} ({license})", package.name, package.version);
+
+                if let Some(repository) = &package.repository {
+                    line.push_str(&format!(", {repository}"));
+                }
+
+                line
+            })
+            .join("\n");
+
+        format!(
+            "Copyright {} {}\n\nThis software includes the following third party packages, \
+             under the licenses shown:\n\n{attributions}\n",
+            self.copyright_range(),
+            self.holder
+        )
+    }
+}
+
+/// Add or update the SPDX header in each `.rs` file under `dir`.
+fn spdx_headers(
+    dir: &Path,
+    target_dir: &Path,
+    expression: &str,
+    check: bool,
+) -> WorkflowResult<()> {
+    let header = format!("{SPDX_PREFIX} {expression}");
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.starts_with(target_dir) || path.file_name().is_some_and(|name| name == ".git") {
+            continue;
+        }
+
+        if path.is_dir() {
+            spdx_headers(&path, target_dir, expression, check)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let source = fs::read_to_string(&path)?;
+            let body = match source.split_once('\n') {
+                Some((first, rest)) if first.starts_with(SPDX_PREFIX) => rest,
+                _ if source.starts_with(SPDX_PREFIX) => "",
+                _ => &source,
+            };
+
+            update_file(&path, &format!("{header}\n{body}"), check)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generate `THIRD_PARTY_LICENSES.md` in the workspace root, with the license
+/// texts of every package a workspace package depends on.
+///
+/// Dev dependencies aren't included, as they're not distributed with
+/// binaries. License texts are read from the `license-file` field in the
+/// package's manifest, and from any `LICENSE*`, `LICENCE*`, `COPYING*` or
+/// `UNLICENSE*` files in the package root. Run it during codegen, so
+/// `cargo xtask codegen --check` will fail in CI if it's out of date.
+pub fn generate_third_party_licenses(check: bool) -> WorkflowResult<()> {
+    if !CodegenTarget::Licenses.is_selected() {
+        return Ok(());
+    }
+
+    let metadata = MetadataCommand::new().exec()?;
+    let mut contents = "# Third Party Licenses\n\nThis software includes the following third \
+                        party packages.\n"
+        .to_string();
+
+    for package in distributed_dependencies(&metadata)? {
+        contents.push_str(&format!(
+            "\n## {} {}\n\nLicense: {}\n",
+            package.name,
+            package.version,
+            package.license.as_deref().unwrap_or("unknown")
+        ));
+
+        if let Some(repository) = &package.repository {
+            contents.push_str(&format!("\nRepository: <{repository}>\n"));
+        }
+
+        let texts = license_texts(package)?;
+
+        if texts.is_empty() {
+            contents.push_str("\nNo license text was found in the package.\n");
+        }
+
+        for (file_name, text) in texts {
+            contents.push_str(&format!(
+                "\n### `{file_name}`\n\n```text\n{}\n```\n",
+                text.trim_end()
+            ));
+        }
+    }
+
+    update_file("THIRD_PARTY_LICENSES.md", &contents, check)
+}
+
+/// Non workspace packages that workspace packages depend on, excluding dev
+/// dependencies, sorted by name and version.
+fn distributed_dependencies(metadata: &Metadata) -> WorkflowResult<Vec<&Package>> {
+    let resolve = metadata
+        .resolve
+        .as_ref()
+        .ok_or("Dependencies weren't resolved by `cargo metadata`")?;
+    let nodes: HashMap<_, _> = resolve.nodes.iter().map(|node| (&node.id, node)).collect();
+    let mut reachable = BTreeSet::<&PackageId>::new();
+    let mut pending: Vec<_> = metadata.workspace_members.iter().collect();
+
+    while let Some(id) = pending.pop() {
+        let Some(node) = nodes.get(id) else {
+            continue;
+        };
+
+        for dep in &node.deps {
+            let distributed = dep
+                .dep_kinds
+                .iter()
+                .any(|kind| kind.kind != DependencyKind::Development);
+
+            if distributed && reachable.insert(&dep.pkg) {
+                pending.push(&dep.pkg);
+            }
+        }
+    }
+
+    Ok(metadata
+        .packages
+        .iter()
+        .filter(|package| {
+            reachable.contains(&package.id) && !metadata.workspace_members.contains(&package.id)
+        })
+        .sorted_by(|x, y| (&x.name, &x.version).cmp(&(&y.name, &y.version)))
+        .collect())
+}
+
+/// The license files for `package`, as `(file name, contents)` pairs
+fn license_texts(package: &Package) -> WorkflowResult<Vec<(String, String)>> {
+    let package_dir = package
+        .manifest_path
+        .parent()
+        .ok_or("Manifest should have a parent directory")?;
+    let mut files = BTreeSet::new();
+
+    if let Some(license_file) = &package.license_file {
+        files.insert(package_dir.join(license_file));
+    }
+
+    for entry in package_dir.read_dir_utf8()? {
+        let entry = entry?;
+        let name = entry.file_name().to_uppercase();
+
+        if entry.file_type()?.is_file()
+            && LICENSE_FILE_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        {
+            files.insert(entry.into_path());
+        }
+    }
+
+    files
+        .into_iter()
+        .filter(|file| file.is_file())
+        .map(|file| {
+            let text = fs::read_to_string(&file)?;
+            let file_name = file.file_name().unwrap_or_default().to_string();
+            Ok((file_name, text))
+        })
+        .collect()
+}
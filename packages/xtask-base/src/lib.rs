@@ -1,48 +1,358 @@
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     env::{current_dir, set_current_dir},
     error,
-    ffi::OsString,
+    ffi::OsStr,
     fs,
-    path::Path,
+    path::{Path, PathBuf},
     process,
 };
 
-use cargo_metadata::{Metadata, MetadataCommand};
-use chrono::{Datelike, Utc};
+use cargo_metadata::{DependencyKind, Metadata, MetadataCommand, Package};
+use chrono::Utc;
 use ci::CI;
 use clap::{CommandFactory, Parser};
 use clap_complete::Shell;
-use duct::IntoExecutablePath;
-use github::actions::Platform;
+use completions::Destination;
+use config::XtaskConfig;
+use coverage::{CoverageReport, CoverageTool};
+use github::actions::{Cmd, Platform, RunOptions};
+use graph::GraphFormat;
 use itertools::Itertools;
+use report::OutputFormat;
 use scopeguard::defer;
-use serde_json::json;
+use serde_json::{json, Value};
+use style::Color;
+use template::TemplateEngine;
 
-mod template;
+mod bench;
+mod binstall;
+mod bump;
+mod clean;
+mod clippy;
+mod codegen;
+mod completions;
+mod docs;
+mod docs_rs;
+mod doctest;
+mod duplicates;
+mod expand;
+mod fix;
+mod geiger;
+mod graph;
+mod init;
+mod legal;
+mod metadata_cache;
+mod nextest;
+mod problem_matchers;
+mod progress;
+mod publish_check;
+mod release_notes;
+mod required_checks;
+mod required_secrets;
+mod rustfmt;
+mod script;
+mod style;
+mod validate;
+mod version_check;
+mod watch;
 
 pub mod ci;
+pub mod config;
+pub mod coverage;
 pub mod github;
+pub mod report;
+pub mod summary;
+pub mod template;
+
+pub use binstall::generate_binstall_metadata;
+pub use clippy::{generate_clippy_config, ClippyConfig};
+pub use codegen::CodegenTarget;
+pub use init::init_project;
+pub use legal::{generate_third_party_licenses, LegalFiles};
+pub use nextest::{Partition, TestTimings};
+pub use rustfmt::RustfmtConfig;
+pub use script::GeneratedScript;
 
 pub type WorkflowResult<T> = Result<T, Box<dyn error::Error>>;
 
 #[derive(Parser)]
 pub enum CommonCmds {
     /// Run CI checks
-    Ci,
+    Ci {
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
+        /// Write the output of each step to a log file in
+        /// `target/xtask/logs/<timestamp>`
+        #[clap(long)]
+        log: bool,
+        /// Fix what can be fixed automatically, instead of checking: generate
+        /// files, apply clippy suggestions, sort dependencies and format Rust
+        /// and TOML files
+        #[clap(long, conflicts_with_all = ["output", "log"])]
+        fix: bool,
+        /// Only run the steps in this profile
+        #[clap(long, conflicts_with = "fix")]
+        profile: Option<String>,
+        /// Run the jobs for this platform, as if it were the current one, to
+        /// debug jobs for other platforms. For example, `ubuntu-latest`.
+        #[clap(
+            long,
+            visible_alias = "platform",
+            value_name = "PLATFORM",
+            conflicts_with = "fix"
+        )]
+        emulate_platform: Option<Platform>,
+    },
+    /// Build the docs and serve them on `localhost`
+    Docs {
+        /// Open the docs in a browser
+        #[clap(long)]
+        open: bool,
+        /// Rebuild the docs whenever a file changes
+        #[clap(long)]
+        watch: bool,
+        /// The port to serve the docs on
+        #[clap(long, default_value_t = 8000)]
+        port: u16,
+    },
+    /// Build the docs as docs.rs would, to check they'll build when published
+    ///
+    /// This uses nightly, with `--cfg docsrs` and the options from
+    /// `[package.metadata.docs.rs]`.
+    DocsRs {
+        /// Only build the docs for this package. Defaults to all publishable
+        /// packages.
+        #[clap(long)]
+        package: Option<String>,
+    },
+    /// Check the publishable packages with `cargo publish --dry-run`, to find
+    /// packaging problems before a release
+    PublishCheck {
+        /// Only check this package. Defaults to all publishable packages.
+        #[clap(long)]
+        package: Option<String>,
+        /// Allow uncommitted changes
+        #[clap(long)]
+        allow_dirty: bool,
+    },
+    /// Remove all build artifacts, including for extra workspaces
+    CleanAll {
+        /// Also remove unused items from the cargo cache, using `cargo-cache`
+        #[clap(long)]
+        prune_cache: bool,
+    },
+    /// Remove stale artifacts from the target directories, including for extra
+    /// workspaces, using `cargo sweep`
+    Sweep {
+        /// Remove artifacts that haven't been used for this many days
+        #[clap(long, default_value_t = 7)]
+        days: u32,
+    },
+    /// Update the pinned Rust and tool versions to the latest releases, and
+    /// run codegen
+    ///
+    /// Versions are written to `[workspace.metadata.xtask.versions]`, which
+    /// is read by
+    /// [`WorkspaceVersions`](ci::WorkspaceVersions).
+    BumpToolchains,
+    /// Scaffold an `xtask` package in another workspace
+    Init {
+        /// The root directory of the workspace
+        dir: PathBuf,
+    },
+    /// Run CI checks whenever a file changes
+    Watch {
+        /// Only run this job. Can be specified multiple times.
+        #[clap(long)]
+        job: Vec<String>,
+    },
     /// Generate derived files. Existing content will be overritten.
     Codegen {
         /// Check the files wouldn't change. Don't actually generate them.
         #[clap(long)]
         check: bool,
+        /// Only generate this group of files
+        #[clap(long, value_enum)]
+        only: Option<CodegenTarget>,
+        /// Run codegen twice, without writing any files, and fail if the output
+        /// differs. The second run has a different time zone and locale.
+        #[clap(long, conflicts_with = "check")]
+        verify_deterministic: bool,
+        #[clap(long, value_enum, default_value_t)]
+        output: OutputFormat,
     },
     /// Generate shell completions
-    ShellCompletion { shell: Shell },
+    ShellCompletion {
+        /// The shell to generate completions for. Defaults to the current
+        /// user's shell.
+        shell: Option<Shell>,
+        /// Install the completions into the standard per-user location for the
+        /// shell, instead of the target directory
+        #[clap(long, conflicts_with = "print")]
+        install: bool,
+        /// Print the completions to `stdout`, instead of writing them to
+        /// `target/xtask/completions`
+        #[clap(long)]
+        print: bool,
+    },
     /// Format all code
     Fmt,
     /// Check all dependencies are used
-    Udeps,
+    Udeps {
+        /// Only check this package. Can be given more than once.
+        #[clap(long, short, value_name = "SPEC")]
+        package: Vec<String>,
+        /// Check all the packages in the workspace
+        #[clap(long, conflicts_with = "package")]
+        workspace: bool,
+        /// Exclude this package from a workspace check. Can be given more than
+        /// once.
+        #[clap(long, value_name = "SPEC", conflicts_with = "package")]
+        exclude: Vec<String>,
+    },
+    /// Sort dependency tables in every `Cargo.toml`
+    Sort {
+        /// Check the dependencies are sorted. Don't actually sort them.
+        #[clap(long)]
+        check: bool,
+    },
     /// Show expanded macros
-    MacroExpand { package: String },
+    MacroExpand {
+        package: String,
+        /// Write the expansion to a file, instead of showing it. With
+        /// `--diff`, the diff is written instead.
+        #[clap(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Show how the expansion has changed since a git revision
+        #[clap(long, value_name = "GIT_REF")]
+        diff: Option<String>,
+    },
+    /// Test the Rust code blocks in `README.md` and `docs/**/*.md`
+    TestReadme,
+    /// Check the links in `README.md` and the generated docs
+    CheckLinks,
+    /// Check dependencies for security advisories, using `cargo-audit`
+    Audit,
+    /// Checks to run before a release
+    ///
+    /// Without any check options, all the checks are run.
+    Release {
+        /// Check the workspace version, the latest version in `CHANGELOG.md`
+        /// and the release tag all agree
+        #[clap(long)]
+        verify: bool,
+        /// The release tag. Defaults to `$GITHUB_REF_NAME` when a workflow is
+        /// triggered by a tag, or the tag at `HEAD`.
+        #[clap(long)]
+        tag: Option<String>,
+    },
+    /// Render release notes from a template
+    ///
+    /// `{{ tag }}` and `{{ version }}` (the tag without a leading `v`) are
+    /// available to the template, as well as the helpers described in
+    /// `build_readme`.
+    ReleaseNotes {
+        /// The template to render
+        #[clap(long, default_value = release_notes::TEMPLATE)]
+        template: PathBuf,
+        /// The release tag. Defaults to `$GITHUB_REF_NAME`.
+        #[clap(long)]
+        tag: Option<String>,
+        /// Write the notes to this file, instead of `stdout`
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// List outdated direct dependencies, using `cargo-outdated`
+    Outdated,
+    /// Report on the unsafe code in each package and its dependencies, using
+    /// `cargo-geiger`
+    ///
+    /// JSON reports and a markdown summary are written to
+    /// `target/xtask/geiger`.
+    Geiger,
+    /// Check there's only one semver compatible version of each dependency
+    ///
+    /// Crates in the `allowed-duplicates` config are ignored.
+    Duplicates,
+    /// Render the workspace dependency graph
+    ///
+    /// The graph is written to `target/xtask/graph`, unless `--stdout` is
+    /// given.
+    Graph {
+        #[clap(long, value_enum, default_value_t)]
+        format: GraphFormat,
+        /// Only include workspace packages
+        #[clap(long)]
+        internal: bool,
+        /// Print the graph instead of writing it to a file
+        #[clap(long)]
+        stdout: bool,
+    },
+    /// Run the tests with `cargo-nextest`, retrying failures
+    ///
+    /// Flaky and failing tests are listed in
+    /// `target/xtask/nextest/flaky-tests.md`, and test durations are written
+    /// to `target/xtask/nextest/timings.json`. Nextest's JUnit report is
+    /// written to `target/xtask/nextest/junit.xml`.
+    Nextest {
+        /// The number of times to retry a failed test
+        #[clap(long, default_value_t = 0)]
+        retries: u32,
+        /// Only run one shard of the tests, like `2/3`
+        #[clap(long)]
+        partition: Option<Partition>,
+        /// Timings from previous runs, used to balance the shards
+        #[clap(long, requires = "partition")]
+        timings: Vec<PathBuf>,
+    },
+    /// Check the workspace config, and the templates it uses
+    ///
+    /// Every problem is listed, with its location in the config file.
+    ValidateConfig,
+    /// Run the tests and collect snapshot changes, using `cargo-insta`
+    Snapshots {
+        /// Review pending snapshots interactively
+        #[clap(long)]
+        review: bool,
+        /// Accept all pending snapshots
+        #[clap(long, conflicts_with = "review")]
+        accept: bool,
+    },
+    /// Set the required status checks for a protected branch, from
+    /// `.github/required-checks.json`
+    ///
+    /// This uses the GitHub CLI, which must be logged in with admin access to
+    /// the repository. See `CI::required_checks`.
+    RequiredChecks {
+        /// The protected branch
+        #[clap(long, default_value = "main")]
+        branch: String,
+    },
+    /// Run Criterion benchmarks
+    Bench {
+        /// Save the results as a named baseline
+        #[clap(long, value_name = "NAME")]
+        save_baseline: Option<String>,
+        /// Compare the results against a named baseline
+        #[clap(long, value_name = "NAME", conflicts_with = "save_baseline")]
+        compare: Option<String>,
+    },
+    /// Run tests with code coverage
+    Coverage {
+        /// The tool used to measure coverage
+        #[clap(long, value_enum, default_value_t)]
+        tool: CoverageTool,
+        /// Generate an HTML report
+        #[clap(long, conflicts_with = "lcov")]
+        html: bool,
+        /// Generate an LCOV report
+        #[clap(long)]
+        lcov: bool,
+        /// Open the HTML report in a browser
+        #[clap(long, requires = "html")]
+        open: bool,
+    },
 }
 
 impl CommonCmds {
@@ -51,7 +361,41 @@ impl CommonCmds {
         in_workspace(|workspace| Self::parse().sub_command::<Self>(workspace, [], ci, codegen));
     }
 
+    /// Like [`Self::run`], but build the CI workflow from the [`Workspace`].
+    ///
+    /// This is useful with
+    /// [`WorkspaceVersions`](ci::WorkspaceVersions).
+    pub fn run_with(
+        ci: impl FnOnce(&Workspace) -> WorkflowResult<CI>,
+        codegen: impl FnOnce(bool) -> WorkflowResult<()>,
+    ) {
+        in_workspace(|workspace| {
+            Self::parse().sub_command::<Self>(workspace, [], ci(workspace)?, codegen)
+        });
+    }
+
+    /// Run with everything configured by the workspace config.
+    ///
+    /// The CI workflow comes from [`CI::from_config`], and codegen builds the
+    /// README and license files if they're enabled. See [`config`] for
+    /// details. The config is validated first, like `cargo xtask
+    /// validate-config`.
+    pub fn run_from_config() {
+        in_workspace(|workspace| {
+            validate::validate_config(workspace)?;
+            let config = workspace.config()?;
+            let ci = CI::from_config(workspace)?;
+
+            Self::parse()
+                .sub_command::<Self>(workspace, [], ci, |check| config_codegen(&config, check))
+        });
+    }
+
     /// Run the subcommand for `self`
+    ///
+    /// Commands that work on extra workspaces, like `fmt`, use
+    /// `extra_workspace_dirs` and the ones recorded in `ci`. See
+    /// [`CI::extra_workspace`].
     pub fn sub_command<'a, T: CommandFactory>(
         &self,
         workspace: &Workspace,
@@ -59,46 +403,325 @@ impl CommonCmds {
         ci: CI,
         codegen: impl FnOnce(bool) -> WorkflowResult<()>,
     ) -> WorkflowResult<()> {
+        let extra_workspaces = ci.extra_workspaces().to_vec();
+        let mut dirs = extra_workspaces
+            .iter()
+            .map(|workspace| Path::new(workspace.dir()))
+            .collect::<Vec<_>>();
+
+        for dir in extra_workspace_dirs.into_iter().map(Path::new) {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        let extra_workspace_dirs = dirs;
+
         match self {
-            CommonCmds::Ci => ci.execute(),
-            CommonCmds::Codegen { check } => {
-                if Platform::current() == Platform::WindowsLatest {
-                    println!("Codegen disabled on windows");
-                    Ok(())
-                } else {
+            CommonCmds::Ci { fix: true, .. } => fix::fix_all(&extra_workspace_dirs, ci, codegen),
+            CommonCmds::Ci {
+                output,
+                log,
+                profile,
+                emulate_platform,
+                ..
+            } => {
+                let ci = match profile {
+                    Some(profile) => ci.select_profile(profile)?,
+                    None => ci,
+                };
+
+                if let Some(platform) = emulate_platform {
+                    eprintln!(
+                        "Emulating {}. Steps that depend on the real platform may fail.",
+                        platform.as_str()
+                    );
+                    platform.emulate();
+                }
+
+                problem_matchers::register(workspace)?;
+                let log_dir = log.then(|| workspace.log_dir()).transpose()?;
+                let log_dir = log_dir.as_deref();
+
+                match output {
+                    OutputFormat::Text if log_dir.is_none() => ci.execute(),
+                    OutputFormat::Text => report::print_ci_summary(&ci.execute_with_logs(log_dir)),
+                    OutputFormat::Json => report::print_ci(&ci.execute_with_logs(log_dir)),
+                }
+            }
+            CommonCmds::Docs { open, watch, port } => docs::serve(workspace, *port, *open, *watch),
+            CommonCmds::DocsRs { package } => docs_rs::check(workspace, package.as_deref()),
+            CommonCmds::PublishCheck {
+                package,
+                allow_dirty,
+            } => publish_check::check(workspace, package.as_deref(), *allow_dirty),
+            CommonCmds::CleanAll { prune_cache } => {
+                clean::clean_all(workspace, extra_workspace_dirs, *prune_cache)
+            }
+            CommonCmds::Sweep { days } => clean::sweep(workspace, extra_workspace_dirs, *days),
+            CommonCmds::BumpToolchains => bump::bump_toolchains(workspace),
+            CommonCmds::Init { dir } => init_project(dir),
+            CommonCmds::Watch { job } => watch::watch(
+                workspace.0.workspace_root.as_std_path(),
+                workspace.target_dir(),
+                &ci,
+                job,
+            ),
+            CommonCmds::Codegen {
+                verify_deterministic: true,
+                ..
+            } => codegen::verify_deterministic(workspace),
+            CommonCmds::Codegen {
+                check,
+                only,
+                output,
+                ..
+            } => {
+                let run_codegen = || -> WorkflowResult<()> {
                     generate_cargo_config(*check)?;
-                    ci.write(*check)?;
-                    codegen(*check)
+
+                    if CodegenTarget::Workflows.is_selected() {
+                        ci.write(*check)?;
+                    }
+
+                    codegen(*check)?;
+
+                    if CodegenTarget::Workflows.is_selected() {
+                        github::actions::prune_stale_workflows(*check)?;
+                    }
+
+                    Ok(())
+                };
+                let result = codegen::with_only(*only, || {
+                    if *check {
+                        report::check_all(run_codegen)
+                    } else {
+                        run_codegen()
+                    }
+                })
+                .and_then(|()| codegen::save_captured());
+
+                match output {
+                    OutputFormat::Text => result,
+                    OutputFormat::Json => report::print_codegen(*check, result),
                 }
             }
-            CommonCmds::ShellCompletion { shell } => {
-                let target_dir = workspace.target_dir();
-                clap_complete::generate_to(*shell, &mut T::command(), "./cargo-xtask", target_dir)?;
-                println!("Completions file generated in `{}`", target_dir.display());
-                Ok(())
+            CommonCmds::ShellCompletion {
+                shell,
+                install,
+                print,
+            } => {
+                let completions_dir;
+                let destination = if *install {
+                    Destination::Install
+                } else if *print {
+                    Destination::Print
+                } else {
+                    completions_dir = workspace.artifact_dir("completions")?;
+                    Destination::Dir(&completions_dir)
+                };
+
+                completions::generate(*shell, T::command(), destination)
             }
             CommonCmds::Fmt => fmt(extra_workspace_dirs),
-            CommonCmds::Udeps => cmd("cargo", ["+nightly", "udeps", "--all-targets"]),
-            CommonCmds::MacroExpand { package } => {
-                duct::cmd("cargo", ["expand", "--color=always", "--package", package])
-                    .pipe(duct::cmd("less", ["-r"]))
-                    .run()?;
-                Ok(())
+            CommonCmds::Udeps {
+                package,
+                workspace,
+                exclude,
+            } => udeps(package, *workspace, exclude, extra_workspace_dirs),
+            CommonCmds::Sort { check } => sort(extra_workspace_dirs, *check),
+            CommonCmds::MacroExpand { package, out, diff } => {
+                expand::run(workspace, package, out.as_deref(), diff.as_deref())
+            }
+            CommonCmds::TestReadme => doctest::test_markdown(workspace),
+            CommonCmds::CheckLinks => check_links(workspace),
+            // Verifying the version is the only check, so it's run whether or
+            // not `--verify` is given.
+            CommonCmds::Release { verify: _, tag } => {
+                version_check::verify(workspace, tag.as_deref())
             }
+            CommonCmds::ReleaseNotes {
+                template,
+                tag,
+                output,
+            } => release_notes::render(template, tag.as_deref(), output.as_deref()),
+            CommonCmds::RequiredChecks { branch } => required_checks::apply(branch),
+            CommonCmds::Audit => {
+                install_on_demand("cargo-audit", &["cargo", "audit"])?;
+                cmd("cargo", ["audit", "--deny", "warnings"])
+            }
+            CommonCmds::Geiger => geiger::run(workspace),
+            CommonCmds::Duplicates => duplicates::check(workspace),
+            CommonCmds::Graph {
+                format,
+                internal,
+                stdout,
+            } => graph::run(workspace, *format, *internal, *stdout),
+            CommonCmds::Nextest {
+                retries,
+                partition,
+                timings,
+            } => nextest::run(workspace, *retries, *partition, timings),
+            CommonCmds::ValidateConfig => validate::validate_config(workspace),
+            CommonCmds::Snapshots { review, accept } => {
+                install_on_demand("cargo-insta", &["cargo", "insta"])?;
+                let mut args = vec!["insta", "test", "--workspace"];
+
+                if *review {
+                    args.push("--review");
+                } else if *accept {
+                    args.push("--accept");
+                }
+
+                cmd("cargo", args)
+            }
+            CommonCmds::Outdated => {
+                install_on_demand("cargo-outdated", &["cargo", "outdated"])?;
+                cmd(
+                    "cargo",
+                    [
+                        "outdated",
+                        "--workspace",
+                        "--root-deps-only",
+                        "--exit-code",
+                        "1",
+                    ],
+                )
+            }
+            CommonCmds::Bench {
+                save_baseline,
+                compare,
+            } => bench::run(
+                workspace.target_dir(),
+                save_baseline.as_deref(),
+                compare.as_deref(),
+            ),
+            CommonCmds::Coverage {
+                tool,
+                html,
+                lcov,
+                open,
+            } => {
+                let report = if *html {
+                    CoverageReport::Html
+                } else if *lcov {
+                    CoverageReport::Lcov
+                } else {
+                    CoverageReport::Summary
+                };
+
+                coverage::run(*tool, &workspace.artifact_dir("coverage")?, report, *open)
+            }
+        }
+    }
+}
+
+fn config_codegen(config: &XtaskConfig, check: bool) -> WorkflowResult<()> {
+    if config.readme {
+        build_readme(".", check)?;
+    }
+
+    if let Some(start_year) = config.license_start_year {
+        generate_rustfmt_config(check)?;
+        let mut legal_files = LegalFiles::new(start_year);
+
+        if let Some(holder) = &config.license_holder {
+            legal_files = legal_files.holder(holder);
         }
+
+        if config.notice {
+            legal_files = legal_files.notice();
+        }
+
+        if let Some(expression) = &config.spdx_headers {
+            legal_files = legal_files.spdx_headers(expression);
+        }
+
+        legal_files.generate(check)?;
     }
+
+    if config.binstall {
+        generate_binstall_metadata(check)?;
+    }
+
+    if config.third_party_licenses {
+        generate_third_party_licenses(check)?;
+    }
+
+    Ok(())
 }
 
-fn fmt<'a>(extra_workspace_dirs: impl IntoIterator<Item = &'a str>) -> WorkflowResult<()> {
+fn fmt<'a>(extra_workspace_dirs: impl IntoIterator<Item = &'a Path>) -> WorkflowResult<()> {
     for dir in extra_workspace_dirs {
-        duct::cmd("cargo", ["+nightly", "fmt", "--all"])
-            .dir(dir)
-            .run()?;
+        cmd_in_dir(Some(dir), "cargo", ["+nightly", "fmt", "--all"])?;
     }
 
     cmd("cargo", ["+nightly", "fmt", "--all"])
 }
 
+/// Run `cargo udeps` on the main workspace, and on each extra workspace unless
+/// specific packages are selected.
+fn udeps<'a>(
+    packages: &[String],
+    workspace: bool,
+    exclude: &[String],
+    extra_workspace_dirs: impl IntoIterator<Item = &'a Path>,
+) -> WorkflowResult<()> {
+    let mut args = vec!["+nightly", "udeps", "--all-targets"];
+
+    if packages.is_empty() {
+        for dir in extra_workspace_dirs {
+            cmd_in_dir(Some(dir), "cargo", &args)?;
+        }
+    }
+
+    for package in packages {
+        args.extend(["--package", package]);
+    }
+
+    // `--exclude` only works with `--workspace`
+    if workspace || !exclude.is_empty() {
+        args.push("--workspace");
+    }
+
+    for package in exclude {
+        args.extend(["--exclude", package]);
+    }
+
+    cmd("cargo", args)
+}
+
+fn check_links(workspace: &Workspace) -> WorkflowResult<()> {
+    install_on_demand("lychee", &["lychee"])?;
+    cmd("cargo", ["doc", "--no-deps"])?;
+    duct::cmd!(
+        "lychee",
+        "--no-progress",
+        "README.md",
+        workspace.target_dir().join("doc")
+    )
+    .run()?;
+    Ok(())
+}
+
+fn sort<'a>(
+    extra_workspace_dirs: impl IntoIterator<Item = &'a Path>,
+    check: bool,
+) -> WorkflowResult<()> {
+    install_on_demand("cargo-sort", &["cargo", "sort"])?;
+    let args = || {
+        ["sort", "--workspace"]
+            .into_iter()
+            .chain(check.then_some("--check"))
+    };
+
+    for dir in extra_workspace_dirs {
+        cmd_in_dir(Some(dir), "cargo", args())?;
+    }
+
+    cmd("cargo", args())
+}
+
 /// Metadata about the cargo workspace
 pub struct Workspace(Metadata);
 
@@ -109,12 +732,303 @@ impl Workspace {
     pub fn target_dir(&self) -> &Path {
         self.0.target_directory.as_std_path()
     }
+
+    /// Create a directory for the output of an `xtask` command, in
+    /// `target/xtask/{subdir}`, and return it.
+    ///
+    /// All outputs go under `target/xtask`, so they have a consistent layout
+    /// and don't clash with cargo's own directories. For example, logs are in
+    /// `target/xtask/logs`, and coverage reports are in
+    /// `target/xtask/coverage`.
+    pub fn artifact_dir(&self, subdir: impl AsRef<Path>) -> WorkflowResult<PathBuf> {
+        let dir = self.target_dir().join("xtask").join(subdir);
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Create a new, timestamped directory for logs, in
+    /// `target/xtask/logs`.
+    pub fn log_dir(&self) -> WorkflowResult<PathBuf> {
+        let timestamp = Utc::now().format("%Y-%m-%dT%H-%M-%S");
+        self.artifact_dir(Path::new("logs").join(timestamp.to_string()))
+    }
+
+    /// Path filters for workflow triggers, matching any file that could
+    /// affect the workspace.
+    ///
+    /// This includes the directory of each workspace package, and the
+    /// workspace level config files. It's computed from `cargo metadata`, so it
+    /// stays correct as packages are moved. For an extra workspace, use
+    /// something like `format!("{dir}/**")`.
+    pub fn path_filters(&self) -> Vec<String> {
+        self.filters_for(self.0.workspace_packages())
+    }
+
+    /// Path filters for workflow triggers, matching any file that could
+    /// affect `package`.
+    ///
+    /// Like [`Self::path_filters`], but only the directories of `package`, and
+    /// the workspace packages it depends on directly or indirectly, are
+    /// included.
+    pub fn package_path_filters(&self, package: &str) -> WorkflowResult<Vec<String>> {
+        let package = self
+            .0
+            .workspace_packages()
+            .into_iter()
+            .find(|p| p.name == package)
+            .ok_or_else(|| format!("Package \"{package}\" not found in workspace"))?;
+
+        Ok(self.filters_for(self.with_workspace_dependencies(package)))
+    }
+
+    /// The name, directory and path filters for each workspace package
+    pub(crate) fn package_dirs_and_filters(&self) -> Vec<(String, String, Vec<String>)> {
+        self.0
+            .workspace_packages()
+            .into_iter()
+            .map(|package| {
+                let dir = self.package_dir(package).unwrap_or_default();
+                let dir = if dir.is_empty() { ".".to_owned() } else { dir };
+                let filters = self.filters_for(self.with_workspace_dependencies(package));
+                (package.name.clone(), dir, filters)
+            })
+            .collect()
+    }
+
+    fn filters_for<'a>(&self, packages: impl IntoIterator<Item = &'a Package>) -> Vec<String> {
+        let mut filters = [
+            "Cargo.toml",
+            "Cargo.lock",
+            ".cargo/**",
+            "rust-toolchain",
+            "rust-toolchain.toml",
+            config::CONFIG_FILE,
+            ".github/workflows/**",
+        ]
+        .map(str::to_owned)
+        .to_vec();
+
+        for package in packages {
+            let filter = match self.package_dir(package) {
+                Some(dir) if dir.is_empty() => "**".to_owned(),
+                Some(dir) => format!("{dir}/**"),
+                None => continue,
+            };
+
+            if !filters.contains(&filter) {
+                filters.push(filter);
+            }
+        }
+
+        filters
+    }
+
+    /// The directory of `package`, relative to the workspace root, with `/`
+    /// separators. It's empty for the root package.
+    fn package_dir(&self, package: &Package) -> Option<String> {
+        let dir = package.manifest_path.parent()?;
+        let dir = dir.strip_prefix(&self.0.workspace_root).ok()?;
+        Some(dir.as_str().replace('\\', "/"))
+    }
+
+    /// `package`, and all the workspace packages it depends on, directly or
+    /// indirectly
+    fn with_workspace_dependencies<'a>(&'a self, package: &'a Package) -> Vec<&'a Package> {
+        let members = self.0.workspace_packages();
+        let mut found = vec![package];
+        let mut index = 0;
+
+        while let Some(current) = found.get(index) {
+            let dependencies = current
+                .dependencies
+                .iter()
+                .filter(|dep| dep.path.is_some())
+                .filter_map(|dep| members.iter().find(|member| member.name == dep.name))
+                .copied()
+                .collect::<Vec<_>>();
+
+            for dependency in dependencies {
+                if !found.iter().any(|p| p.id == dependency.id) {
+                    found.push(dependency);
+                }
+            }
+
+            index += 1;
+        }
+
+        found
+    }
+
+    /// The minimum supported Rust version, which is the highest
+    /// `rust-version` of any workspace package
+    ///
+    /// The patch version is left out if it's 0, like in `Cargo.toml`.
+    pub fn msrv(&self) -> Option<String> {
+        let rust_version = self
+            .0
+            .workspace_packages()
+            .into_iter()
+            .filter_map(|package| package.rust_version.as_ref())
+            .max()?;
+
+        Some(if rust_version.patch == 0 {
+            format!("{}.{}", rust_version.major, rust_version.minor)
+        } else {
+            rust_version.to_string()
+        })
+    }
+
+    /// The workspace configuration, from `xtask.toml` or
+    /// `[workspace.metadata.xtask]`
+    pub fn config(&self) -> WorkflowResult<XtaskConfig> {
+        XtaskConfig::for_workspace(
+            self.0.workspace_root.as_std_path(),
+            &self.0.workspace_metadata,
+        )
+    }
+
+    /// The `[package.metadata.xtask]` configuration for `package`
+    pub fn package_config(&self, package: &str) -> WorkflowResult<XtaskConfig> {
+        let package_info = self
+            .0
+            .workspace_packages()
+            .into_iter()
+            .find(|p| p.name == package)
+            .ok_or_else(|| format!("Package \"{package}\" not found in workspace"))?;
+
+        XtaskConfig::from_metadata(&package_info.metadata, "package")
+    }
+
+    /// The names of all the publishable workspace packages, ordered so that
+    /// each package comes after all it's workspace dependencies.
+    ///
+    /// Dev dependencies are ignored, as they don't affect publishing.
+    pub fn publish_order(&self) -> WorkflowResult<Vec<&str>> {
+        let order = dependency_order(&self.dependency_graph(false))?;
+        let publishable = self
+            .0
+            .workspace_packages()
+            .into_iter()
+            .filter(|package| is_publishable(package))
+            .map(|package| package.name.as_str())
+            .collect::<HashSet<_>>();
+
+        Ok(order
+            .into_iter()
+            .filter(|package| publishable.contains(package))
+            .collect())
+    }
+
+    /// The names of all workspace packages that depend on `package`, directly
+    /// or indirectly, including through dev dependencies.
+    pub fn dependents(&self, package: &str) -> Vec<&str> {
+        transitive_dependents(&self.dependency_graph(true), package)
+    }
+
+    /// A map from each workspace package name to the names of it's workspace
+    /// dependencies, from the `cargo metadata` resolve graph.
+    fn dependency_graph(&self, include_dev: bool) -> BTreeMap<&str, BTreeSet<&str>> {
+        let names = self
+            .0
+            .workspace_packages()
+            .into_iter()
+            .map(|package| (&package.id, package.name.as_str()))
+            .collect::<HashMap<_, _>>();
+        let mut graph = names
+            .values()
+            .map(|&name| (name, BTreeSet::new()))
+            .collect::<BTreeMap<_, _>>();
+
+        for node in self.0.resolve.iter().flat_map(|resolve| &resolve.nodes) {
+            let Some(&name) = names.get(&node.id) else {
+                continue;
+            };
+
+            let dependencies = node
+                .deps
+                .iter()
+                .filter(|dep| {
+                    include_dev
+                        || dep
+                            .dep_kinds
+                            .iter()
+                            .any(|info| info.kind != DependencyKind::Development)
+                })
+                .filter_map(|dep| names.get(&dep.pkg).copied());
+
+            graph.entry(name).or_default().extend(dependencies);
+        }
+
+        graph
+    }
+}
+
+/// The packages in `dependencies`, ordered so that each package comes after
+/// all it's dependencies. Packages with no order between them are sorted by
+/// name.
+fn dependency_order<'a>(
+    dependencies: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+) -> WorkflowResult<Vec<&'a str>> {
+    let mut remaining = dependencies.keys().copied().collect::<BTreeSet<_>>();
+    let mut order = Vec::new();
+
+    while !remaining.is_empty() {
+        let ready = remaining
+            .iter()
+            .copied()
+            .filter(|package| {
+                dependencies[package]
+                    .iter()
+                    .all(|dependency| !remaining.contains(dependency))
+            })
+            .collect::<Vec<_>>();
+
+        if ready.is_empty() {
+            return Err(format!(
+                "Dependency cycle between packages: {}",
+                remaining.iter().join(", ")
+            )
+            .into());
+        }
+
+        for package in ready {
+            remaining.remove(package);
+            order.push(package);
+        }
+    }
+
+    Ok(order)
+}
+
+/// The packages in `dependencies` that depend on `package`, directly or
+/// indirectly, sorted by name.
+fn transitive_dependents<'a>(
+    dependencies: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    package: &str,
+) -> Vec<&'a str> {
+    let mut dependents = BTreeSet::new();
+    let mut pending = vec![package];
+
+    while let Some(dependency) = pending.pop() {
+        for (&dependent, dependent_deps) in dependencies {
+            if dependent_deps.contains(dependency) && dependents.insert(dependent) {
+                pending.push(dependent);
+            }
+        }
+    }
+
+    dependents.remove(package);
+    dependents.into_iter().collect()
 }
 
 /// Run a function, passing it a [Workspace]
 ///
 /// If an error is returned, a human friendly version is output, and the process
 /// exits with code 1
+///
+/// Set `XTASK_CACHE_METADATA=1` to cache the output of `cargo metadata` in the
+/// target directory. The cache is invalidated when `Cargo.lock` or any
+/// `Cargo.toml` changes.
 pub fn in_workspace(f: impl FnOnce(&Workspace) -> WorkflowResult<()>) {
     try_in_workspace(f).unwrap_or_else(|e| {
         eprintln!("{}", e);
@@ -123,13 +1037,24 @@ pub fn in_workspace(f: impl FnOnce(&Workspace) -> WorkflowResult<()>) {
 }
 
 fn try_in_workspace(f: impl FnOnce(&Workspace) -> WorkflowResult<()>) -> WorkflowResult<()> {
-    let metadata = MetadataCommand::new().exec()?;
+    let metadata = if metadata_cache::is_enabled() {
+        metadata_cache::metadata()?
+    } else {
+        MetadataCommand::new().exec()?
+    };
 
     let dir = current_dir()?;
     set_current_dir(&metadata.workspace_root)?;
     defer! {set_current_dir(dir).expect("Failed to reset current directory to {dir}")}
 
-    f(&Workspace(metadata))
+    let workspace = Workspace(metadata);
+
+    // An invalid config is reported by whatever uses it.
+    if let Ok(config) = workspace.config() {
+        config.env.apply();
+    }
+
+    f(&workspace)
 }
 
 /// Build `README.md` from `README.tmpl.md`
@@ -137,19 +1062,97 @@ fn try_in_workspace(f: impl FnOnce(&Workspace) -> WorkflowResult<()>) -> Workflo
 /// The template is a Handlebars template with helpers:
 ///
 /// - `{{ include "my-file.txt" }}` will include the contents of `my-file.txt`
+/// - `{{ include "my-file.rs" anchor="usage" }}` will include the lines of
+///   `my-file.rs` between comments containing `ANCHOR: usage` and `ANCHOR_END:
+///   usage`, with common indentation removed.
+/// - `{{ include "my-file.rs" lines="10-20" }}` will include lines 10 to 20
+///   (inclusive) of `my-file.rs`. `lines` can also be of the form `10`, `10-`
+///   or `-20`.
 /// - `{{ shell "ls -l" }}` will run `ls -l` and include the contents of it's
-///   `stdout`. The system shell is used to run the command.
+///   `stdout`. The system shell is used to run the command. It's an error if
+///   the command writes anything to `stderr`, unless `allow_stderr=true` is
+///   specified. `trim=true` will remove leading and trailing whitespace from
+///   the output. Set `XTASK_CACHE_TEMPLATES=1` and list the files and
+///   directories the output depends on, like `inputs="src Cargo.lock"`, to
+///   cache it in the target directory. Commands without `inputs` aren't cached.
+/// - `{{ env "MY_VAR" }}` will include the value of the environment variable
+///   `MY_VAR`.
+/// - `{{ rustc_version }}` will include the output of `rustc --version`.
+/// - `{{ date "%Y" }}` will include the current UTC date, formatted with a
+///   [chrono format string](https://docs.rs/chrono/latest/chrono/format/strftime/index.html).
+/// - `{{ crate "my-crate" "version" }}` will include a field from the
+///   `Cargo.toml` of the workspace package `my-crate`. Supported fields are
+///   `name`, `version`, `description`, `rust_version`, `edition`, `license`,
+///   `repository`, `homepage` and `documentation`.
+/// - `{{{ badges "my-crate" }}}` will include a line of badges for `my-crate`:
+///   crates.io and docs.rs if it's publishable, the status of the `tests`
+///   workflow if the repository is on Github, and the license and MSRV. Use
+///   `workflow="ci"` to show a different workflow.
+/// - `{{ docs "my-crate/src/lib.rs" }}` will include the crate level (`//!`)
+///   docs from `my-crate/src/lib.rs`, with intra-doc links converted to
+///   `docs.rs` links. Use triple braces to avoid HTML escaping.
+/// - `{{{ toc }}}` will include a table of contents, linking to all level 2 and
+///   3 headings in the rendered document. Use `{{{ toc max_level=4 }}}` to
+///   include deeper headings.
+/// - `{{{ changelog "1.2.0" }}}` will include the section for version `1.2.0`
+///   from `CHANGELOG.md`. Use `file="NEWS.md"` to read a different file.
+/// - `{{{ contributors }}}` will include a Markdown list of commit authors
+///   since the previous tag. Use `since="v1.1.0"` to pick the tag.
+/// - `{{{ artifacts "dist" }}}` will include a Markdown table of the files in
+///   `dist`, with their sizes. These three are intended for release notes, and
+///   use triple braces to avoid HTML escaping.
 pub fn build_readme(dir: &str, check: bool) -> WorkflowResult<()> {
+    build_readme_with(dir, &TemplateEngine::default(), check)
+}
+
+/// Build `README.md` from `README.tmpl.md`, using a specific template engine
+///
+/// See [`template::registry_with`] to add your own helpers and partials, or
+/// [`TemplateEngine::minijinja`] to use MiniJinja templates.
+pub fn build_readme_with(dir: &str, engine: &TemplateEngine, check: bool) -> WorkflowResult<()> {
+    if !CodegenTarget::Readme.is_selected() {
+        return Ok(());
+    }
+
     let dir = Path::new(dir);
-    let template = fs::read_to_string(dir.join("README.tmpl.md"))?;
 
-    update_file(
+    render_template_file_with(
+        engine,
+        dir.join("README.tmpl.md"),
         dir.join("README.md"),
-        &template::registry().render_template(&template, &"{}")?,
+        &json!({}),
         check,
     )
 }
 
+/// Render the Handlebars template `src` to `dest`
+///
+/// `data` is available to the template, along with all the helpers described
+/// in [`build_readme`]. If `check` is true, `dest` isn't written, but an error
+/// is returned if it would change.
+pub fn render_template_file(
+    src: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    data: &Value,
+    check: bool,
+) -> WorkflowResult<()> {
+    render_template_file_with(&TemplateEngine::default(), src, dest, data, check)
+}
+
+/// Render the template `src` to `dest`, using a specific template engine
+///
+/// See [`render_template_file`] and [`TemplateEngine`].
+pub fn render_template_file_with(
+    engine: &TemplateEngine,
+    src: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    data: &Value,
+    check: bool,
+) -> WorkflowResult<()> {
+    let template = fs::read_to_string(src)?;
+    update_file(dest, &engine.render(&template, data)?, check)
+}
+
 /// Generate Rustfmt and Cargo configs, and dual Apache 2 and MIT licenses
 ///
 /// The follwing files are generated in the workspace root:
@@ -159,77 +1162,72 @@ pub fn build_readme(dir: &str, check: bool) -> WorkflowResult<()> {
 /// - `LICENSE-APACHE`
 /// - `LICENSE-MIT`
 pub fn generate_open_source_files(start_year: i32, check: bool) -> WorkflowResult<()> {
-    generate_rustfmt_config(check)?;
-    generate_license_apache(start_year, check)?;
-    generate_license_mit(start_year, check)?;
+    generate_open_source_files_with(&TemplateEngine::default(), start_year, check)
+}
 
-    Ok(())
+/// Generate open source files, using a specific template engine
+///
+/// See [`generate_open_source_files`]. The bundled license templates work
+/// with either engine.
+pub fn generate_open_source_files_with(
+    engine: &TemplateEngine,
+    start_year: i32,
+    check: bool,
+) -> WorkflowResult<()> {
+    generate_rustfmt_config(check)?;
+    LegalFiles::new(start_year).generate_with(engine, check)
 }
 
 /// Generate `rustfmt.toml` in the workspace root
+///
+/// Use [`RustfmtConfig`] to customize it.
 pub fn generate_rustfmt_config(check: bool) -> WorkflowResult<()> {
-    update_file(
-        "rustfmt.toml",
-        include_str!("boilerplate/rustfmt.toml"),
-        check,
-    )?;
-
-    Ok(())
+    RustfmtConfig::default().generate(check)
 }
 
 /// Generate `.cargo/config.toml` in the workspace root
 ///
 /// It contains a single alias for `xtask`
 pub fn generate_cargo_config(check: bool) -> WorkflowResult<()> {
+    if !codegen::is_selected(None) {
+        return Ok(());
+    }
+
     if !check {
         fs::create_dir_all(".cargo")?;
     }
 
-    update_file(
-        ".cargo/config.toml",
-        include_str!("boilerplate/.cargo/config.toml"),
-        check,
-    )?;
+    update_file(".cargo/config.toml", boilerplate_cargo_config(), check)?;
 
     Ok(())
 }
 
+fn boilerplate_cargo_config() -> &'static str {
+    include_str!("boilerplate/.cargo/config.toml")
+}
+
 pub fn generate_license_apache(start_year: i32, check: bool) -> WorkflowResult<()> {
-    generate_license(
+    if !CodegenTarget::Licenses.is_selected() {
+        return Ok(());
+    }
+
+    LegalFiles::new(start_year).license(
+        &TemplateEngine::default(),
         include_str!("boilerplate/LICENSE-APACHE"),
         "LICENSE-APACHE",
-        start_year,
         check,
     )
 }
 
 pub fn generate_license_mit(start_year: i32, check: bool) -> WorkflowResult<()> {
-    generate_license(
+    if !CodegenTarget::Licenses.is_selected() {
+        return Ok(());
+    }
+
+    LegalFiles::new(start_year).license(
+        &TemplateEngine::default(),
         include_str!("boilerplate/LICENSE-MIT"),
         "LICENSE-MIT",
-        start_year,
-        check,
-    )
-}
-
-fn generate_license(
-    template: &str,
-    filename: &str,
-    start_year: i32,
-    check: bool,
-) -> WorkflowResult<()> {
-    let end_year = Utc::now().year();
-
-    let copyright_range = if start_year == end_year {
-        format!("{}", start_year)
-    } else {
-        format!("{}-{}", start_year, end_year)
-    };
-
-    update_file(
-        filename,
-        &template::registry()
-            .render_template(template, &json!({ "copyright_range": copyright_range }))?,
         check,
     )
 }
@@ -237,17 +1235,36 @@ fn generate_license(
 fn update_file(path: impl AsRef<Path>, contents: &str, check: bool) -> WorkflowResult<()> {
     let path = path.as_ref();
 
+    if codegen::capture(path, contents) {
+        // It's still recorded, so it's not pruned as a stale workflow
+        report::record_file(path, false);
+        return Ok(());
+    }
+
+    // Ignore windows line endings
+    let changed = fs::read_to_string(path).map_or(true, |existing_contents| {
+        existing_contents.lines().join("\n") != contents.lines().join("\n")
+    });
+    report::record_file(path, changed);
+
     if check {
-        // Ignore windows line endings
-        let existing_contents = fs::read_to_string(path)?.lines().join("\n");
+        if changed {
+            let existing_contents = fs::read_to_string(path).unwrap_or_default();
 
-        if existing_contents != contents.lines().join("\n") {
-            return Err(format!(
-                "Differences found in file \"{}\". New contents are:\n{}\n",
+            if summary::is_github_actions() {
+                summary::add(format!(
+                    "### `{}` is out of date\n\n```diff\n{}\n```\n",
+                    path.display(),
+                    style::plain_diff(&existing_contents, contents)
+                ))?;
+            }
+
+            return report::check_failure(format!(
+                "{} in file \"{}\":\n{}\n",
+                style::paint("Differences found", Color::Red),
                 path.display(),
-                contents
-            )
-            .into());
+                style::diff(&existing_contents, contents)
+            ));
         }
     } else {
         if let Some(parent) = path.parent() {
@@ -260,12 +1277,106 @@ fn update_file(path: impl AsRef<Path>, contents: &str, check: bool) -> WorkflowR
     Ok(())
 }
 
-fn cmd<T, U>(program: T, args: U) -> WorkflowResult<()>
-where
-    T: IntoExecutablePath,
-    U: IntoIterator,
-    U::Item: Into<OsString>,
-{
-    duct::cmd(program, args).run()?;
+/// Can `package` be published? It can't if `publish = false`.
+pub(crate) fn is_publishable(package: &Package) -> bool {
+    !matches!(&package.publish, Some(registries) if registries.is_empty())
+}
+
+/// Install `crate_name` with `cargo install`, unless `program` is already
+/// available.
+///
+/// `program` is the command, and any arguments, used to check the tool is
+/// installed. `--version` is appended to it.
+fn install_on_demand(crate_name: &str, program: &[&str]) -> WorkflowResult<()> {
+    let (program, args) = program.split_first().expect("Program should not be empty");
+    // Running a program that isn't installed is an error, rather than a failed
+    // exit status
+    let installed = duct::cmd(*program, args.iter().chain(&["--version"]))
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .is_ok_and(|output| output.status.success());
+
+    if !installed {
+        cmd("cargo", ["install", "--locked", crate_name])?;
+    }
+
     Ok(())
 }
+
+fn cmd(program: &str, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> WorkflowResult<()> {
+    cmd_in_dir(None::<&Path>, program, args)
+}
+
+fn cmd_in_dir(
+    dir: Option<impl Into<PathBuf>>,
+    program: &str,
+    args: impl IntoIterator<Item = impl AsRef<OsStr>>,
+) -> WorkflowResult<()> {
+    Cmd::new(program)
+        .args(args)
+        .run_in_dir(dir, &[], RunOptions::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::{dependency_order, transitive_dependents};
+
+    /// A graph from each package to it's dependencies
+    fn graph<'a>(dependencies: &[(&'a str, &[&'a str])]) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+        dependencies
+            .iter()
+            .map(|(package, deps)| (*package, deps.iter().copied().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn order() {
+        let dependencies = graph(&[
+            ("app", &["macros", "core"]),
+            ("core", &[]),
+            ("macros", &["core"]),
+            ("cli", &["app"]),
+        ]);
+
+        assert_eq!(
+            dependency_order(&dependencies).unwrap(),
+            ["core", "macros", "app", "cli"]
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let dependencies = graph(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"]), ("d", &[])]);
+        let error = dependency_order(&dependencies).unwrap_err().to_string();
+
+        assert_eq!(error, "Dependency cycle between packages: a, b, c");
+    }
+
+    #[test]
+    fn dependents() {
+        let dependencies = graph(&[
+            ("app", &["macros"]),
+            ("core", &[]),
+            ("macros", &["core"]),
+            ("cli", &["app"]),
+            ("other", &[]),
+        ]);
+
+        assert_eq!(
+            transitive_dependents(&dependencies, "core"),
+            ["app", "cli", "macros"]
+        );
+        assert!(transitive_dependents(&dependencies, "cli").is_empty());
+    }
+
+    #[test]
+    fn dependents_in_cycle() {
+        let dependencies = graph(&[("a", &["b"]), ("b", &["a"])]);
+
+        assert_eq!(transitive_dependents(&dependencies, "a"), ["b"]);
+    }
+}
@@ -0,0 +1,184 @@
+//! Cache `cargo metadata` output in the target directory
+//!
+//! The cache is keyed on the contents of `Cargo.lock`, every `Cargo.toml` in
+//! the workspace, the manifests of path dependencies outside the workspace,
+//! and the cargo config files, so it's invalidated automatically when they
+//! change.
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use cargo_metadata::{Metadata, MetadataCommand};
+use serde::{Deserialize, Serialize};
+
+use crate::WorkflowResult;
+
+/// Set this environment variable to cache `cargo metadata` output.
+pub const CACHE_ENV_VAR: &str = "XTASK_CACHE_METADATA";
+
+/// The last metadata from [`shared_metadata`], with its workspace root and key
+static SHARED: Mutex<Option<(PathBuf, u64, Metadata)>> = Mutex::new(None);
+
+#[derive(Deserialize)]
+struct CachedMetadata {
+    key: u64,
+    metadata: Metadata,
+}
+
+#[derive(Serialize)]
+struct CachedMetadataRef<'a> {
+    key: u64,
+    metadata: &'a Metadata,
+}
+
+pub fn is_enabled() -> bool {
+    env::var_os(CACHE_ENV_VAR).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Get the workspace metadata from the cache, or run `cargo metadata` and
+/// update the cache.
+pub fn metadata() -> WorkflowResult<Metadata> {
+    let root = workspace_root()?;
+    let cache_file = target_dir(&root).join("xtask").join("metadata.json");
+
+    if let Ok(contents) = fs::read_to_string(&cache_file) {
+        if let Ok(cached) = serde_json::from_str::<CachedMetadata>(&contents) {
+            if cached.key == cache_key(&root, &cached.metadata)? {
+                return Ok(cached.metadata);
+            }
+        }
+    }
+
+    let metadata = MetadataCommand::new().exec()?;
+    let key = cache_key(&root, &metadata)?;
+
+    if let Some(parent) = cache_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(
+        &cache_file,
+        serde_json::to_string(&CachedMetadataRef {
+            key,
+            metadata: &metadata,
+        })?,
+    )?;
+
+    Ok(metadata)
+}
+
+/// Get the workspace metadata for code that needs it many times, like
+/// template helpers.
+///
+/// It's kept in memory, and only read again if the key for the cache file
+/// changes. The cache file is also used if it's enabled.
+pub(crate) fn shared_metadata() -> WorkflowResult<Metadata> {
+    let root = workspace_root()?;
+    let mut shared = SHARED.lock().expect("Shared metadata lock poisoned");
+
+    if let Some((shared_root, key, metadata)) = &*shared {
+        if *shared_root == root && *key == cache_key(&root, metadata)? {
+            return Ok(metadata.clone());
+        }
+    }
+
+    let metadata = if is_enabled() {
+        self::metadata()?
+    } else {
+        MetadataCommand::new().exec()?
+    };
+    let key = cache_key(&root, &metadata)?;
+    *shared = Some((root, key, metadata.clone()));
+
+    Ok(metadata)
+}
+
+/// Find the workspace root, without running `cargo metadata`.
+pub(crate) fn workspace_root() -> WorkflowResult<PathBuf> {
+    let manifest = duct::cmd(
+        "cargo",
+        ["locate-project", "--workspace", "--message-format", "plain"],
+    )
+    .read()?;
+    let manifest = PathBuf::from(manifest.trim());
+
+    Ok(manifest
+        .parent()
+        .ok_or("Workspace manifest has no parent directory")?
+        .to_path_buf())
+}
+
+/// The target directory for the workspace at `root`, without running `cargo
+/// metadata`.
+pub(crate) fn target_dir(root: &Path) -> PathBuf {
+    env::var_os("CARGO_TARGET_DIR").map_or_else(|| root.join("target"), PathBuf::from)
+}
+
+/// The key for `metadata`, which is used to find path dependencies outside
+/// the workspace. Adding one changes a workspace manifest, so it's safe to
+/// use the cached metadata to find them.
+fn cache_key(root: &Path, metadata: &Metadata) -> WorkflowResult<u64> {
+    let mut files = vec![root.join("Cargo.lock")];
+    manifests(root, &mut files)?;
+    files.extend(config_files(root));
+    files.extend(
+        metadata
+            .packages
+            .iter()
+            .filter(|package| package.source.is_none())
+            .map(|package| package.manifest_path.clone().into_std_path_buf()),
+    );
+    files.sort();
+    files.dedup();
+
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+
+    for file in files {
+        file.hash(&mut hasher);
+        fs::read(&file).ok().hash(&mut hasher);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// The cargo config files that could apply to the workspace at `root`: those
+/// in `root` or any of its ancestors, and in the cargo home directory.
+fn config_files(root: &Path) -> Vec<PathBuf> {
+    let cargo_home = env::var_os("CARGO_HOME").map(PathBuf::from).or_else(|| {
+        env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| Path::new(&home).join(".cargo"))
+    });
+
+    root.ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(cargo_home)
+        .flat_map(|dir| [dir.join("config.toml"), dir.join("config")])
+        .collect()
+}
+
+/// Find all the `Cargo.toml` files under `dir`, ignoring hidden directories
+/// and `target`.
+fn manifests(dir: &Path, files: &mut Vec<PathBuf>) -> WorkflowResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if entry.file_type()?.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                manifests(&path, files)?;
+            }
+        } else if name == "Cargo.toml" {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
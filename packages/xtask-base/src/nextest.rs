@@ -0,0 +1,483 @@
+//! Run tests with `cargo-nextest`, retrying failures, reporting flaky tests,
+//! and splitting tests into shards with similar durations
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::BufReader,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use quick_junit::{Report, TestCase, TestCaseStatus};
+use toml::Table;
+
+use crate::{cmd, install_on_demand, summary, WorkflowResult, Workspace};
+
+/// The nextest profile that `cargo xtask nextest` defines in its tool config.
+/// It inherits from the workspace's default profile.
+const PROFILE: &str = "xtask";
+
+/// Run the workspace tests with `cargo nextest`, retrying each failed test up
+/// to `retries` times.
+///
+/// Tests that pass on a retry are flaky, and tests that fail every attempt are
+/// persistent failures. Both are listed in
+/// `target/xtask/nextest/flaky-tests.md`, which is also added to the job
+/// summary under Github Actions, along with the test counts. Only persistent
+/// failures fail the run. The results are read from nextest's JUnit report,
+/// which is written to `target/xtask/nextest/junit.xml`.
+///
+/// The duration of each test is written to `target/xtask/nextest/timings.json`.
+/// With `partition`, only one shard of the tests is run, balanced using the
+/// timings in `timings_files`. See [`TestTimings`].
+pub(crate) fn run(
+    workspace: &Workspace,
+    retries: u32,
+    partition: Option<Partition>,
+    timings_files: &[PathBuf],
+) -> WorkflowResult<()> {
+    install_on_demand("cargo-nextest", &["cargo", "nextest"])?;
+    let output_dir = workspace.artifact_dir("nextest")?;
+    let junit_path = output_dir.join("junit.xml");
+    let mut profile = Table::new();
+
+    if let Some(partition) = partition {
+        let mut timings = TestTimings::default();
+
+        for file in timings_files {
+            if file.exists() {
+                timings.merge(TestTimings::load(file)?);
+            } else {
+                eprintln!("Timings file `{}` not found", file.display());
+            }
+        }
+
+        let tests = list_tests()?;
+        let shard = timings.shard(&tests, partition);
+
+        if shard.is_empty() {
+            println!("No tests in partition {partition}");
+            return Ok(());
+        }
+
+        eprintln!(
+            "Running {} of {} tests in partition {partition}",
+            shard.len(),
+            tests.len()
+        );
+        // The filter can be too long for the command line, so it goes in the
+        // config.
+        profile.insert("default-filter".into(), filter_expression(&shard).into());
+    }
+
+    // Don't mistake the report from a previous run for this one.
+    if junit_path.exists() {
+        fs::remove_file(&junit_path)?;
+    }
+
+    let junit = Table::from_iter([
+        (
+            "path".to_string(),
+            junit_path.to_string_lossy().as_ref().into(),
+        ),
+        ("report-skipped".to_string(), "ignored".into()),
+    ]);
+    profile.insert("junit".into(), junit.into());
+    let config = Table::from_iter([(
+        "profile".to_string(),
+        Table::from_iter([(PROFILE.to_string(), profile.into())]).into(),
+    )]);
+    let config_path = output_dir.join("nextest.toml");
+    fs::write(&config_path, toml::to_string(&config)?)?;
+
+    let tool_config = format!("xtask:{}", config_path.display());
+    let retries = retries.to_string();
+    let result = cmd(
+        "cargo",
+        [
+            "nextest",
+            "run",
+            "--workspace",
+            "--no-fail-fast",
+            "--tool-config-file",
+            &tool_config,
+            "--profile",
+            PROFILE,
+            "--retries",
+            &retries,
+        ],
+    );
+
+    // Nextest doesn't write a report if it fails before running any tests.
+    if !junit_path.exists() {
+        return result;
+    }
+
+    let report = Report::deserialize(BufReader::new(fs::File::open(&junit_path)?))?;
+    let results = TestResults::parse(&report);
+    TestTimings::parse(&report).save(output_dir.join("timings.json"))?;
+    let flaky_report = results.report();
+    fs::write(output_dir.join("flaky-tests.md"), &flaky_report)?;
+
+    if summary::is_github_actions() {
+        summary::add(results.counts_report())?;
+
+        if !results.is_empty() {
+            summary::add(&flaky_report)?;
+        }
+    }
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if results.failed.is_empty() => Err(e),
+        Err(_) => Err(format!("Tests failed after retrying: {}", results.failed.join(", ")).into()),
+    }
+}
+
+/// The tests nextest reported as flaky or failed
+#[derive(Default, Debug)]
+struct TestResults {
+    passed: usize,
+    skipped: usize,
+    flaky: Vec<String>,
+    failed: Vec<String>,
+}
+
+impl TestResults {
+    /// Read the results from nextest's JUnit report.
+    ///
+    /// Flaky tests pass with earlier failed runs, and are counted as passed.
+    /// Only ignored tests are reported as skipped.
+    fn parse(report: &Report) -> Self {
+        let mut results = Self::default();
+
+        for (test, case) in test_cases(report) {
+            match &case.status {
+                TestCaseStatus::Success { flaky_runs } => {
+                    results.passed += 1;
+
+                    if !flaky_runs.is_empty() {
+                        results.flaky.push(test);
+                    }
+                }
+                TestCaseStatus::NonSuccess { .. } => results.failed.push(test),
+                TestCaseStatus::Skipped { .. } => results.skipped += 1,
+            }
+        }
+
+        results
+    }
+
+    fn is_empty(&self) -> bool {
+        self.flaky.is_empty() && self.failed.is_empty()
+    }
+
+    fn counts_report(&self) -> String {
+        format!(
+            "### Tests\n\n{}",
+            summary::table(
+                ["Passed", "Failed", "Flaky", "Skipped"],
+                [[
+                    self.passed,
+                    self.failed.len(),
+                    self.flaky.len(),
+                    self.skipped
+                ]]
+            )
+        )
+    }
+
+    fn report(&self) -> String {
+        let mut report = "### Flaky tests\n\n".to_string();
+
+        if self.is_empty() {
+            report.push_str("All tests passed on the first attempt.\n");
+            return report;
+        }
+
+        let rows = self
+            .failed
+            .iter()
+            .map(|test| [test.as_str(), "Failed every attempt"])
+            .chain(
+                self.flaky
+                    .iter()
+                    .map(|test| [test.as_str(), "Passed on a retry"]),
+            );
+        report.push_str(&summary::table(["Test", "Result"], rows));
+
+        report
+    }
+}
+
+/// Each test case in `report`, identified like `cargo nextest list` does, as
+/// `binary-id test-name`.
+fn test_cases(report: &Report) -> impl Iterator<Item = (String, &TestCase)> {
+    report
+        .test_suites
+        .iter()
+        .flat_map(|suite| &suite.test_cases)
+        .map(|case| {
+            let name = case.name.as_str();
+            let test = match &case.classname {
+                Some(binary_id) => format!("{} {name}", binary_id.as_str()),
+                None => name.to_string(),
+            };
+
+            (test, case)
+        })
+}
+
+/// A shard of the tests to run, like `2/3` for the second of three shards
+#[derive(Copy, Clone, Debug)]
+pub struct Partition {
+    /// The shard number, starting from 1
+    pub index: usize,
+    /// The total number of shards
+    pub count: usize,
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Invalid partition \"{s}\". Expected a shard like `2/3`.");
+        let (index, count) = s.split_once('/').ok_or_else(error)?;
+        let index: usize = index.parse().map_err(|_| error())?;
+        let count: usize = count.parse().map_err(|_| error())?;
+
+        if index == 0 || index > count {
+            return Err(error());
+        }
+
+        Ok(Self { index, count })
+    }
+}
+
+impl std::fmt::Display for Partition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// The duration of each test, in seconds, from a previous run of `cargo xtask
+/// nextest`
+///
+/// Tests are identified by their nextest binary ID and name, like `my-crate
+/// tests::it_works`. Each run writes its timings to
+/// `target/xtask/nextest/timings.json`. Save that as an artifact, and load it
+/// in a later run to keep the shards balanced.
+#[derive(Default, Debug)]
+pub struct TestTimings(BTreeMap<String, f64>);
+
+impl TestTimings {
+    /// Load timings written by a previous run.
+    pub fn load(path: impl AsRef<Path>) -> WorkflowResult<Self> {
+        Ok(Self(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    /// Add the timings from `other`, replacing any for the same tests.
+    ///
+    /// Use this to combine timings written by each shard.
+    pub fn merge(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    fn save(&self, path: impl AsRef<Path>) -> WorkflowResult<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.0)?)?;
+        Ok(())
+    }
+
+    /// The tests from `tests` in `partition`.
+    ///
+    /// The longest tests are assigned first, each to the shard with the
+    /// shortest total duration so far. Tests without a timing are assumed to
+    /// take the mean duration. The assignment only depends on `tests` and the
+    /// timings, so every shard agrees on it.
+    pub fn shard(&self, tests: &[String], partition: Partition) -> Vec<String> {
+        let mean = if self.0.is_empty() {
+            1.0
+        } else {
+            self.0.values().sum::<f64>() / self.0.len() as f64
+        };
+        let mut tests = tests
+            .iter()
+            .map(|test| (self.0.get(test).copied().unwrap_or(mean), test))
+            .collect::<Vec<_>>();
+        tests.sort_by(|(x_time, x), (y_time, y)| y_time.total_cmp(x_time).then(x.cmp(y)));
+
+        let mut totals = vec![0.0_f64; partition.count];
+        let mut shard = Vec::new();
+
+        for (time, test) in tests {
+            let (index, _) = totals
+                .iter()
+                .enumerate()
+                .min_by(|(_, x), (_, y)| x.total_cmp(y))
+                .expect("There should be at least one shard");
+            totals[index] += time;
+
+            if index + 1 == partition.index {
+                shard.push(test.clone());
+            }
+        }
+
+        shard.sort();
+        shard
+    }
+
+    /// Read the duration of each test that was run from nextest's JUnit
+    /// report.
+    ///
+    /// Retried tests have the duration of their last attempt, or their first
+    /// if they failed every attempt.
+    fn parse(report: &Report) -> Self {
+        let mut timings = Self::default();
+
+        for (test, case) in test_cases(report) {
+            let skipped = matches!(case.status, TestCaseStatus::Skipped { .. });
+
+            if let (Some(time), false) = (case.time, skipped) {
+                timings.0.insert(test, time.as_secs_f64());
+            }
+        }
+
+        timings
+    }
+}
+
+/// The tests in the workspace, as `binary-id test-name`
+fn list_tests() -> WorkflowResult<Vec<String>> {
+    let list = duct::cmd!(
+        "cargo",
+        "nextest",
+        "list",
+        "--workspace",
+        "--message-format",
+        "oneline"
+    )
+    .read()?;
+
+    Ok(list.lines().map(str::to_string).collect())
+}
+
+/// A nextest filter expression that matches exactly `tests`
+fn filter_expression(tests: &[String]) -> String {
+    tests
+        .iter()
+        .map(|test| {
+            let (binary_id, name) = test.split_once(' ').unwrap_or(("", test));
+            format!("(binary_id(={binary_id}) & test(={name}))")
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+#[cfg(test)]
+mod tests {
+    use quick_junit::Report;
+
+    use super::{Partition, TestResults, TestTimings};
+
+    const REPORT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="nextest-run" tests="4" failures="1" errors="0">
+    <testsuite name="my-crate" tests="4" disabled="1" errors="0" failures="1">
+        <testcase name="tests::ok" classname="my-crate" time="0.004">
+        </testcase>
+        <testcase name="tests::flaky" classname="my-crate" time="0.003">
+            <flakyFailure type="test failure"/>
+        </testcase>
+        <testcase name="tests::broken" classname="my-crate" time="0.002">
+            <failure type="test failure"/>
+            <rerunFailure type="test failure"/>
+        </testcase>
+        <testcase name="tests::ignored" classname="my-crate" time="0.000">
+            <skipped message="Skipped: test ignored"/>
+        </testcase>
+    </testsuite>
+</testsuites>
+"#;
+
+    fn partition(s: &str) -> Partition {
+        s.parse().unwrap()
+    }
+
+    fn timings(tests: &[(&str, f64)]) -> TestTimings {
+        TestTimings(
+            tests
+                .iter()
+                .map(|(test, time)| (test.to_string(), *time))
+                .collect(),
+        )
+    }
+
+    fn tests(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_partition() {
+        let partition = partition("2/3");
+        assert_eq!((partition.index, partition.count), (2, 3));
+        assert_eq!(partition.to_string(), "2/3");
+
+        for invalid in ["", "2", "0/3", "4/3", "a/3", "2/b", "-1/3"] {
+            assert!(invalid.parse::<Partition>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn shard_by_duration() {
+        let timings = timings(&[("a", 4.0), ("b", 3.0), ("c", 2.0), ("d", 1.0)]);
+        let all = tests(&["a", "b", "c", "d"]);
+
+        assert_eq!(timings.shard(&all, partition("1/2")), tests(&["a", "d"]));
+        assert_eq!(timings.shard(&all, partition("2/2")), tests(&["b", "c"]));
+        assert_eq!(timings.shard(&all, partition("1/1")), all);
+    }
+
+    #[test]
+    fn shard_without_timings() {
+        let timings = timings(&[("a", 3.0), ("b", 1.0)]);
+        let all = tests(&["a", "b", "c", "d"]);
+        let shards = ["1/2", "2/2"].map(|p| timings.shard(&all, partition(p)));
+
+        // Untimed tests take the mean duration, 2s.
+        assert_eq!(shards, [tests(&["a", "b"]), tests(&["c", "d"])]);
+    }
+
+    #[test]
+    fn shard_more_partitions_than_tests() {
+        let timings = TestTimings::default();
+        let all = tests(&["a"]);
+
+        assert_eq!(timings.shard(&all, partition("1/3")), all);
+        assert!(timings.shard(&all, partition("3/3")).is_empty());
+    }
+
+    #[test]
+    fn parse_results() {
+        let report = Report::deserialize_from_str(REPORT).unwrap();
+        let results = TestResults::parse(&report);
+
+        assert_eq!(results.passed, 2);
+        assert_eq!(results.skipped, 1);
+        assert_eq!(results.flaky, ["my-crate tests::flaky"]);
+        assert_eq!(results.failed, ["my-crate tests::broken"]);
+    }
+
+    #[test]
+    fn parse_timings() {
+        let report = Report::deserialize_from_str(REPORT).unwrap();
+        let timings = TestTimings::parse(&report);
+
+        assert_eq!(
+            timings.0.into_iter().collect::<Vec<_>>(),
+            [
+                ("my-crate tests::broken".to_string(), 0.002),
+                ("my-crate tests::flaky".to_string(), 0.003),
+                ("my-crate tests::ok".to_string(), 0.004),
+            ]
+        );
+    }
+}
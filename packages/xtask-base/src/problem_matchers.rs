@@ -0,0 +1,68 @@
+use std::fs;
+
+use serde_json::json;
+
+use crate::{summary, WorkflowResult, Workspace};
+
+/// Register problem matchers for `rustc`, `clippy` and `rustfmt` output, when
+/// running under Github Actions.
+///
+/// Errors and warnings from commands run by `cargo xtask ci` are then shown as
+/// annotations on the pull request diff. The matchers are written to
+/// `target/xtask/github`, as Github reads them from a file.
+pub fn register(workspace: &Workspace) -> WorkflowResult<()> {
+    if !summary::is_github_actions() {
+        return Ok(());
+    }
+
+    // Optional ANSI color codes
+    let color = r"(?:\x1b\[[\d;]+m)*";
+    let matchers = json!({
+        "problemMatcher": [
+            {
+                "owner": "xtask-rustc",
+                "pattern": [
+                    {
+                        "regexp": format!(
+                            r"^{color}(warning|error)(?:\[(\S*)\])?{color}: (.*?){color}$"
+                        ),
+                        "severity": 1,
+                        "code": 2,
+                        "message": 3
+                    },
+                    {
+                        "regexp": format!(
+                            r"^{color}\s*{color}\s*--> {color}(.*?):(\d+):(\d+){color}$"
+                        ),
+                        "file": 1,
+                        "line": 2,
+                        "column": 3
+                    }
+                ]
+            },
+            {
+                "owner": "xtask-rustfmt",
+                "severity": "warning",
+                "pattern": [
+                    {
+                        "regexp": r"^(Diff in (\S+?))(?: at line |:)(\d+):",
+                        "message": 1,
+                        "file": 2,
+                        "line": 3
+                    }
+                ]
+            }
+        ]
+    });
+
+    let path = workspace
+        .artifact_dir("github")?
+        .join("problem-matchers.json");
+    fs::write(&path, serde_json::to_string_pretty(&matchers)?)?;
+
+    // Workflow commands are read from `stderr` as well as `stdout`, so this
+    // doesn't interfere with JSON output.
+    eprintln!("::add-matcher::{}", path.display());
+
+    Ok(())
+}
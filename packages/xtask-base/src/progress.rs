@@ -0,0 +1,91 @@
+//! Progress bars for local CI runs
+//!
+//! Each job gets a bar showing the current step and the time elapsed. Command
+//! output is printed above the bar. Bars are only shown when `stderr` is a
+//! terminal and the `CI` environment variable isn't set, otherwise output is
+//! logged sequentially, as it would be without them.
+use std::{
+    env,
+    fmt::Display,
+    io::{stderr, IsTerminal},
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::style::{self, Color};
+
+/// Should progress bars be shown?
+fn enabled() -> bool {
+    stderr().is_terminal() && env::var_os("CI").is_none()
+}
+
+/// The progress of a single job, with `None` for plain logging
+pub(crate) struct JobProgress(Option<ProgressBar>);
+
+impl JobProgress {
+    /// A progress bar for the job `name`, with `steps` steps
+    pub fn new(name: &str, steps: usize) -> Self {
+        if !enabled() {
+            return Self(None);
+        }
+
+        let style = ProgressStyle::with_template(
+            "{spinner:.cyan} {prefix:.bold} [{elapsed}] {pos}/{len} {wide_msg}",
+        )
+        .expect("Progress template should be valid");
+        let bar = ProgressBar::new(steps as u64)
+            .with_style(style)
+            .with_prefix(name.to_string());
+        bar.enable_steady_tick(Duration::from_millis(100));
+
+        Self(Some(bar))
+    }
+
+    /// Is a progress bar being shown?
+    pub fn is_visible(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Show `step` as the current step
+    pub fn start_step(&self, step: impl Display) {
+        if let Some(bar) = &self.0 {
+            bar.set_message(step.to_string());
+        }
+    }
+
+    pub fn finish_step(&self) {
+        if let Some(bar) = &self.0 {
+            bar.inc(1);
+        }
+    }
+
+    /// Print `line` to `stderr`, above the progress bar if there is one.
+    pub fn println(&self, line: impl Display) {
+        match &self.0 {
+            Some(bar) => bar.println(line.to_string()),
+            None => eprintln!("{line}"),
+        }
+    }
+
+    /// Stop the progress bar, leaving the final status on the terminal.
+    pub fn finish(&self, success: bool) {
+        if let Some(bar) = &self.0 {
+            let status = if success {
+                style::paint("passed", Color::Green)
+            } else {
+                style::paint("failed", Color::Red)
+            };
+
+            bar.finish_with_message(status);
+        }
+    }
+}
+
+/// Print `line` to `stderr`, above `progress` if there is one.
+pub(crate) fn println(progress: Option<&JobProgress>, line: impl Display) {
+    match progress {
+        Some(progress) => progress.println(line),
+        None => eprintln!("{line}"),
+    }
+}
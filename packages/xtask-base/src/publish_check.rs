@@ -0,0 +1,82 @@
+//! Check packages can be published, with `cargo publish --dry-run`
+use cargo_metadata::Package;
+
+use crate::{cmd, WorkflowResult, Workspace};
+
+/// Package each publishable package and verify it builds, as `cargo publish`
+/// would, without uploading anything. Check just `package` if it's specified.
+///
+/// This finds problems like files that aren't `include`d, and path
+/// dependencies without a version. `cargo` only warns about missing
+/// `description` and `license` metadata, but crates.io rejects them, so
+/// they're errors here. The packages are checked together, so dependencies on
+/// new versions of other workspace packages resolve to the local versions.
+/// That needs cargo 1.90 or later.
+pub fn check(
+    workspace: &Workspace,
+    package: Option<&str>,
+    allow_dirty: bool,
+) -> WorkflowResult<()> {
+    let names = match package {
+        Some(package) => vec![package],
+        None => workspace.publish_order()?,
+    };
+    let workspace_packages = workspace.0.workspace_packages();
+    let mut packages = Vec::new();
+
+    for name in &names {
+        let package = workspace_packages
+            .iter()
+            .find(|p| p.name == *name)
+            .ok_or_else(|| format!("Package \"{name}\" not found in workspace"))?;
+        packages.push(*package);
+    }
+
+    if packages.is_empty() {
+        println!("No publishable packages");
+        return Ok(());
+    }
+
+    let problems = packages
+        .iter()
+        .flat_map(|package| missing_metadata(package))
+        .collect::<Vec<_>>();
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+
+        return Err(format!("Found {} packaging problem(s)", problems.len()).into());
+    }
+
+    let mut args = vec!["publish", "--dry-run"];
+
+    if allow_dirty {
+        args.push("--allow-dirty");
+    }
+
+    for name in names {
+        args.extend(["--package", name]);
+    }
+
+    cmd("cargo", args)
+}
+
+/// The metadata crates.io requires that `package` is missing
+fn missing_metadata(package: &Package) -> Vec<String> {
+    let mut problems = Vec::new();
+    let manifest = &package.manifest_path;
+
+    if package.description.is_none() {
+        problems.push(format!("{manifest}: `description` is required to publish"));
+    }
+
+    if package.license.is_none() && package.license_file.is_none() {
+        problems.push(format!(
+            "{manifest}: `license` or `license-file` is required to publish"
+        ));
+    }
+
+    problems
+}
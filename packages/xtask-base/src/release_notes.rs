@@ -0,0 +1,33 @@
+use std::{env, fs, path::Path};
+
+use serde_json::json;
+
+use crate::{template::TemplateEngine, WorkflowResult};
+
+/// The default release notes template
+pub(crate) const TEMPLATE: &str = "RELEASE_NOTES.tmpl.md";
+
+/// Where the release job downloads artifacts to
+pub(crate) const ARTIFACTS_DIR: &str = "target/release-artifacts";
+
+/// Render the release notes `template` for `tag`, to `output` or `stdout`.
+///
+/// The tag defaults to `$GITHUB_REF_NAME`, which is the tag name in a workflow
+/// triggered by pushing a tag.
+pub fn render(template: &Path, tag: Option<&str>, output: Option<&Path>) -> WorkflowResult<()> {
+    let tag = match tag {
+        Some(tag) => tag.to_string(),
+        None => env::var("GITHUB_REF_NAME")
+            .map_err(|_| "No tag given, and `GITHUB_REF_NAME` isn't set")?,
+    };
+    let version = tag.strip_prefix('v').unwrap_or(&tag);
+    let data = json!({ "tag": tag, "version": version });
+    let notes = TemplateEngine::default().render(&fs::read_to_string(template)?, &data)?;
+
+    match output {
+        Some(output) => fs::write(output, notes)?,
+        None => print!("{notes}"),
+    }
+
+    Ok(())
+}
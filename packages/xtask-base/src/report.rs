@@ -0,0 +1,181 @@
+//! Machine-readable reports for `cargo xtask ci` and `cargo xtask codegen`
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    ci::JobReport,
+    style::{self, Color},
+    WorkflowResult,
+};
+
+/// How results are output
+#[derive(ValueEnum, Copy, Clone, Default, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// A JSON report on `stdout`. All other output goes to `stderr`.
+    Json,
+}
+
+/// A file checked or written by codegen
+#[derive(Serialize, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// In check mode, this means the file is out of date.
+    pub changed: bool,
+}
+
+#[derive(Serialize)]
+struct CiReport<'a> {
+    success: bool,
+    jobs: &'a [JobReport],
+}
+
+#[derive(Serialize)]
+struct CodegenReport<'a> {
+    check: bool,
+    success: bool,
+    error: Option<String>,
+    files: &'a [FileReport],
+}
+
+static FILE_REPORTS: Mutex<Vec<FileReport>> = Mutex::new(Vec::new());
+static DEFER_CHECK_FAILURES: AtomicBool = AtomicBool::new(false);
+
+/// Record that `path` was checked or written. A file can be recorded more than
+/// once, for example to check its contents and permissions separately. It's
+/// changed if any of the records are.
+pub(crate) fn record_file(path: &Path, changed: bool) {
+    let mut reports = FILE_REPORTS.lock().expect("File reports lock poisoned");
+
+    match reports.iter_mut().find(|report| report.path == path) {
+        Some(report) => report.changed |= changed,
+        None => reports.push(FileReport {
+            path: path.to_path_buf(),
+            changed,
+        }),
+    }
+}
+
+/// Report an out of date file in check mode
+///
+/// This is an error, unless we're inside [`check_all`], in which case
+/// `message` is printed and checking carries on.
+pub(crate) fn check_failure(message: String) -> WorkflowResult<()> {
+    if DEFER_CHECK_FAILURES.load(Ordering::Relaxed) {
+        eprintln!("{message}");
+        Ok(())
+    } else {
+        Err(message.into())
+    }
+}
+
+/// Run `codegen` in check mode, reporting all out of date files at the end,
+/// rather than stopping at the first one.
+pub(crate) fn check_all(codegen: impl FnOnce() -> WorkflowResult<()>) -> WorkflowResult<()> {
+    DEFER_CHECK_FAILURES.store(true, Ordering::Relaxed);
+    let result = codegen();
+    DEFER_CHECK_FAILURES.store(false, Ordering::Relaxed);
+    result?;
+
+    let out_of_date = file_reports()
+        .into_iter()
+        .filter(|file| file.changed)
+        .map(|file| format!("  {}", file.path.display()))
+        .collect::<Vec<_>>();
+
+    if out_of_date.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "{} generated file(s) out of date:\n{}\nRun `cargo xtask codegen` to update them.",
+        out_of_date.len(),
+        out_of_date.join("\n")
+    )
+    .into())
+}
+
+/// All the files checked or written by codegen so far
+pub fn file_reports() -> Vec<FileReport> {
+    FILE_REPORTS
+        .lock()
+        .expect("File reports lock poisoned")
+        .clone()
+}
+
+pub(crate) fn print_ci(jobs: &[JobReport]) -> WorkflowResult<()> {
+    let success = jobs
+        .iter()
+        .all(|job| job.steps.iter().all(|step| step.success));
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&CiReport { success, jobs })?
+    );
+
+    if success {
+        Ok(())
+    } else {
+        Err("CI failed".into())
+    }
+}
+
+/// Print a summary of `jobs` in human readable form, with the log file for
+/// any failed steps.
+pub(crate) fn print_ci_summary(jobs: &[JobReport]) -> WorkflowResult<()> {
+    let failures = jobs
+        .iter()
+        .flat_map(|job| job.steps.iter().map(move |step| (job, step)))
+        .filter(|(_, step)| !step.success)
+        .collect::<Vec<_>>();
+
+    if let Some(log_dir) = jobs
+        .iter()
+        .flat_map(|job| &job.steps)
+        .find_map(|step| step.log.as_ref()?.parent())
+    {
+        eprintln!("Logs written to \"{}\"", log_dir.display());
+    }
+
+    if failures.is_empty() {
+        eprintln!("{}", style::paint("CI passed", Color::Green));
+        return Ok(());
+    }
+
+    for (job, step) in failures {
+        eprintln!(
+            "{} job \"{}\" ({}): `{}`",
+            style::paint("Failed", Color::Red),
+            job.name,
+            job.platform,
+            step.command
+        );
+
+        if let Some(log) = &step.log {
+            eprintln!("  log: {}", log.display());
+        }
+    }
+
+    Err("CI failed".into())
+}
+
+pub(crate) fn print_codegen(check: bool, result: WorkflowResult<()>) -> WorkflowResult<()> {
+    let report = CodegenReport {
+        check,
+        success: result.is_ok(),
+        error: result.as_ref().err().map(ToString::to_string),
+        files: &file_reports(),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    result.map_err(|_| "Codegen failed".into())
+}
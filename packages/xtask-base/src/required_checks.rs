@@ -0,0 +1,55 @@
+//! The manifest of required status checks, for branch protection
+use std::{fs, path::Path};
+
+use serde_json::json;
+
+use crate::{cmd, update_file, WorkflowResult};
+
+const MANIFEST: &str = ".github/required-checks.json";
+
+/// Write the manifest, or check it's up to date if `check` is true.
+///
+/// The contents are the body of a request to the Github API's "Update status
+/// check protection" endpoint.
+pub(crate) fn write(checks: Vec<String>, check: bool) -> WorkflowResult<()> {
+    let manifest = json!({ "strict": false, "contexts": checks });
+    update_file(
+        MANIFEST,
+        &format!("{}\n", serde_json::to_string_pretty(&manifest)?),
+        check,
+    )
+}
+
+/// Set the required status checks for `branch` from the manifest, using the
+/// Github CLI.
+pub(crate) fn apply(branch: &str) -> WorkflowResult<()> {
+    if !Path::new(MANIFEST).exists() {
+        return Err(format!(
+            "`{MANIFEST}` not found. Enable it with `CI::required_checks`, then run `cargo xtask \
+             codegen`."
+        )
+        .into());
+    }
+
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(MANIFEST)?)?;
+    println!("Required checks for `{branch}`:");
+
+    for check in manifest["contexts"].as_array().into_iter().flatten() {
+        println!("  {}", check.as_str().unwrap_or_default());
+    }
+
+    cmd(
+        "gh",
+        [
+            "api",
+            "--method",
+            "PATCH",
+            &format!(
+                "repos/{{owner}}/{{repo}}/branches/{branch}/protection/required_status_checks"
+            ),
+            "--input",
+            MANIFEST,
+            "--silent",
+        ],
+    )
+}
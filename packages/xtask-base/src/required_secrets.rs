@@ -0,0 +1,21 @@
+//! The manifest of repository secrets that workflows need
+use std::collections::BTreeSet;
+
+use serde_json::json;
+
+use crate::{update_file, WorkflowResult};
+
+const MANIFEST: &str = ".github/required-secrets.json";
+
+/// Write the manifest, or check it's up to date if `check` is true.
+///
+/// The manifest lists the secrets declared with `requires_secret`, so they can
+/// be checked when setting up a repository.
+pub(crate) fn write(secrets: &BTreeSet<String>, check: bool) -> WorkflowResult<()> {
+    let manifest = json!({ "secrets": secrets });
+    update_file(
+        MANIFEST,
+        &format!("{}\n", serde_json::to_string_pretty(&manifest)?),
+        check,
+    )
+}
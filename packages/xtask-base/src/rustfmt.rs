@@ -0,0 +1,101 @@
+//! `rustfmt.toml` generation
+use toml::Value;
+
+use crate::{codegen, update_file, WorkflowResult};
+
+const BASELINE: &str = include_str!("boilerplate/rustfmt.toml");
+
+/// The comment in the baseline config that unstable options go below
+const UNSTABLE_MARKER: &str = "# All unstable features should be below this";
+
+/// Generate `rustfmt.toml`, starting from the bundled baseline
+///
+/// Options are replaced in place if they're already in the baseline, otherwise
+/// they're added. Unstable options are added to the end, so they stay below
+/// `unstable_features = true`.
+///
+/// ```no_run
+/// # use xtask_base::{RustfmtConfig, WorkflowResult};
+/// # fn codegen(check: bool) -> WorkflowResult<()> {
+/// RustfmtConfig::default()
+///     .edition("2021")
+///     .imports_granularity("Module")
+///     .option("max_width", 120)
+///     .unstable_option("normalize_comments", true)
+///     .generate(check)
+/// # }
+/// ```
+#[derive(Default)]
+pub struct RustfmtConfig {
+    options: Vec<(String, Value, bool)>,
+}
+
+impl RustfmtConfig {
+    /// Set the edition used to parse code.
+    pub fn edition(self, edition: &str) -> Self {
+        self.option("edition", edition)
+    }
+
+    /// Set how imports are merged, like `"Crate"`, `"Module"` or `"Item"`.
+    ///
+    /// This is an unstable option.
+    pub fn imports_granularity(self, granularity: &str) -> Self {
+        self.unstable_option("imports_granularity", granularity)
+    }
+
+    /// Set a stable option.
+    pub fn option(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.options.push((key.into(), value.into(), false));
+        self
+    }
+
+    /// Set an option that's only available on nightly `rustfmt`.
+    pub fn unstable_option(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.options.push((key.into(), value.into(), true));
+        self
+    }
+
+    /// Generate `rustfmt.toml` in the workspace root, or check it's up to date
+    /// if `check` is set.
+    pub fn generate(&self, check: bool) -> WorkflowResult<()> {
+        if !codegen::is_selected(None) {
+            return Ok(());
+        }
+
+        update_file("rustfmt.toml", &self.contents(), check)
+    }
+
+    fn contents(&self) -> String {
+        let mut lines: Vec<String> = BASELINE.lines().map(str::to_string).collect();
+
+        for (key, value, unstable) in &self.options {
+            let line = format!("{key} = {value}");
+            let existing = lines.iter().position(|line| {
+                line.split_once('=')
+                    .is_some_and(|(existing_key, _)| existing_key.trim() == key)
+            });
+
+            if let Some(index) = existing {
+                lines[index] = line;
+            } else if *unstable {
+                lines.push(line);
+            } else {
+                let index = lines
+                    .iter()
+                    .position(|line| line == UNSTABLE_MARKER)
+                    .map_or(lines.len(), |marker| {
+                        // Keep the blank line above the marker
+                        if marker > 0 && lines[marker - 1].is_empty() {
+                            marker - 1
+                        } else {
+                            marker
+                        }
+                    });
+                lines.insert(index, line);
+            }
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+}
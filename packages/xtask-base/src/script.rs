@@ -0,0 +1,110 @@
+use std::path::PathBuf;
+
+use crate::{report, update_file, WorkflowResult};
+
+/// A generated executable script, with an optional Windows shim
+///
+/// On Unix, the script's permissions are set to the POSIX `mode`. Windows has
+/// no executable bit, so the mode is ignored there, and a `.cmd` shim can be
+/// generated alongside the script instead. The shim is generated on every
+/// platform, so check mode gives the same result everywhere.
+///
+/// For example, to generate a `./ci` wrapper:
+///
+/// ```no_run
+/// # use xtask_base::{GeneratedScript, WorkflowResult};
+/// # fn main() -> WorkflowResult<()> {
+/// GeneratedScript::new("ci", "exec cargo xtask ci \"$@\"\n")
+///     .shebang("/bin/sh")
+///     .windows_shim("@cargo xtask ci %*\n")
+///     .write(false)
+/// # }
+/// ```
+pub struct GeneratedScript {
+    path: PathBuf,
+    contents: String,
+    shebang: Option<String>,
+    mode: u32,
+    windows_shim: Option<String>,
+}
+
+impl GeneratedScript {
+    /// A script at `path`, executable by everyone (mode `0o755`)
+    pub fn new(path: impl Into<PathBuf>, contents: &str) -> Self {
+        Self {
+            path: path.into(),
+            contents: contents.to_string(),
+            shebang: None,
+            mode: 0o755,
+            windows_shim: None,
+        }
+    }
+
+    /// Start the script with `#!{interpreter}`
+    pub fn shebang(mut self, interpreter: &str) -> Self {
+        self.shebang = Some(interpreter.to_string());
+        self
+    }
+
+    /// Set the POSIX permissions. Only the lower 9 bits are used.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.mode = mode & 0o777;
+        self
+    }
+
+    /// Also generate a `.cmd` file with `contents`, next to the script
+    pub fn windows_shim(mut self, contents: &str) -> Self {
+        self.windows_shim = Some(contents.to_string());
+        self
+    }
+
+    /// Write the script, or check it's up to date if `check` is true
+    pub fn write(&self, check: bool) -> WorkflowResult<()> {
+        let contents = match &self.shebang {
+            Some(interpreter) => format!("#!{interpreter}\n{}", self.contents),
+            None => self.contents.clone(),
+        };
+
+        update_file(&self.path, &contents, check)?;
+        self.update_mode(check)?;
+
+        if let Some(shim) = &self.windows_shim {
+            update_file(self.path.with_extension("cmd"), shim, check)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn update_mode(&self, check: bool) -> WorkflowResult<()> {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        if check {
+            // A missing script has already been reported as out of date
+            let Ok(metadata) = fs::metadata(&self.path) else {
+                return Ok(());
+            };
+            let mode = metadata.permissions().mode() & 0o777;
+
+            let changed = mode != self.mode;
+            report::record_file(&self.path, changed);
+
+            if changed {
+                return report::check_failure(format!(
+                    "File \"{}\" has mode {mode:o}, but should have {:o}",
+                    self.path.display(),
+                    self.mode
+                ));
+            }
+        } else {
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(self.mode))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn update_mode(&self, _check: bool) -> WorkflowResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+//! Colored status output
+//!
+//! Colors are enabled when `stderr` is a terminal. `NO_COLOR` disables them,
+//! and `CLICOLOR_FORCE` enables them even when `stderr` isn't a terminal.
+use std::{
+    env,
+    ffi::OsString,
+    fmt::{self, Display},
+    io::{stderr, IsTerminal},
+};
+
+use itertools::Itertools;
+
+#[derive(Copy, Clone)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+}
+
+impl Color {
+    fn code(self) -> u8 {
+        match self {
+            Color::Red => 31,
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Cyan => 36,
+        }
+    }
+}
+
+pub fn color_enabled() -> bool {
+    color_choice(
+        env::var_os("CLICOLOR_FORCE"),
+        env::var_os("NO_COLOR"),
+        stderr().is_terminal(),
+    )
+}
+
+fn color_choice(
+    clicolor_force: Option<OsString>,
+    no_color: Option<OsString>,
+    is_terminal: bool,
+) -> bool {
+    if clicolor_force.is_some_and(|value| !value.is_empty() && value != "0") {
+        true
+    } else if no_color.is_some_and(|value| !value.is_empty()) {
+        false
+    } else {
+        is_terminal
+    }
+}
+
+/// Bold text in `color`, if colors are enabled
+pub fn paint(text: impl Display, color: Color) -> String {
+    if color_enabled() {
+        format!("\x1B[1;{}m{text}\x1B[0m", color.code())
+    } else {
+        text.to_string()
+    }
+}
+
+/// Print a header for a job or step to `stderr`
+pub fn header(text: impl Display) {
+    eprintln!("{}", paint(format_args!("==> {text}"), Color::Cyan));
+}
+
+/// A line based diff from `old` to `new`, colored if colors are enabled.
+///
+/// Common leading and trailing lines are elided, with a few lines of context.
+pub fn diff(old: &str, new: &str) -> String {
+    diff_with(old, new, |text, color| paint(text, color))
+}
+
+/// Like [`diff`], but never colored.
+pub fn plain_diff(old: &str, new: &str) -> String {
+    diff_with(old, new, |text, _color| text.to_string())
+}
+
+fn diff_with(old: &str, new: &str, paint: fn(fmt::Arguments, Color) -> String) -> String {
+    const CONTEXT: usize = 3;
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let prefix = old
+        .iter()
+        .zip(&new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take(max_suffix)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let context_start = prefix.saturating_sub(CONTEXT);
+    let removed = &old[prefix..old.len() - suffix];
+    let added = &new[prefix..new.len() - suffix];
+    let trailing = &new[new.len() - suffix..];
+
+    let mut lines = Vec::new();
+
+    if context_start > 0 {
+        lines.push(format!("@@ line {} @@", context_start + 1));
+    }
+
+    lines.extend(
+        new[context_start..prefix]
+            .iter()
+            .map(|line| format!(" {line}")),
+    );
+    lines.extend(
+        removed
+            .iter()
+            .map(|line| paint(format_args!("-{line}"), Color::Red)),
+    );
+    lines.extend(
+        added
+            .iter()
+            .map(|line| paint(format_args!("+{line}"), Color::Green)),
+    );
+    lines.extend(trailing.iter().take(CONTEXT).map(|line| format!(" {line}")));
+
+    lines.iter().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{color_choice, diff_with, plain_diff, Color};
+
+    #[test]
+    fn changed_lines() {
+        assert_eq!(
+            plain_diff("a\nb\nc\n", "a\nB\nc\nd\n"),
+            " a\n-b\n-c\n+B\n+c\n+d"
+        );
+        assert_eq!(plain_diff("a\nb\nc", "a\nx\nc"), " a\n-b\n+x\n c");
+    }
+
+    #[test]
+    fn elided_context() {
+        let old = (1..=10).map(|line| line.to_string()).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new[5] = "six".to_string();
+
+        assert_eq!(
+            plain_diff(&old.join("\n"), &new.join("\n")),
+            "@@ line 3 @@\n 3\n 4\n 5\n-6\n+six\n 7\n 8\n 9"
+        );
+    }
+
+    #[test]
+    fn identical() {
+        assert_eq!(plain_diff("a\nb", "a\nb"), " a\n b");
+    }
+
+    #[test]
+    fn painted_lines() {
+        let paint = |text: std::fmt::Arguments, color| match color {
+            Color::Red => format!("<red>{text}"),
+            Color::Green => format!("<green>{text}"),
+            Color::Yellow | Color::Cyan => unreachable!(),
+        };
+
+        assert_eq!(diff_with("a\nb", "a\nc", paint), " a\n<red>-b\n<green>+c");
+    }
+
+    #[test]
+    fn color_env_vars() {
+        let var = |value: &str| Some(value.into());
+
+        assert!(color_choice(None, None, true));
+        assert!(!color_choice(None, None, false));
+        assert!(!color_choice(None, var("1"), true));
+        assert!(color_choice(None, var(""), true));
+        assert!(color_choice(var("1"), None, false));
+        assert!(color_choice(var("1"), var("1"), false));
+        assert!(!color_choice(var("0"), None, false));
+        assert!(!color_choice(var(""), var("1"), true));
+    }
+}
@@ -0,0 +1,49 @@
+//! Github job summaries
+//!
+//! Under Github Actions, markdown is appended to the file in
+//! `$GITHUB_STEP_SUMMARY`, so it shows up on the job's summary page. Locally,
+//! it's printed to `stderr` instead.
+use std::{env, fmt::Display, fs::OpenOptions, io::Write};
+
+use crate::WorkflowResult;
+
+/// Add `markdown` to the job summary.
+pub fn add(markdown: impl Display) -> WorkflowResult<()> {
+    match env::var_os("GITHUB_STEP_SUMMARY") {
+        Some(summary_file) if is_github_actions() => {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(summary_file)?;
+            writeln!(file, "{markdown}")?;
+        }
+        _ => eprintln!("{markdown}"),
+    }
+
+    Ok(())
+}
+
+/// Are we running under Github Actions?
+pub fn is_github_actions() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true")
+}
+
+/// A markdown table, with a header row.
+pub fn table<'a>(
+    header: impl IntoIterator<Item = &'a str>,
+    rows: impl IntoIterator<Item = impl IntoIterator<Item = impl Display>>,
+) -> String {
+    let header = header.into_iter().collect::<Vec<_>>();
+    let mut table = format!("| {} |\n", header.join(" | "));
+    table.push_str(&format!("|{}\n", " --- |".repeat(header.len())));
+
+    for row in rows {
+        let cells = row
+            .into_iter()
+            .map(|cell| cell.to_string().replace('|', "\\|"))
+            .collect::<Vec<_>>();
+        table.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+
+    table
+}
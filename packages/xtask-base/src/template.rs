@@ -1,29 +1,243 @@
-use std::process::Output;
+//! Handlebars or MiniJinja templates, with the helpers described in
+//! [`build_readme`](crate::build_readme).
 
+use std::{env, fmt::Write, process::Output};
+
+use cargo_metadata::Package;
+use chrono::Utc;
+pub use handlebars;
 use handlebars::{Handlebars, RenderError};
+pub use minijinja;
+use minijinja::Environment;
+pub(crate) use release::latest_changelog_version;
+use serde_json::Value;
+pub(crate) use shell_cache::CACHE_ENV_VAR;
 
-mod handlebars_helpers {
-    use std::fs;
+use crate::{config::EnvConfig, metadata_cache, WorkflowResult};
 
+mod badges;
+mod jinja;
+mod release;
+mod rustdoc;
+mod shell_cache;
+mod snippet;
+mod toc;
+
+mod handlebars_helpers {
     use handlebars::handlebars_helper;
 
-    use super::run_process;
+    use super::{
+        badges::badges as package_badges, env_var, format_date, package_field, read_rustc_version,
+        release, rustdoc::crate_docs, shell_output, snippet, toc::placeholder as toc_placeholder,
+    };
 
-    handlebars_helper!(include: |file: str| { fs::read_to_string(file)? });
-    handlebars_helper!(shell: |cmd: str| { run_process(cmd)? });
+    handlebars_helper!(include: |file: str, {anchor: str = "", lines: str = ""}| {
+        snippet::include(file, anchor, lines)?
+    });
+    handlebars_helper!(shell: |
+        cmd: str,
+        {allow_stderr: bool = false, trim: bool = false, inputs: str = ""}
+    | {
+        shell_output(cmd, allow_stderr, trim, inputs)?
+    });
+    handlebars_helper!(env: |var: str| { env_var(var)? });
+    handlebars_helper!(rustc_version: | | { read_rustc_version()? });
+    handlebars_helper!(date: |format: str| { format_date(format)? });
+    handlebars_helper!(toc: |{max_level: u64 = 3}| { toc_placeholder(max_level) });
+    handlebars_helper!(docs: |file: str| { crate_docs(file)? });
+    handlebars_helper!(crate_field: |package: str, field: str| { package_field(package, field)? });
+    handlebars_helper!(badges: |package: str, {workflow: str = "tests"}| {
+        package_badges(package, workflow)?
+    });
+    handlebars_helper!(changelog: |version: str, {file: str = "CHANGELOG.md"}| {
+        release::changelog(version, file)?
+    });
+    handlebars_helper!(contributors: |{since: str = ""}| { release::contributors(since)? });
+    handlebars_helper!(artifacts: |dir: str| { release::artifacts(dir)? });
 }
 
+/// A Handlebars registry with all the standard helpers.
 pub fn registry() -> Handlebars<'static> {
     let mut reg = Handlebars::new();
     reg.set_strict_mode(true);
     reg.register_helper("include", Box::new(handlebars_helpers::include));
     reg.register_helper("shell", Box::new(handlebars_helpers::shell));
+    reg.register_helper("env", Box::new(handlebars_helpers::env));
+    reg.register_helper("rustc_version", Box::new(handlebars_helpers::rustc_version));
+    reg.register_helper("date", Box::new(handlebars_helpers::date));
+    reg.register_helper("crate", Box::new(handlebars_helpers::crate_field));
+    reg.register_helper("badges", Box::new(handlebars_helpers::badges));
+    reg.register_helper("docs", Box::new(handlebars_helpers::docs));
+    reg.register_helper("toc", Box::new(handlebars_helpers::toc));
+    reg.register_helper("changelog", Box::new(handlebars_helpers::changelog));
+    reg.register_helper("contributors", Box::new(handlebars_helpers::contributors));
+    reg.register_helper("artifacts", Box::new(handlebars_helpers::artifacts));
+    reg
+}
+
+/// A Handlebars registry with all the standard helpers, customized by `f`.
+///
+/// Use this to add your own helpers and partials. For example:
+///
+/// ```
+/// use xtask_base::template::{handlebars::handlebars_helper, registry_with};
+///
+/// handlebars_helper!(shout: |s: str| s.to_uppercase());
+///
+/// let registry = registry_with(|reg| {
+///     reg.register_helper("shout", Box::new(shout));
+///     reg.register_partial("footer", "Made with xtask-base")
+///         .unwrap();
+/// });
+/// ```
+pub fn registry_with(f: impl FnOnce(&mut Handlebars<'static>)) -> Handlebars<'static> {
+    let mut reg = registry();
+    f(&mut reg);
     reg
 }
 
-fn run_process(cmd: &str) -> Result<String, RenderError> {
+/// A MiniJinja environment with all the standard helpers.
+///
+/// The helpers are functions, so are called like `{{ include("my-file.rs",
+/// anchor="usage") }}`. Add your own functions, filters and templates to the
+/// environment, and use it with [`TemplateEngine::MiniJinja`].
+pub fn minijinja_environment() -> Environment<'static> {
+    jinja::environment()
+}
+
+/// The template language used to render templates
+pub enum TemplateEngine {
+    Handlebars(Handlebars<'static>),
+    MiniJinja(Environment<'static>),
+}
+
+impl TemplateEngine {
+    /// Handlebars, with the standard helpers.
+    pub fn handlebars() -> Self {
+        Self::Handlebars(registry())
+    }
+
+    /// MiniJinja, with the standard helpers.
+    pub fn minijinja() -> Self {
+        Self::MiniJinja(minijinja_environment())
+    }
+
+    /// Render `template`, then fill in any tables of contents.
+    pub fn render(&self, template: &str, data: &Value) -> WorkflowResult<String> {
+        let rendered = match self {
+            Self::Handlebars(reg) => reg.render_template(template, data)?,
+            Self::MiniJinja(env) => env.render_str(template, data)?,
+        };
+
+        Ok(toc::insert(&rendered))
+    }
+
+    /// Check `template` compiles, without rendering it.
+    pub(crate) fn validate(&self, template: &str) -> WorkflowResult<()> {
+        match self {
+            Self::Handlebars(_) => {
+                handlebars::Template::compile(template)?;
+            }
+            Self::MiniJinja(env) => {
+                env.template_from_str(template)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Render `template` as plain text, without HTML escaping.
+    pub(crate) fn render_text(&self, template: &str, data: &Value) -> WorkflowResult<String> {
+        Ok(match self {
+            Self::Handlebars(reg) => {
+                let mut reg = reg.clone();
+                reg.register_escape_fn(handlebars::no_escape);
+                reg.render_template(template, data)?
+            }
+            Self::MiniJinja(env) => env.render_str(template, data)?,
+        })
+    }
+}
+
+impl Default for TemplateEngine {
+    fn default() -> Self {
+        Self::handlebars()
+    }
+}
+
+fn env_var(var: &str) -> Result<String, RenderError> {
+    env::var(var).map_err(|e| RenderError::new(format!("Environment variable \"{var}\": {e}")))
+}
+
+fn read_rustc_version() -> Result<String, RenderError> {
+    Ok(duct::cmd("rustc", ["--version"]).read()?)
+}
+
+fn format_date(format: &str) -> Result<String, RenderError> {
+    let mut date = String::new();
+    write!(date, "{}", Utc::now().format(format))
+        .map_err(|_| RenderError::new(format!("Invalid date format \"{format}\"")))?;
+    Ok(date)
+}
+
+fn workspace_package(package: &str) -> Result<Package, RenderError> {
+    let metadata = metadata_cache::shared_metadata()
+        .map_err(|e| RenderError::new(format!("Failed to read cargo metadata: {e}")))?;
+    metadata
+        .workspace_packages()
+        .into_iter()
+        .find(|p| p.name == package)
+        .cloned()
+        .ok_or_else(|| RenderError::new(format!("Package \"{package}\" not found")))
+}
+
+fn package_field(package: &str, field: &str) -> Result<String, RenderError> {
+    let package_info = workspace_package(package)?;
+
+    let value = match field {
+        "name" => Some(package_info.name.clone()),
+        "version" => Some(package_info.version.to_string()),
+        "description" => package_info.description.clone(),
+        "rust_version" => package_info.rust_version.as_ref().map(ToString::to_string),
+        "edition" => Some(package_info.edition.to_string()),
+        "license" => package_info.license.clone(),
+        "repository" => package_info.repository.clone(),
+        "homepage" => package_info.homepage.clone(),
+        "documentation" => package_info.documentation.clone(),
+        _ => {
+            return Err(RenderError::new(format!(
+                "Unknown package field \"{field}\""
+            )))
+        }
+    };
+
+    value.ok_or_else(|| RenderError::new(format!("Package \"{package}\" has no {field}")))
+}
+
+/// Run `cmd` with the system shell, using the cache if it's enabled.
+fn shell_output(
+    cmd: &str,
+    allow_stderr: bool,
+    trim: bool,
+    inputs: &str,
+) -> Result<String, RenderError> {
+    let run = || run_process(cmd, allow_stderr, trim);
+
+    if shell_cache::is_enabled() && !inputs.trim().is_empty() {
+        let options = [cmd, &allow_stderr.to_string(), &trim.to_string()];
+        shell_cache::cached(&options, inputs, run)
+    } else {
+        run()
+    }
+}
+
+fn run_process(cmd: &str, allow_stderr: bool, trim: bool) -> Result<String, RenderError> {
     let mut shell_cmd = execute::shell(cmd);
 
+    if let Some(vars) = EnvConfig::filtered_env() {
+        shell_cmd.env_clear().envs(vars);
+    }
+
     let Output {
         status,
         stdout,
@@ -31,20 +245,30 @@ fn run_process(cmd: &str) -> Result<String, RenderError> {
     } = shell_cmd.output()?;
 
     let output = String::from_utf8(stdout)?;
-
-    if !stderr.is_empty() {
-        return Err(RenderError::new(format!(
-            "Stderr is not empty:\n\n{}",
-            String::from_utf8(stderr)?
-        )));
-    }
+    let stderr = String::from_utf8(stderr)?;
 
     if !status.success() {
-        return Err(RenderError::new(status.code().map_or_else(
+        let mut message = status.code().map_or_else(
             || "Process failed".to_owned(),
             |code| format!("Process exited with code {}", code),
+        );
+
+        if !stderr.is_empty() {
+            write!(message, ". Stderr:\n\n{stderr}").expect("Failed to write to string");
+        }
+
+        return Err(RenderError::new(message));
+    }
+
+    if !allow_stderr && !stderr.is_empty() {
+        return Err(RenderError::new(format!(
+            "Stderr is not empty:\n\n{stderr}"
         )));
     }
 
-    Ok(output)
+    Ok(if trim {
+        output.trim().to_owned()
+    } else {
+        output
+    })
 }
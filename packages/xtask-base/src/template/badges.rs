@@ -0,0 +1,64 @@
+use handlebars::RenderError;
+
+use super::workspace_package;
+use crate::is_publishable;
+
+/// A line of Markdown badges for `package`
+///
+/// There are badges for crates.io and docs.rs if the package is publishable,
+/// the `workflow` status if the repository is on Github, and the license and
+/// MSRV if they're set in `Cargo.toml`.
+pub fn badges(package: &str, workflow: &str) -> Result<String, RenderError> {
+    let package = workspace_package(package)?;
+    let name = &package.name;
+    let mut badges = Vec::new();
+
+    if is_publishable(&package) {
+        badges.push(format!(
+            "[![crates.io](https://img.shields.io/crates/v/{name}.svg)](https://crates.io/crates/{name})"
+        ));
+        badges.push(format!(
+            "[![docs.rs](https://docs.rs/{name}/badge.svg)](https://docs.rs/{name})"
+        ));
+    }
+
+    if let Some(repo) = package
+        .repository
+        .as_deref()
+        .and_then(|url| url.strip_prefix("https://github.com/"))
+    {
+        let repo = repo.trim_end_matches('/').trim_end_matches(".git");
+        let url = format!("https://github.com/{repo}/actions/workflows/{workflow}.yml");
+        badges.push(format!("[![{workflow}]({url}/badge.svg)]({url})"));
+    }
+
+    if let Some(license) = &package.license {
+        badges.push(format!(
+            "![license](https://img.shields.io/badge/license-{}-blue.svg)",
+            shields_escape(license)
+        ));
+    }
+
+    if let Some(rust_version) = &package.rust_version {
+        // `rust-version` is usually written without a patch version
+        let rust_version = if rust_version.patch == 0 {
+            format!("{}.{}", rust_version.major, rust_version.minor)
+        } else {
+            rust_version.to_string()
+        };
+        badges.push(format!(
+            "![MSRV](https://img.shields.io/badge/MSRV-{}-blue.svg)",
+            shields_escape(&rust_version)
+        ));
+    }
+
+    Ok(badges.join(" "))
+}
+
+/// Escape text for a shields.io static badge URL path.
+fn shields_escape(text: &str) -> String {
+    text.replace('-', "--")
+        .replace('_', "__")
+        .replace(' ', "%20")
+        .replace('/', "%2F")
+}
@@ -0,0 +1,91 @@
+use handlebars::RenderError;
+use minijinja::{value::Kwargs, Environment, Error, ErrorKind, UndefinedBehavior};
+
+use super::{
+    badges::badges as package_badges, env_var, format_date, package_field, read_rustc_version,
+    release, rustdoc::crate_docs, shell_output, snippet, toc::placeholder as toc_placeholder,
+};
+
+/// A MiniJinja environment with the same helpers as the Handlebars registry.
+pub fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+    env.add_function("include", include);
+    env.add_function("shell", shell);
+    env.add_function("env", |var: String| env_var(&var).map_err(error));
+    env.add_function("rustc_version", || read_rustc_version().map_err(error));
+    env.add_function("date", |format: String| format_date(&format).map_err(error));
+    env.add_function("crate", |package: String, field: String| {
+        package_field(&package, &field).map_err(error)
+    });
+    env.add_function("badges", badges);
+    env.add_function("docs", |file: String| crate_docs(&file).map_err(error));
+    env.add_function("toc", toc);
+    env.add_function("changelog", changelog);
+    env.add_function("contributors", contributors);
+    env.add_function("artifacts", |dir: String| {
+        release::artifacts(&dir).map_err(error)
+    });
+    env
+}
+
+fn include(file: String, kwargs: Kwargs) -> Result<String, Error> {
+    let anchor: Option<String> = kwargs.get("anchor")?;
+    let lines: Option<String> = kwargs.get("lines")?;
+    kwargs.assert_all_used()?;
+
+    snippet::include(
+        &file,
+        anchor.as_deref().unwrap_or(""),
+        lines.as_deref().unwrap_or(""),
+    )
+    .map_err(error)
+}
+
+fn shell(cmd: String, kwargs: Kwargs) -> Result<String, Error> {
+    let allow_stderr: Option<bool> = kwargs.get("allow_stderr")?;
+    let trim: Option<bool> = kwargs.get("trim")?;
+    let inputs: Option<String> = kwargs.get("inputs")?;
+    kwargs.assert_all_used()?;
+
+    shell_output(
+        &cmd,
+        allow_stderr.unwrap_or(false),
+        trim.unwrap_or(false),
+        inputs.as_deref().unwrap_or_default(),
+    )
+    .map_err(error)
+}
+
+fn badges(package: String, kwargs: Kwargs) -> Result<String, Error> {
+    let workflow: Option<String> = kwargs.get("workflow")?;
+    kwargs.assert_all_used()?;
+
+    package_badges(&package, workflow.as_deref().unwrap_or("tests")).map_err(error)
+}
+
+fn changelog(version: String, kwargs: Kwargs) -> Result<String, Error> {
+    let file: Option<String> = kwargs.get("file")?;
+    kwargs.assert_all_used()?;
+
+    release::changelog(&version, file.as_deref().unwrap_or("CHANGELOG.md")).map_err(error)
+}
+
+fn contributors(kwargs: Kwargs) -> Result<String, Error> {
+    let since: Option<String> = kwargs.get("since")?;
+    kwargs.assert_all_used()?;
+
+    release::contributors(since.as_deref().unwrap_or("")).map_err(error)
+}
+
+fn toc(kwargs: Kwargs) -> Result<String, Error> {
+    let max_level: Option<u64> = kwargs.get("max_level")?;
+    kwargs.assert_all_used()?;
+
+    Ok(toc_placeholder(max_level.unwrap_or(3)))
+}
+
+fn error(e: RenderError) -> Error {
+    Error::new(ErrorKind::InvalidOperation, e.to_string())
+}
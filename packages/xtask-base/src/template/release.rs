@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use handlebars::RenderError;
+use itertools::Itertools;
+
+/// The section of the changelog `file` for `version`
+///
+/// The section starts at a level 2 heading for the version, like `## 1.2.0`,
+/// `## [1.2.0] - 2024-01-01` or `## v1.2.0`, and ends at the next level 2
+/// heading. The heading isn't included.
+pub fn changelog(version: &str, file: &str) -> Result<String, RenderError> {
+    let contents = fs::read_to_string(file)?;
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let mut lines = contents
+        .lines()
+        .skip_while(|line| !is_heading_for(line, version));
+
+    if lines.next().is_none() {
+        return Err(RenderError::new(format!(
+            "Version \"{version}\" not found in {file}"
+        )));
+    }
+
+    let section = lines.take_while(|line| !line.starts_with("## ")).join("\n");
+    Ok(section.trim().to_string())
+}
+
+/// The version in the first level 2 heading of `changelog`, ignoring an
+/// `Unreleased` section
+///
+/// Headings are in the same formats as for [`changelog`].
+pub fn latest_changelog_version(changelog: &str) -> Option<&str> {
+    changelog
+        .lines()
+        .filter_map(|line| line.strip_prefix("## "))
+        .map(|heading| {
+            let heading = heading.trim_start_matches('[');
+            let heading = heading.strip_prefix('v').unwrap_or(heading);
+            let end = heading
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '.' | '-' | '+')))
+                .unwrap_or(heading.len());
+            &heading[..end]
+        })
+        .find(|version| !version.eq_ignore_ascii_case("unreleased"))
+}
+
+fn is_heading_for(line: &str, version: &str) -> bool {
+    let Some(heading) = line.strip_prefix("## ") else {
+        return false;
+    };
+    let heading = heading.trim_start_matches('[');
+    let heading = heading.strip_prefix('v').unwrap_or(heading);
+
+    heading
+        .strip_prefix(version)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '.'))
+}
+
+/// A Markdown list of the authors of commits since the tag `since`
+///
+/// If `since` is empty, the most recent tag before `HEAD` is used, or all
+/// history if there are no tags.
+pub fn contributors(since: &str) -> Result<String, RenderError> {
+    let since = if since.is_empty() {
+        duct::cmd!("git", "describe", "--tags", "--abbrev=0", "HEAD^")
+            .stderr_null()
+            .read()
+            .ok()
+    } else {
+        Some(since.to_string())
+    };
+    let range = since.map_or_else(|| "HEAD".to_string(), |tag| format!("{tag}..HEAD"));
+    let authors = duct::cmd!("git", "log", "--format=%an", range).read()?;
+
+    Ok(authors
+        .lines()
+        .unique()
+        .sorted_by_key(|author| author.to_lowercase())
+        .map(|author| format!("- {author}"))
+        .join("\n"))
+}
+
+/// A Markdown table of the files in `dir`, and their sizes
+///
+/// Files in subdirectories are included, with paths relative to `dir`.
+pub fn artifacts(dir: &str) -> Result<String, RenderError> {
+    let dir = Path::new(dir);
+    let mut files = Vec::new();
+    find_files(dir, &mut files)?;
+    files.sort();
+
+    let mut table = "| File | Size |\n| ---- | ---- |".to_string();
+
+    for file in files {
+        let size = fs::metadata(&file)?.len();
+        let name = file.strip_prefix(dir).unwrap_or(&file);
+        table.push_str(&format!(
+            "\n| `{}` | {} |",
+            name.display(),
+            human_size(size)
+        ));
+    }
+
+    Ok(table)
+}
+
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), RenderError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
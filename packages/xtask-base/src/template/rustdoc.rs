@@ -0,0 +1,214 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use handlebars::RenderError;
+
+use crate::metadata_cache;
+
+/// Extract the crate level (`//!`) docs from `file`, converting intra-doc
+/// links to `docs.rs` links.
+///
+/// Lines hidden from rustdoc with `# ` are removed from Rust code blocks.
+pub fn crate_docs(file: &str) -> Result<String, RenderError> {
+    let source = fs::read_to_string(file)?;
+    let docs = source
+        .lines()
+        .map(str::trim_start)
+        .filter_map(|line| line.strip_prefix("//!"))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect::<Vec<_>>();
+    let base_url = docs_rs_url(file)?;
+    let references = docs
+        .iter()
+        .filter_map(|line| line.strip_prefix('['))
+        .filter_map(|line| line.split_once("]:"))
+        .map(|(name, _)| name)
+        .collect::<HashSet<_>>();
+    let mut code_block = None;
+    let mut output = String::new();
+
+    for line in &docs {
+        if let Some(info) = line.trim_start().strip_prefix("```") {
+            code_block = match code_block {
+                Some(_) => None,
+                None => Some(is_rust(info)),
+            };
+            output.push_str(line);
+        } else if let Some(is_rust) = code_block {
+            match hidden_line(line) {
+                Some(_) if is_rust => continue,
+                _ if is_rust => output.push_str(&unescape_hash(line)),
+                _ => output.push_str(line),
+            }
+        } else if let Some(line) = convert_definition(line, &base_url) {
+            output.push_str(&line);
+        } else {
+            output.push_str(&convert_links(line, &base_url, &references));
+        }
+
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// Is a code block with this info string Rust? rustdoc treats code blocks as
+/// Rust unless they name another language.
+fn is_rust(info: &str) -> bool {
+    info.split([',', ' ', '\t']).all(|token| {
+        let token = token.trim();
+        token.is_empty()
+            || token == "rust"
+            || token.starts_with("edition")
+            || matches!(
+                token,
+                "ignore" | "no_run" | "should_panic" | "compile_fail" | "test_harness"
+            )
+    })
+}
+
+/// `Some` if rustdoc hides `line`, which is `#` on its own, or starts with
+/// `# `.
+fn hidden_line(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    (trimmed == "#" || trimmed.starts_with("# ")).then_some(trimmed)
+}
+
+/// rustdoc shows a line starting with `##` with a single `#`.
+fn unescape_hash(line: &str) -> String {
+    let trimmed = line.trim_start();
+
+    match trimmed.strip_prefix("##") {
+        Some(rest) => format!("{}#{rest}", &line[..line.len() - trimmed.len()]),
+        None => line.to_string(),
+    }
+}
+
+/// Convert a reference link definition, like `[Foo]: crate::Foo`, if it points
+/// to a Rust path.
+fn convert_definition(line: &str, base_url: &str) -> Option<String> {
+    let (label, target) = line.strip_prefix('[')?.split_once("]:")?;
+    let target = target.trim();
+    let path = rust_path(target.trim_matches('`'))?;
+
+    Some(format!("[{label}]: {}", search_url(base_url, path)))
+}
+
+/// The `docs.rs` URL for the library target with source file `file`.
+fn docs_rs_url(file: &str) -> Result<String, RenderError> {
+    let file = fs::canonicalize(file)?;
+    let metadata = metadata_cache::shared_metadata()
+        .map_err(|e| RenderError::new(format!("Failed to read cargo metadata: {e}")))?;
+
+    for package in metadata.workspace_packages() {
+        for target in &package.targets {
+            if target.kind.iter().any(|kind| kind == "lib")
+                && same_file(target.src_path.as_std_path(), &file)
+            {
+                let lib_name = target.name.replace('-', "_");
+                return Ok(format!(
+                    "https://docs.rs/{}/latest/{lib_name}/",
+                    package.name
+                ));
+            }
+        }
+    }
+
+    Err(RenderError::new(format!(
+        "\"{}\" is not the root of a library in this workspace",
+        file.display()
+    )))
+}
+
+fn same_file(path: &Path, canonical: &Path) -> bool {
+    fs::canonicalize(path).is_ok_and(|path| path == canonical)
+}
+
+fn convert_links(line: &str, base_url: &str, references: &HashSet<&str>) -> String {
+    let mut output = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find(['[', '`']) {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('`') {
+            // Copy inline code verbatim
+            let end = rest[1..].find('`').map_or(rest.len(), |end| end + 2);
+            output.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let Some(close) = rest.find(']') else {
+            break;
+        };
+
+        let text = &rest[1..close];
+        let after = &rest[close + 1..];
+
+        if let Some(target) = after.strip_prefix('(') {
+            let Some(target_end) = target.find(')') else {
+                break;
+            };
+            let link_target = &target[..target_end];
+
+            output.push('[');
+            output.push_str(text);
+            output.push_str("](");
+
+            if let Some(path) = rust_path(link_target) {
+                output.push_str(&search_url(base_url, path));
+            } else {
+                output.push_str(link_target);
+            }
+
+            output.push(')');
+            rest = &target[target_end + 1..];
+        } else {
+            let path = rust_path(text.trim_matches('`'));
+
+            match path {
+                Some(path)
+                    if !references.contains(text)
+                        && !after.starts_with('[')
+                        && !after.starts_with(':') =>
+                {
+                    output.push_str(&format!("[{text}]({})", search_url(base_url, path)));
+                }
+                _ => output.push_str(&rest[..=close]),
+            }
+
+            rest = after;
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// If `target` looks like a Rust path, return it without any `crate::` or
+/// `self::` prefix, or any trailing `()` or `!`.
+fn rust_path(target: &str) -> Option<&str> {
+    let path = target
+        .strip_prefix("crate::")
+        .or_else(|| target.strip_prefix("self::"))
+        .unwrap_or(target);
+    let path = path
+        .strip_suffix("()")
+        .or_else(|| path.strip_suffix('!'))
+        .unwrap_or(path);
+
+    path.split("::")
+        .all(|segment| {
+            segment
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphabetic() || c == '_')
+                && segment.chars().all(|c| c.is_alphanumeric() || c == '_')
+        })
+        .then_some(path)
+}
+
+fn search_url(base_url: &str, path: &str) -> String {
+    format!("{base_url}?search={path}")
+}
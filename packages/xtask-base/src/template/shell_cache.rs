@@ -0,0 +1,121 @@
+//! Cache the output of the `shell` helper in the target directory
+//!
+//! Each output is keyed on the command, its options, and the contents of its
+//! input files, so it's invalidated when any of them change. Only commands with
+//! explicit inputs are cached.
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use handlebars::RenderError;
+
+use crate::metadata_cache;
+
+/// Set this environment variable to cache the output of the `shell` helper.
+pub const CACHE_ENV_VAR: &str = "XTASK_CACHE_TEMPLATES";
+
+pub fn is_enabled() -> bool {
+    env::var_os(CACHE_ENV_VAR).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Get the output for `command` from the cache, or `run` it and update the
+/// cache.
+///
+/// `inputs` is a space separated list of files and directories the output
+/// depends on. Hidden directories, `target` and files generated from templates
+/// are ignored, so rendering a template doesn't invalidate its own cache.
+pub fn cached(
+    command: &[&str],
+    inputs: &str,
+    run: impl FnOnce() -> Result<String, RenderError>,
+) -> Result<String, RenderError> {
+    let root = metadata_cache::workspace_root().map_err(|e| RenderError::new(e.to_string()))?;
+    let cache_file = metadata_cache::target_dir(&root)
+        .join("xtask")
+        .join("template-cache.json");
+    let key = format!("{:016x}", cache_key(command, inputs)?);
+    let mut cache: BTreeMap<String, String> = fs::read_to_string(&cache_file)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+
+    if let Some(output) = cache.get(&key) {
+        return Ok(output.clone());
+    }
+
+    let output = run()?;
+    cache.insert(key, output.clone());
+
+    if let Some(parent) = cache_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&cache_file, serde_json::to_string(&cache)?)?;
+
+    Ok(output)
+}
+
+fn cache_key(command: &[&str], inputs: &str) -> Result<u64, RenderError> {
+    let mut files = Vec::new();
+
+    for input in inputs.split_whitespace() {
+        input_files(Path::new(input), &mut files)?;
+    }
+
+    files.sort();
+
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    command.hash(&mut hasher);
+
+    for file in files {
+        file.hash(&mut hasher);
+        fs::read(&file).ok().hash(&mut hasher);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Find all the files under `path`, ignoring hidden directories, `target` and
+/// generated files.
+fn input_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), RenderError> {
+    if !path.is_dir() {
+        if !is_generated(path) {
+            files.push(path.to_path_buf());
+        }
+
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if entry.file_type()?.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                input_files(&entry.path(), files)?;
+            }
+        } else if !is_generated(&entry.path()) {
+            files.push(entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+/// Is `path` rendered from a template next to it, like `README.md` from
+/// `README.tmpl.md`?
+fn is_generated(path: &Path) -> bool {
+    let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+        return false;
+    };
+    let mut template = stem.to_os_string();
+    template.push(".tmpl.");
+    template.push(extension);
+
+    path.with_file_name(template).exists()
+}
@@ -0,0 +1,150 @@
+use std::fs;
+
+use handlebars::RenderError;
+
+/// Include the contents of `file`.
+///
+/// If `anchor` is non-empty, only the lines between `ANCHOR: {anchor}` and
+/// `ANCHOR_END: {anchor}` are included. If `lines` is non-empty, only that
+/// (1-based, inclusive) line range is included. It can be of the form `10`,
+/// `10-20`, `10-` or `-20`.
+pub fn include(file: &str, anchor: &str, lines: &str) -> Result<String, RenderError> {
+    extract(file, &fs::read_to_string(file)?, anchor, lines)
+}
+
+/// The part of `contents` selected by `anchor` and `lines`. See [`include`].
+fn extract(file: &str, contents: &str, anchor: &str, lines: &str) -> Result<String, RenderError> {
+    if anchor.is_empty() && lines.is_empty() {
+        return Ok(contents.replace("\r\n", "\n"));
+    }
+
+    let mut selected = contents.lines().collect::<Vec<_>>();
+
+    if !lines.is_empty() {
+        let (start, end) = line_range(lines, selected.len())?;
+        selected = selected[start..end].to_vec();
+    }
+
+    if !anchor.is_empty() {
+        selected = anchored(&selected, anchor)
+            .ok_or_else(|| RenderError::new(format!("Anchor \"{anchor}\" not found in {file}")))?;
+    }
+
+    Ok(dedent(
+        selected.into_iter().filter(|line| !is_anchor_line(line)),
+    ))
+}
+
+fn line_range(lines: &str, line_count: usize) -> Result<(usize, usize), RenderError> {
+    let (start, end) = lines.split_once('-').unwrap_or((lines, lines));
+    let parse = |line: &str, default| -> Result<usize, RenderError> {
+        Ok(if line.is_empty() {
+            default
+        } else {
+            line.trim().parse()?
+        })
+    };
+    let start = parse(start, 1)?;
+    let end = parse(end, line_count)?;
+
+    if start == 0 || start > end || end > line_count {
+        return Err(RenderError::new(format!(
+            "Invalid line range \"{lines}\" for a file with {line_count} lines"
+        )));
+    }
+
+    Ok((start - 1, end))
+}
+
+fn anchored<'a>(lines: &[&'a str], anchor: &str) -> Option<Vec<&'a str>> {
+    let is_marker = |line: &str, marker: &str| {
+        line.split_once(marker)
+            .is_some_and(|(_, name)| name.trim() == anchor)
+    };
+    let start = lines.iter().position(|line| is_marker(line, "ANCHOR:"))?;
+    let len = lines[start..]
+        .iter()
+        .position(|line| is_marker(line, "ANCHOR_END:"))?;
+
+    Some(lines[start + 1..start + len].to_vec())
+}
+
+fn is_anchor_line(line: &str) -> bool {
+    line.contains("ANCHOR:") || line.contains("ANCHOR_END:")
+}
+
+/// Remove any indentation common to all non-blank lines.
+fn dedent<'a>(lines: impl Iterator<Item = &'a str> + Clone) -> String {
+    let indent = lines
+        .clone()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines
+        .map(|line| line.get(indent..).unwrap_or("").trim_end())
+        .fold(String::new(), |mut output, line| {
+            output.push_str(line);
+            output.push('\n');
+            output
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract;
+
+    const SOURCE: &str = "\
+fn main() {
+    // ANCHOR: body
+    let x = 1;
+
+    println!(\"{x}\");
+    // ANCHOR_END: body
+}
+";
+
+    fn snippet(anchor: &str, lines: &str) -> String {
+        extract("main.rs", SOURCE, anchor, lines).unwrap()
+    }
+
+    #[test]
+    fn whole_file() {
+        assert_eq!(snippet("", ""), SOURCE);
+        assert_eq!(extract("main.rs", "a\r\nb\r\n", "", "").unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn anchor() {
+        assert_eq!(snippet("body", ""), "let x = 1;\n\nprintln!(\"{x}\");\n");
+    }
+
+    #[test]
+    fn missing_anchor() {
+        let error = extract("main.rs", SOURCE, "missing", "").unwrap_err();
+        assert_eq!(error.to_string(), "Anchor \"missing\" not found in main.rs");
+    }
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(snippet("", "1"), "fn main() {\n");
+        assert_eq!(snippet("", "3-5"), "let x = 1;\n\nprintln!(\"{x}\");\n");
+        // Anchor comments are left out
+        assert_eq!(snippet("", "6-"), "}\n");
+        assert_eq!(snippet("", "-2"), "fn main() {\n");
+    }
+
+    #[test]
+    fn invalid_line_ranges() {
+        for lines in ["0", "5-3", "8", "x"] {
+            assert!(extract("main.rs", SOURCE, "", lines).is_err(), "{lines}");
+        }
+    }
+
+    #[test]
+    fn anchor_within_lines() {
+        assert_eq!(snippet("body", "2-7"), "let x = 1;\n\nprintln!(\"{x}\");\n");
+        assert!(extract("main.rs", SOURCE, "body", "3-7").is_err());
+    }
+}
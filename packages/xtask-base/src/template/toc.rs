@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+const PLACEHOLDER_START: &str = "XTASK_BASE_TOC_START_";
+const PLACEHOLDER_END: &str = "_XTASK_BASE_TOC_END";
+
+/// A placeholder for a table of contents, which is filled in by [`insert`]
+/// once the whole document has been rendered.
+///
+/// The placeholder doesn't contain any characters that Handlebars will escape.
+pub fn placeholder(max_level: u64) -> String {
+    format!("{PLACEHOLDER_START}{max_level}{PLACEHOLDER_END}")
+}
+
+/// Replace any placeholders in `markdown` with a table of contents.
+///
+/// The table of contents includes all headings from level 2 up to the
+/// `max_level` given to [`placeholder`].
+pub fn insert(markdown: &str) -> String {
+    if !markdown.contains(PLACEHOLDER_START) {
+        return markdown.to_owned();
+    }
+
+    let headings = headings(markdown);
+    let mut output = String::new();
+    let mut rest = markdown;
+
+    while let Some((before, after)) = rest.split_once(PLACEHOLDER_START) {
+        let Some((max_level, after)) = after.split_once(PLACEHOLDER_END) else {
+            break;
+        };
+
+        output.push_str(before);
+
+        if let Ok(max_level) = max_level.parse() {
+            output.push_str(&table_of_contents(&headings, max_level));
+        }
+
+        rest = after;
+    }
+
+    output.push_str(rest);
+    output
+}
+
+struct Heading {
+    level: usize,
+    title: String,
+    anchor: String,
+}
+
+fn headings(markdown: &str) -> Vec<Heading> {
+    let mut anchor_counts = HashMap::new();
+    let mut in_code_block = false;
+    let mut headings = Vec::new();
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+
+        if in_code_block {
+            continue;
+        }
+
+        let level = line.chars().take_while(|&c| c == '#').count();
+
+        if let Some(title) = line[level..].strip_prefix(' ').filter(|_| level > 0) {
+            let title = title.trim().trim_end_matches('#').trim().to_owned();
+            let slug = slug(&title);
+            let count = anchor_counts.entry(slug.clone()).or_insert(0);
+            let anchor = if *count == 0 {
+                slug
+            } else {
+                format!("{slug}-{count}")
+            };
+
+            *count += 1;
+            headings.push(Heading {
+                level,
+                title,
+                anchor,
+            });
+        }
+    }
+
+    headings
+}
+
+/// Github style heading anchors
+fn slug(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+fn table_of_contents(headings: &[Heading], max_level: usize) -> String {
+    headings
+        .iter()
+        .filter(|heading| (2..=max_level).contains(&heading.level))
+        .map(|heading| {
+            format!(
+                "{}- [{}](#{})\n",
+                "  ".repeat(heading.level - 2),
+                heading.title,
+                heading.anchor
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert, placeholder};
+
+    fn document(max_level: u64) -> String {
+        format!(
+            "# Title\n\n{}\n## Usage\n\n### Install\n\n```sh\n# Not a heading\n```\n\n#### Details\n\n## Usage\n",
+            placeholder(max_level)
+        )
+    }
+
+    #[test]
+    fn no_placeholder() {
+        assert_eq!(insert("# Title\n\n## Usage\n"), "# Title\n\n## Usage\n");
+    }
+
+    #[test]
+    fn placeholder_replaced() {
+        let markdown = insert(&document(3));
+
+        assert!(
+            markdown.starts_with(
+                "# Title\n\n- [Usage](#usage)\n  - [Install](#install)\n- [Usage](#usage-1)\n\n## Usage\n"
+            ),
+            "{markdown}"
+        );
+    }
+
+    #[test]
+    fn max_level() {
+        let toc = |max_level| {
+            let markdown = insert(&document(max_level));
+            markdown["# Title\n\n".len()..markdown.find("\n## Usage").unwrap()].to_owned()
+        };
+
+        assert_eq!(toc(1), "");
+        assert_eq!(toc(2), "- [Usage](#usage)\n- [Usage](#usage-1)\n");
+        assert_eq!(
+            toc(4),
+            "- [Usage](#usage)\n  - [Install](#install)\n    - [Details](#details)\n- [Usage](#usage-1)\n"
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        let markdown = insert(&format!(
+            "{}\n## What's new?\n## C++ & Rust_2\n",
+            placeholder(2)
+        ));
+
+        assert!(
+            markdown.starts_with("- [What's new?](#whats-new)\n- [C++ & Rust_2](#c--rust_2)\n"),
+            "{markdown}"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Validate the workspace config, and any templates it uses
+use std::fs;
+
+use cargo_metadata::camino::Utf8Path;
+use serde::Deserialize;
+
+use crate::{
+    ci::{WorkspaceVersions, CI},
+    config::{XtaskConfig, CONFIG_FILE},
+    template::TemplateEngine,
+    WorkflowResult, Workspace,
+};
+
+// These are only deserialized to find errors, so the fields are never read.
+
+/// The parts of a `Cargo.toml` that hold `xtask` config
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Manifest {
+    workspace: Option<MetadataTable>,
+    package: Option<MetadataTable>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct MetadataTable {
+    metadata: Option<XtaskTable>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct XtaskTable {
+    xtask: Option<XtaskConfig>,
+}
+
+/// Check the workspace config and the templates it uses, listing every
+/// problem found.
+///
+/// Config files are parsed from source, so errors point to the line and
+/// column in `xtask.toml` or `Cargo.toml`. The `jobs` list is checked against
+/// the standard CI jobs, and `README.tmpl.md` is compiled if `readme` is set.
+pub(crate) fn validate_config(workspace: &Workspace) -> WorkflowResult<()> {
+    let problems = problems(workspace)?;
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for (file, problem) in &problems {
+        eprintln!("{file}: {}\n", problem.trim_end());
+    }
+
+    Err(format!(
+        "Found {} problem(s) in the workspace config",
+        problems.len()
+    )
+    .into())
+}
+
+/// `(file, problem)` for each problem found
+fn problems(workspace: &Workspace) -> WorkflowResult<Vec<(String, String)>> {
+    let root = &workspace.0.workspace_root;
+    let relative = |path: &Utf8Path| path.strip_prefix(root).unwrap_or(path).to_string();
+    let mut problems = Vec::new();
+
+    let config_file = root.join(CONFIG_FILE);
+    let config_source = if config_file.exists() {
+        CONFIG_FILE
+    } else {
+        "Cargo.toml"
+    };
+
+    if config_file.exists() {
+        if let Err(e) = toml::from_str::<XtaskConfig>(&fs::read_to_string(&config_file)?) {
+            problems.push((CONFIG_FILE.to_string(), e.to_string()));
+        }
+    }
+
+    let mut manifests = vec![root.join("Cargo.toml")];
+
+    for package in workspace.0.workspace_packages() {
+        if !manifests.contains(&package.manifest_path) {
+            manifests.push(package.manifest_path.clone());
+        }
+    }
+
+    for manifest in manifests {
+        if let Err(e) = toml::from_str::<Manifest>(&fs::read_to_string(&manifest)?) {
+            problems.push((relative(&manifest), e.to_string()));
+        }
+    }
+
+    if !problems.is_empty() {
+        return Ok(problems);
+    }
+
+    // Everything parses, but the config might be in both `xtask.toml` and
+    // `Cargo.toml`.
+    let config = match workspace.config() {
+        Ok(config) => config,
+        Err(e) => return Ok(vec![(config_source.to_string(), e.to_string())]),
+    };
+
+    if let Some(jobs) = &config.jobs {
+        let ci =
+            CI::standard_workflow(WorkspaceVersions::load(workspace)?.standard_versions(), &[]);
+        let known = ci.job_names();
+
+        for job in jobs {
+            if !known.contains(job.as_str()) {
+                problems.push((
+                    config_source.to_string(),
+                    format!(
+                        "Unknown job \"{job}\" in `jobs`. Expected one of: {}",
+                        known.iter().copied().collect::<Vec<_>>().join(", ")
+                    ),
+                ));
+            }
+        }
+    }
+
+    if config.readme {
+        let template_file = root.join("README.tmpl.md");
+
+        match fs::read_to_string(&template_file) {
+            Ok(template) => {
+                if let Err(e) = TemplateEngine::default().validate(&template) {
+                    problems.push((relative(&template_file), e.to_string()));
+                }
+            }
+            Err(e) => problems.push((
+                relative(&template_file),
+                format!("Can't read the template for `readme`: {e}"),
+            )),
+        }
+    }
+
+    Ok(problems)
+}
@@ -0,0 +1,101 @@
+//! Check the workspace version, changelog and release tag agree
+use std::{collections::BTreeSet, env, fs};
+
+use itertools::Itertools;
+
+use crate::{is_publishable, template, WorkflowResult, Workspace};
+
+const CHANGELOG: &str = "CHANGELOG.md";
+
+/// Check the version of the publishable packages, the latest heading in
+/// `CHANGELOG.md`, and the release tag all agree.
+///
+/// The publishable packages must all have the same version, so workspaces
+/// that version packages independently can't use this. If none are
+/// publishable, all the workspace packages are checked. The tag is `tag`,
+/// `$GITHUB_REF_NAME` for a workflow triggered by a tag, or the tag at `HEAD`.
+/// It can have a leading `v`. The changelog and tag are only checked if they
+/// exist.
+pub fn verify(workspace: &Workspace, tag: Option<&str>) -> WorkflowResult<()> {
+    let version = workspace_version(workspace)?;
+    let mut problems = Vec::new();
+    println!("Workspace version is {version}");
+
+    let changelog = workspace.0.workspace_root.join(CHANGELOG);
+
+    if changelog.exists() {
+        match template::latest_changelog_version(&fs::read_to_string(&changelog)?) {
+            Some(changelog_version) if changelog_version == version => {
+                println!("{CHANGELOG} matches")
+            }
+            Some(changelog_version) => problems.push(format!(
+                "The latest version in {CHANGELOG} is {changelog_version}"
+            )),
+            None => problems.push(format!("There are no versions in {CHANGELOG}")),
+        }
+    } else {
+        println!("No {CHANGELOG} to check");
+    }
+
+    match tag.map(str::to_string).or_else(|| release_tag(workspace)) {
+        Some(tag) if tag.strip_prefix('v').unwrap_or(&tag) == version => {
+            println!("Tag \"{tag}\" matches")
+        }
+        Some(tag) => problems.push(format!("The tag is \"{tag}\"")),
+        None => println!("No release tag to check"),
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+
+    Err(format!("Versions don't match the workspace version {version}").into())
+}
+
+/// The version shared by the publishable packages
+fn workspace_version(workspace: &Workspace) -> WorkflowResult<String> {
+    let packages = workspace.0.workspace_packages();
+    let publishable = packages
+        .iter()
+        .filter(|package| is_publishable(package))
+        .collect::<Vec<_>>();
+    let packages = if publishable.is_empty() {
+        packages.iter().collect()
+    } else {
+        publishable
+    };
+    let versions = packages
+        .iter()
+        .map(|package| package.version.to_string())
+        .collect::<BTreeSet<_>>();
+
+    match versions.len() {
+        0 => Err("No packages in the workspace".into()),
+        1 => Ok(versions.into_iter().next().unwrap()),
+        _ => Err(format!(
+            "Packages have different versions:\n{}",
+            packages
+                .iter()
+                .map(|package| format!("  {} {}", package.name, package.version))
+                .join("\n")
+        )
+        .into()),
+    }
+}
+
+/// The tag that triggered the workflow, or the tag at `HEAD` when run locally
+fn release_tag(workspace: &Workspace) -> Option<String> {
+    if env::var("GITHUB_REF_TYPE").is_ok_and(|ref_type| ref_type == "tag") {
+        return env::var("GITHUB_REF_NAME").ok();
+    }
+
+    duct::cmd!("git", "describe", "--tags", "--exact-match", "HEAD")
+        .dir(&workspace.0.workspace_root)
+        .stderr_null()
+        .read()
+        .ok()
+}
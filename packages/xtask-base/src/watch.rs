@@ -0,0 +1,62 @@
+use std::{path::Path, sync::mpsc, time::Duration};
+
+use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+
+use crate::{ci::CI, WorkflowResult};
+
+/// Run the CI `jobs` (or all jobs if `jobs` is empty) whenever a file in the
+/// workspace changes.
+pub fn watch(
+    workspace_root: &Path,
+    target_dir: &Path,
+    ci: &CI,
+    jobs: &[String],
+) -> WorkflowResult<()> {
+    run_jobs(ci, jobs);
+    on_change(workspace_root, target_dir, || run_jobs(ci, jobs))
+}
+
+/// Call `f` whenever a file in the workspace changes.
+///
+/// Changes in the target directory and `.git` are ignored. This never returns,
+/// unless there's an error.
+pub fn on_change(
+    workspace_root: &Path,
+    target_dir: &Path,
+    mut f: impl FnMut(),
+) -> WorkflowResult<()> {
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer = new_debouncer(Duration::from_millis(500), sender)?;
+    debouncer
+        .watcher()
+        .watch(workspace_root, RecursiveMode::Recursive)?;
+    let git_dir = workspace_root.join(".git");
+
+    for events in receiver {
+        let changed = events?
+            .into_iter()
+            .any(|event| !event.path.starts_with(target_dir) && !event.path.starts_with(&git_dir));
+
+        if changed {
+            f();
+        }
+    }
+
+    Ok(())
+}
+
+fn run_jobs(ci: &CI, jobs: &[String]) {
+    // Clear the screen and move the cursor to the top left
+    print!("\x1B[2J\x1B[1;1H");
+
+    let result = if jobs.is_empty() {
+        ci.execute()
+    } else {
+        jobs.iter().try_for_each(|job| ci.execute_job(job))
+    };
+
+    match result {
+        Ok(()) => println!("\nChecks passed. Waiting for changes..."),
+        Err(e) => println!("\n{e}\n\nChecks failed. Waiting for changes..."),
+    }
+}
@@ -1,12 +1,17 @@
 use xtask_base::{
     build_readme,
-    ci::{StandardVersions, CI},
+    ci::{WorkspaceVersions, CI},
     generate_open_source_files, CommonCmds, WorkflowResult,
 };
 
 fn main() {
-    CommonCmds::run(
-        CI::standard_workflow(StandardVersions::default(), &[]),
+    CommonCmds::run_with(
+        |workspace| {
+            Ok(
+                CI::standard_workflow(WorkspaceVersions::load(workspace)?.standard_versions(), &[])
+                    .required_checks(),
+            )
+        },
         code_gen,
     )
 }